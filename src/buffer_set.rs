@@ -0,0 +1,291 @@
+use crate::screen_manager::TextScreen;
+
+/// Texture keys reserved for the line cache of every buffer
+const LINE_CACHE_STRIDE: usize = 1 << 24;
+/// Pixels between the two panes of a split view
+pub const SPLIT_GAP: u32 = 2;
+
+/// Divide `width` pixels into a left and a right pane as `(x, width)`, with a gap between them
+pub fn split_panes(width: u32) -> ((u32, u32), (u32, u32)) {
+    let inner = width.saturating_sub(SPLIT_GAP);
+    let left = inner / 2;
+    let right = inner - left;
+    ((0, left), (left + SPLIT_GAP, right))
+}
+
+/// The open buffers, exactly one of which is shown and edited at a time
+pub struct BufferSet {
+    buffers: Vec<TextScreen>,
+    active: usize,
+    /// Buffer shown in the pane without focus, if the window is split
+    other: Option<usize>,
+    /// Whether the focused pane is the left one
+    focus_left: bool,
+    /// Counter for the line cache keys, so closed buffers never share keys with new ones
+    next_id: usize,
+}
+
+impl BufferSet {
+    pub fn new(first: TextScreen) -> Self {
+        let mut buffers = BufferSet {
+            buffers: Vec::new(),
+            active: 0,
+            other: None,
+            focus_left: true,
+            next_id: 0,
+        };
+        buffers.open(first);
+        buffers
+    }
+
+    /// Add a buffer after the others and switch to it, returning its index.
+    /// In a split view it is shown in the focused pane.
+    pub fn open(&mut self, mut screen: TextScreen) -> usize {
+        screen.enable_line_cache(self.next_id * LINE_CACHE_STRIDE);
+        self.next_id += 1;
+        self.buffers.push(screen);
+        self.active = self.buffers.len() - 1;
+        self.active
+    }
+
+    /// Close the active buffer and switch to the one after it, or the one before if it was last.
+    /// A buffer with unsaved changes is only closed if `force` is set.
+    pub fn close(&mut self, force: bool) -> Result<TextScreen, String> {
+        if self.buffers.len() == 1 {
+            return Err(String::from("Cannot close the only buffer"));
+        }
+        if self.active().is_dirty() && !force {
+            return Err(String::from("The buffer has unsaved changes"));
+        }
+        let closed = self.buffers.remove(self.active);
+        let len = self.buffers.len();
+        if let Some(other) = self.other {
+            self.other = Some(if other > self.active {
+                other - 1
+            } else {
+                other
+            });
+        }
+        self.active = self.active.min(len - 1);
+        if self.other == Some(self.active) {
+            if len == 1 {
+                self.unsplit();
+            } else {
+                self.active = (self.active + len - 1) % len;
+            }
+        }
+        Ok(closed)
+    }
+
+    /// Show `idx` in the focused pane, the panes trade buffers if the other one shows it already
+    fn switch_to(&mut self, idx: usize) {
+        if self.other == Some(idx) {
+            self.other = Some(self.active);
+        }
+        self.active = idx;
+    }
+
+    pub fn next_buffer(&mut self) {
+        self.switch_to((self.active + 1) % self.buffers.len());
+    }
+
+    pub fn prev_buffer(&mut self) {
+        self.switch_to((self.active + self.buffers.len() - 1) % self.buffers.len());
+    }
+
+    /// Show the next buffer next to the active one, which keeps the focus in the left pane
+    pub fn split(&mut self) -> Result<(), String> {
+        if self.buffers.len() < 2 {
+            return Err(String::from("Splitting needs a second buffer"));
+        }
+        self.other = Some((self.active + 1) % self.buffers.len());
+        self.focus_left = true;
+        Ok(())
+    }
+
+    pub fn unsplit(&mut self) {
+        self.other = None;
+        self.focus_left = true;
+    }
+
+    #[inline]
+    pub fn is_split(&self) -> bool {
+        self.other.is_some()
+    }
+
+    #[inline]
+    pub fn focus_left(&self) -> bool {
+        self.focus_left
+    }
+
+    /// Move the focus to the other pane of a split view
+    pub fn focus_other(&mut self) {
+        if let Some(other) = self.other {
+            self.other = Some(self.active);
+            self.active = other;
+            self.focus_left = !self.focus_left;
+        }
+    }
+
+    /// Buffer index, x position and width of the shown panes, from left to right
+    pub fn panes(&self, width: u32) -> Vec<(usize, u32, u32)> {
+        let Some(other) = self.other else {
+            return vec![(self.active, 0, width)];
+        };
+        let ((left_x, left_width), (right_x, right_width)) = split_panes(width);
+        let (left, right) = if self.focus_left {
+            (self.active, other)
+        } else {
+            (other, self.active)
+        };
+        vec![(left, left_x, left_width), (right, right_x, right_width)]
+    }
+
+    /// The pane at window position `x` as buffer index and the x position of the pane
+    pub fn pane_at(&self, x: i32, width: u32) -> Option<(usize, u32)> {
+        self.panes(width)
+            .into_iter()
+            .find(|(_, pane_x, pane_width)| {
+                x >= *pane_x as i32 && x < (*pane_x + *pane_width) as i32
+            })
+            .map(|(idx, pane_x, _)| (idx, pane_x))
+    }
+
+    #[inline]
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    #[inline]
+    pub fn active(&self) -> &TextScreen {
+        &self.buffers[self.active]
+    }
+
+    #[inline]
+    pub fn active_mut(&mut self) -> &mut TextScreen {
+        &mut self.buffers[self.active]
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut TextScreen> {
+        self.buffers.get_mut(idx)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, TextScreen> {
+        self.buffers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, TextScreen> {
+        self.buffers.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    fn named(name: &str) -> TextScreen {
+        let mut screen = TextScreen::new(800, 600, 10);
+        screen.set_path(Some(PathBuf::from(name)));
+        screen
+    }
+
+    fn active_name(buffers: &BufferSet) -> &Path {
+        buffers.active().path().unwrap()
+    }
+
+    #[test]
+    fn open_switches_to_the_new_buffer() {
+        let mut buffers = BufferSet::new(named("a"));
+        assert_eq!(buffers.active_index(), 0);
+        assert_eq!(buffers.open(named("b")), 1);
+        assert_eq!(buffers.open(named("c")), 2);
+        assert_eq!(buffers.len(), 3);
+        assert_eq!(active_name(&buffers), Path::new("c"));
+        buffers.next_buffer();
+        assert_eq!(active_name(&buffers), Path::new("a"));
+        buffers.prev_buffer();
+        assert_eq!(active_name(&buffers), Path::new("c"));
+    }
+
+    #[test]
+    fn close_moves_to_the_neighbouring_buffer() {
+        let mut buffers = BufferSet::new(named("a"));
+        buffers.open(named("b"));
+        buffers.open(named("c"));
+        buffers.prev_buffer();
+        // closing a middle buffer activates the one after it
+        let closed = buffers.close(false).unwrap();
+        assert_eq!(closed.path(), Some(Path::new("b")));
+        assert_eq!(buffers.active_index(), 1);
+        assert_eq!(active_name(&buffers), Path::new("c"));
+        // closing the last one activates the one before it
+        buffers.close(false).unwrap();
+        assert_eq!(buffers.active_index(), 0);
+        assert_eq!(active_name(&buffers), Path::new("a"));
+        assert!(buffers.close(true).is_err());
+        assert_eq!(buffers.len(), 1);
+    }
+
+    #[test]
+    fn close_keeps_unsaved_buffers_unless_forced() {
+        let mut font = crate::res_man::tests::monospace_font(8);
+        let mut buffers = BufferSet::new(named("a"));
+        buffers.open(named("b"));
+        buffers
+            .active_mut()
+            .push_string(font.get_string_lossy("edit"));
+        assert!(buffers.close(false).is_err());
+        assert_eq!(buffers.len(), 2);
+        assert_eq!(active_name(&buffers), Path::new("b"));
+        assert!(buffers.close(true).is_ok());
+        assert_eq!(active_name(&buffers), Path::new("a"));
+    }
+
+    #[test]
+    fn split_panes_share_the_width_around_the_gap() {
+        assert_eq!(split_panes(802), ((0, 400), (402, 400)));
+        // an odd remainder goes to the right pane
+        assert_eq!(split_panes(101), ((0, 49), (51, 50)));
+        assert_eq!(split_panes(1), ((0, 0), (SPLIT_GAP, 0)));
+    }
+
+    #[test]
+    fn panes_follow_the_split_and_the_focus() {
+        let mut buffers = BufferSet::new(named("a"));
+        assert!(buffers.split().is_err());
+        buffers.open(named("b"));
+        buffers.prev_buffer();
+        assert_eq!(buffers.panes(802), [(0, 0, 802)]);
+        assert_eq!(buffers.pane_at(700, 802), Some((0, 0)));
+
+        buffers.split().unwrap();
+        assert_eq!(buffers.panes(802), [(0, 0, 400), (1, 402, 400)]);
+        assert_eq!(buffers.pane_at(399, 802), Some((0, 0)));
+        // the gap belongs to neither pane
+        assert_eq!(buffers.pane_at(401, 802), None);
+        assert_eq!(buffers.pane_at(402, 802), Some((1, 402)));
+        assert_eq!(buffers.pane_at(802, 802), None);
+
+        // the buffers stay in their panes while the focus moves
+        buffers.focus_other();
+        assert!(!buffers.focus_left());
+        assert_eq!(active_name(&buffers), Path::new("b"));
+        assert_eq!(buffers.panes(802), [(0, 0, 400), (1, 402, 400)]);
+        buffers.focus_other();
+        assert!(buffers.focus_left());
+        assert_eq!(active_name(&buffers), Path::new("a"));
+
+        buffers.unsplit();
+        buffers.focus_other();
+        assert_eq!(active_name(&buffers), Path::new("a"));
+        assert_eq!(buffers.panes(802), [(0, 0, 802)]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::screen_manager::CaretShape;
+use crate::theme::Theme;
+use crate::FONT_SIZE;
+
+const DEFAULT_FONT_FILE: &str = "fonts/Arial.ttf";
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 600;
+
+/// User settings, read from `~/.config/saute/config.toml`.
+/// Anything left out of the file keeps its default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub font_path: String,
+    /// Fonts to take missing glyphs from, in order
+    pub fallback_fonts: Vec<String>,
+    pub font_size: u32,
+    pub tab_width: u32,
+    /// Start soft-wrapped continuation rows at the indentation of their line
+    pub wrap_indent: bool,
+    /// Extra columns continuation rows are indented by, with `wrap_indent`
+    pub hanging_indent: u32,
+    /// Color brackets by their nesting depth
+    pub rainbow_brackets: bool,
+    /// Either "dark" or "light"
+    pub theme: String,
+    /// Caret drawn in insert mode, one of "bar", "block" or "underline"
+    pub caret: String,
+    pub window_width: u32,
+    pub window_height: u32,
+    /// Strip spaces and tabs at the end of lines when saving
+    pub trim_trailing_whitespace: bool,
+    /// End saved files with exactly one line break
+    pub ensure_final_newline: bool,
+    /// Time a navigation key has to be held before it repeats
+    pub key_repeat_delay_ms: u64,
+    /// Time between two repeats of a held key
+    pub key_repeat_interval_ms: u64,
+    /// Frames per second to draw at most, or None to wait for the vertical sync of the display
+    pub target_fps: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            font_path: String::from(DEFAULT_FONT_FILE),
+            fallback_fonts: Vec::new(),
+            font_size: FONT_SIZE,
+            tab_width: 4,
+            wrap_indent: false,
+            hanging_indent: 0,
+            rainbow_brackets: false,
+            theme: String::from("dark"),
+            caret: String::from("bar"),
+            window_width: DEFAULT_WIDTH,
+            window_height: DEFAULT_HEIGHT,
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            key_repeat_delay_ms: 400,
+            key_repeat_interval_ms: 33,
+            target_fps: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        let home = env::var_os("HOME")?;
+        Some(PathBuf::from(home).join(".config/saute/config.toml"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|err| format!("Could not parse config: {err}"))
+    }
+
+    /// Read the config file, falling back to the defaults if it is missing or broken
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Config::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Config::default();
+        };
+        info!("Loading config {}", path.display());
+        Self::parse(&text).unwrap_or_else(|err| {
+            warn!("{err}, using the defaults");
+            Config::default()
+        })
+    }
+
+    /// Whether presenting a frame waits for the vertical sync
+    #[inline]
+    pub fn vsync(&self) -> bool {
+        self.target_fps.is_none()
+    }
+
+    /// Shortest time between two frames, None if vsync or a cap of 0 leaves it uncapped
+    pub fn frame_duration(&self) -> Option<Duration> {
+        match self.target_fps {
+            Some(fps) if fps > 0 => Some(Duration::from_secs(1) / fps),
+            _ => None,
+        }
+    }
+
+    pub fn theme(&self) -> Theme {
+        match self.theme.as_str() {
+            "dark" => Theme::dark(),
+            "light" => Theme::light(),
+            other => {
+                warn!("Unknown theme {other}, using the dark theme");
+                Theme::dark()
+            }
+        }
+    }
+
+    pub fn caret_shape(&self) -> CaretShape {
+        match self.caret.as_str() {
+            "bar" => CaretShape::Bar,
+            "block" => CaretShape::Block,
+            "underline" => CaretShape::Underline,
+            other => {
+                warn!("Unknown caret {other}, using a bar");
+                CaretShape::Bar
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_the_given_fields() {
+        let config = Config::parse(
+            r#"
+            font_path = "fonts/Consolas.ttf"
+            fallback_fonts = ["fonts/DejaVuSansMono.ttf"]
+            font_size = 18
+            theme = "light"
+            rainbow_brackets = true
+            target_fps = 144
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.font_path, "fonts/Consolas.ttf");
+        assert_eq!(config.fallback_fonts, vec!["fonts/DejaVuSansMono.ttf"]);
+        assert_eq!(config.font_size, 18);
+        assert_eq!(config.theme, "light");
+        assert!(config.rainbow_brackets);
+        assert_eq!(config.target_fps, Some(144));
+    }
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        let config = Config::parse("tab_width = 2").unwrap();
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(
+            config,
+            Config {
+                tab_width: 2,
+                ..Config::default()
+            }
+        );
+        assert!(Config::default().font_path.starts_with("fonts/"));
+    }
+
+    #[test]
+    fn caret_names_map_to_shapes() {
+        let with_caret = |caret: &str| Config {
+            caret: caret.to_owned(),
+            ..Config::default()
+        };
+        assert_eq!(Config::default().caret_shape(), CaretShape::Bar);
+        assert_eq!(with_caret("block").caret_shape(), CaretShape::Block);
+        assert_eq!(with_caret("underline").caret_shape(), CaretShape::Underline);
+        assert_eq!(with_caret("triangle").caret_shape(), CaretShape::Bar);
+    }
+
+    #[test]
+    fn parse_rejects_mistyped_values() {
+        assert!(Config::parse("font_size = \"large\"").is_err());
+    }
+
+    #[test]
+    fn frame_duration_follows_the_fps_cap() {
+        let with_fps = |target_fps| Config {
+            target_fps,
+            ..Config::default()
+        };
+        assert_eq!(with_fps(None).frame_duration(), None);
+        assert!(with_fps(None).vsync());
+        assert_eq!(with_fps(Some(0)).frame_duration(), None);
+        assert_eq!(
+            with_fps(Some(1)).frame_duration(),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            with_fps(Some(60)).frame_duration(),
+            Some(Duration::from_nanos(16_666_666))
+        );
+        assert_eq!(
+            with_fps(Some(250)).frame_duration(),
+            Some(Duration::from_millis(4))
+        );
+        assert!(!with_fps(Some(60)).vsync());
+    }
+}
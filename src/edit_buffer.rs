@@ -0,0 +1,1750 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use log::debug;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::screen_manager::Lines;
+use crate::{FontChar, FontDef};
+
+/// Line break style of the file backing a buffer. In memory, lines always end in `'\n'`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// The style used by the majority of line breaks in `text`
+    pub fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Opening and closing brackets that are matched up
+pub(crate) const BRACKET_PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// A reversible modification of the text buffer
+#[derive(Clone)]
+enum EditOp {
+    Insert {
+        pos: u32,
+        text: Vec<Rc<FontChar>>,
+    },
+    Delete {
+        pos: u32,
+        text: Vec<Rc<FontChar>>,
+    },
+    Replace {
+        pos: u32,
+        old: Vec<Rc<FontChar>>,
+        new: Vec<Rc<FontChar>>,
+    },
+    /// Several edits undone and redone as one step, in the order they were applied
+    Group(Vec<EditOp>),
+}
+
+/// The text of a buffer together with its cursor, selection and undo history.
+/// It knows nothing about how the text is laid out or drawn, see `TextScreen` for that.
+#[derive(Default, Clone)]
+pub struct EditBuffer {
+    pub(crate) content: Lines,
+    pub(crate) cursor_abs: u32,
+    cursor_col: u32,
+    cursor_row: u32,
+    highlight_mark: u32,
+    dirty: bool,
+    line_ending: LineEnding,
+    /// File the buffer is saved to, `None` until it has one
+    path: Option<PathBuf>,
+    undo_stack: Vec<EditOp>,
+    redo_stack: Vec<EditOp>,
+    pub(crate) tab_width: u32,
+    insert_spaces_for_tab: bool,
+    comment_prefix: String,
+    overwrite: bool,
+    find: Option<String>,
+    find_case_insensitive: bool,
+    /// Set when the cursor moved, so views can bring it into sight
+    pub(crate) follow_cursor: bool,
+    /// Lines edited since the last render
+    pub(crate) dirty_lines: HashSet<usize>,
+    /// Every line from this one on has to be rendered anew
+    pub(crate) dirty_from: Option<usize>,
+    /// Bumped on every change of the text
+    revision: u64,
+}
+
+impl EditBuffer {
+    pub fn new() -> Self {
+        EditBuffer {
+            highlight_mark: u32::MAX,
+            tab_width: 4,
+            comment_prefix: String::from("// "),
+            ..Default::default()
+        }
+    }
+
+    /// Counter that changes whenever the text does, to tell if derived data is stale
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    // #region Getters And Setters
+    #[inline]
+    pub fn set_cursor_row(&mut self, row: u32) {
+        self.cursor_row = row;
+    }
+    #[inline]
+    pub fn set_cursor_col(&mut self, col: u32) {
+        self.cursor_col = col;
+    }
+    #[inline]
+    pub fn tab_width(&self) -> u32 {
+        self.tab_width
+    }
+    #[inline]
+    pub fn set_tab_width(&mut self, tab_width: u32) {
+        self.tab_width = tab_width;
+    }
+    #[inline]
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+    /// Typed characters replace the one under the cursor instead of being inserted
+    #[inline]
+    pub fn set_overwrite(&mut self, enabled: bool) {
+        self.overwrite = enabled;
+    }
+    #[inline]
+    pub fn comment_prefix(&self) -> &str {
+        &self.comment_prefix
+    }
+    #[inline]
+    pub fn set_comment_prefix<S: Into<String>>(&mut self, prefix: S) {
+        self.comment_prefix = prefix.into();
+    }
+    #[inline]
+    pub fn insert_spaces_for_tab(&self) -> bool {
+        self.insert_spaces_for_tab
+    }
+    #[inline]
+    pub fn set_insert_spaces_for_tab(&mut self, enabled: bool) {
+        self.insert_spaces_for_tab = enabled;
+    }
+
+    #[inline]
+    pub fn set_highlight_mark(&mut self, pos: u32) {
+        self.highlight_mark = pos;
+    }
+    #[inline]
+    pub fn set_cursor_abs(&mut self, pos: u32) {
+        self.cursor_abs = pos;
+    }
+
+    #[inline]
+    pub fn get_cursor_row(&self) -> u32 {
+        self.cursor_row
+    }
+
+    #[inline]
+    pub fn get_cursor_col(&self) -> u32 {
+        self.cursor_col
+    }
+
+    #[inline]
+    pub fn get_highlight_mark(&mut self) -> u32 {
+        self.highlight_mark
+    }
+
+    #[inline]
+    pub fn get_cursor_abs(&mut self) -> u32 {
+        self.cursor_abs
+    }
+
+    /// Number of logical lines, a trailing line break starts an empty one
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.content.line_count()
+    }
+
+    /// Number of characters in the text, line breaks included
+    #[inline]
+    pub fn char_count(&self) -> usize {
+        self.content.len()
+    }
+
+    /// Length of the line the cursor is on, without its line break
+    pub fn current_line_len(&self) -> usize {
+        let line = self.content.line(self.line_of(self.cursor_abs)).unwrap();
+        match line.content().iter().last() {
+            Some(fch) if fch.ch == '\n' => line.len() - 1,
+            _ => line.len(),
+        }
+    }
+
+    /// Summary for the status bar: position, size, selection and edit mode
+    pub fn status_string(&self) -> String {
+        let mut status = format!(
+            "Line: {}; Char: {}; Lines: {}; Chars: {}",
+            self.cursor_row,
+            self.cursor_col,
+            self.line_count(),
+            self.char_count()
+        );
+        if let Some((start, end)) = self.selection_range() {
+            status.push_str(&format!("; Selected: {}", end - start));
+        }
+        status.push_str(if self.overwrite { "; OVR" } else { "; INS" });
+        status
+    }
+
+    pub fn get_text(&self) -> String {
+        self.content.iter().map(|fch| fch.ch).collect::<String>()
+    }
+
+    /// Start and end of the highlighted region, if a mark is set
+    pub fn selection_range(&self) -> Option<(u32, u32)> {
+        if self.highlight_mark == u32::MAX {
+            return None;
+        }
+        let len = self.content.len() as u32;
+        let start = self.highlight_mark.min(self.cursor_abs).min(len);
+        let end = self.highlight_mark.max(self.cursor_abs).min(len);
+        Some((start, end))
+    }
+
+    /// Called before moving the cursor: when extending, the mark is set at the current
+    /// position if there is none yet, otherwise any selection is dropped
+    pub fn update_selection(&mut self, extend: bool) {
+        if !extend {
+            self.highlight_mark = u32::MAX;
+        } else if self.highlight_mark == u32::MAX {
+            self.highlight_mark = self.cursor_abs;
+        }
+    }
+
+    pub fn select_all(&mut self) {
+        if self.content.is_empty() {
+            return;
+        }
+        self.highlight_mark = 0;
+        self.move_cursor_to(self.content.len() as u32);
+    }
+
+    pub fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection_range()?;
+        Some(
+            self.content
+                .range(start as usize..end as usize)
+                .map(|fch| fch.ch)
+                .collect::<String>(),
+        )
+    }
+
+    #[inline]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    #[inline]
+    pub fn set_path(&mut self, path: Option<PathBuf>) {
+        self.path = path;
+    }
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+    /// Style the line breaks are written out with
+    #[inline]
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// True if the buffer was edited since it was last loaded or saved
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    #[inline]
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
+    /// Lines edited since the last render
+    #[inline]
+    pub fn dirty_lines(&self) -> &HashSet<usize> {
+        &self.dirty_lines
+    }
+    // #endregion
+
+    /// Logical line containing `pos`
+    fn line_of(&self, pos: u32) -> usize {
+        self.content
+            .locate((pos as usize).min(self.content.len()))
+            .0
+    }
+
+    /// Note an edit on `line`. Adding or removing a line break shifts every line after it.
+    fn mark_line_dirty(&mut self, line: usize, newline: bool) {
+        self.dirty = true;
+        self.revision += 1;
+        if newline {
+            self.invalidate_lines_from(line);
+        } else {
+            self.dirty_lines.insert(line);
+        }
+    }
+
+    fn invalidate_lines_from(&mut self, line: usize) {
+        self.dirty_from = Some(self.dirty_from.map_or(line, |from| from.min(line)));
+    }
+
+    /// Treat every line as edited, so views draw all of them anew
+    #[inline]
+    pub fn invalidate_lines(&mut self) {
+        self.invalidate_lines_from(0);
+    }
+
+    /// Move the cursor over the next grapheme cluster
+    pub fn cursor_forward(&mut self) {
+        let target = self.next_cluster_boundary(self.cursor_abs);
+        while self.cursor_abs < target {
+            self.step_forward();
+        }
+        self.scroll_to_cursor();
+    }
+
+    /// Move the cursor over the previous grapheme cluster
+    pub fn cursor_back(&mut self) {
+        let target = self.prev_cluster_boundary(self.cursor_abs);
+        while self.cursor_abs > target {
+            self.step_back();
+        }
+        self.scroll_to_cursor();
+    }
+
+    /// Move the cursor a single character forward
+    fn step_forward(&mut self) {
+        if let Some(fch) = self.content.get((self.cursor_abs) as usize) {
+            if fch.ch == '\n' {
+                debug!("New line!");
+                self.cursor_col = 0;
+                self.cursor_row += 1;
+            } else {
+                self.cursor_col += 1;
+            }
+            self.cursor_abs += 1;
+        }
+    }
+
+    /// Move the cursor a single character back
+    fn step_back(&mut self) {
+        if self.cursor_abs == 0 {
+            return;
+        }
+        if self.content.get((self.cursor_abs - 1) as usize).is_some() {
+            self.cursor_abs -= 1;
+            if self.cursor_col == 0 {
+                // the cursor is now on the line break, at the end of the previous line
+                self.cursor_row -= 1;
+                self.cursor_col = self.cursor_abs - self.line_start(self.cursor_abs);
+            } else {
+                self.cursor_col -= 1;
+            }
+        }
+    }
+
+    /// Split the line containing `pos` into grapheme clusters, as (start, end) positions
+    fn line_clusters(&self, pos: u32) -> Vec<(u32, u32)> {
+        let start = self.line_start(pos);
+        let end = self.line_end(pos);
+        let text = self
+            .content
+            .range(start as usize..end as usize)
+            .map(|fch| fch.ch)
+            .collect::<String>();
+        let mut boundary = start;
+        text.graphemes(true)
+            .map(|cluster| {
+                let cluster_start = boundary;
+                boundary += cluster.chars().count() as u32;
+                (cluster_start, boundary)
+            })
+            .collect()
+    }
+
+    /// End of the grapheme cluster at `pos`. A newline is a cluster of its own.
+    fn next_cluster_boundary(&self, pos: u32) -> u32 {
+        if pos as usize >= self.content.len() {
+            return pos;
+        }
+        if self.content[pos as usize].ch == '\n' {
+            return pos + 1;
+        }
+        self.line_clusters(pos)
+            .into_iter()
+            .find(|&(_, end)| end > pos)
+            .map_or(pos + 1, |(_, end)| end)
+    }
+
+    /// Start of the grapheme cluster in front of `pos`. A newline is a cluster of its own.
+    fn prev_cluster_boundary(&self, pos: u32) -> u32 {
+        if pos == 0 {
+            return 0;
+        }
+        if self.content[pos as usize - 1].ch == '\n' {
+            return pos - 1;
+        }
+        self.line_clusters(pos)
+            .into_iter()
+            .rev()
+            .find(|&(start, _)| start < pos)
+            .map_or(pos - 1, |(start, _)| start)
+    }
+
+    /// Absolute position of the first character on the line containing `pos`
+    fn line_start(&self, pos: u32) -> u32 {
+        self.content
+            .range(0..pos as usize)
+            .rev()
+            .position(|fch| fch.ch == '\n')
+            .map_or(0, |idx| pos - idx as u32)
+    }
+
+    /// Absolute position of the newline (or buffer end) terminating the line containing `pos`
+    fn line_end(&self, pos: u32) -> u32 {
+        self.content
+            .range(pos as usize..self.content.len())
+            .position(|fch| fch.ch == '\n')
+            .map_or(self.content.len() as u32, |idx| pos + idx as u32)
+    }
+
+    pub fn cursor_up(&mut self) {
+        let start = self.line_start(self.cursor_abs);
+        if start == 0 {
+            return;
+        }
+        let prev_start = self.line_start(start - 1);
+        let prev_len = start - 1 - prev_start;
+        self.cursor_col = self.cursor_col.min(prev_len);
+        self.cursor_abs = prev_start + self.cursor_col;
+        self.cursor_row -= 1;
+        self.scroll_to_cursor();
+    }
+
+    pub fn cursor_down(&mut self) {
+        let end = self.line_end(self.cursor_abs);
+        if end as usize >= self.content.len() {
+            return;
+        }
+        let next_start = end + 1;
+        let next_len = self.line_end(next_start) - next_start;
+        self.cursor_col = self.cursor_col.min(next_len);
+        self.cursor_abs = next_start + self.cursor_col;
+        self.cursor_row += 1;
+        self.scroll_to_cursor();
+    }
+
+    pub fn cursor_home(&mut self) {
+        self.cursor_abs = self.line_start(self.cursor_abs);
+        self.cursor_col = 0;
+        self.scroll_to_cursor();
+    }
+
+    pub fn cursor_end(&mut self) {
+        let end = self.line_end(self.cursor_abs);
+        self.cursor_col += end - self.cursor_abs;
+        self.cursor_abs = end;
+        self.scroll_to_cursor();
+    }
+
+    /// Position after the word starting at or after `pos` and the whitespace following it.
+    /// Newlines count as a word of their own.
+    fn next_word_boundary(&self, pos: u32) -> u32 {
+        let len = self.content.len() as u32;
+        let is_space = |fch: &Rc<FontChar>| fch.ch.is_whitespace() && fch.ch != '\n';
+        if pos < len && self.content[pos as usize].ch == '\n' {
+            return pos + 1;
+        }
+        let rest = self.content.range(pos as usize..len as usize);
+        let word = rest
+            .clone()
+            .take_while(|fch| !fch.ch.is_whitespace())
+            .count();
+        let space = rest.skip(word).take_while(|fch| is_space(fch)).count();
+        pos + (word + space) as u32
+    }
+
+    /// Position at the start of the word before `pos`, including whitespace in between.
+    /// Newlines count as a word of their own.
+    fn prev_word_boundary(&self, pos: u32) -> u32 {
+        let is_space = |fch: &Rc<FontChar>| fch.ch.is_whitespace() && fch.ch != '\n';
+        if pos > 0 && self.content[pos as usize - 1].ch == '\n' {
+            return pos - 1;
+        }
+        let before = self.content.range(0..pos as usize).rev();
+        let space = before.clone().take_while(|fch| is_space(fch)).count();
+        let word = before
+            .skip(space)
+            .take_while(|fch| !fch.ch.is_whitespace())
+            .count();
+        pos - (space + word) as u32
+    }
+
+    pub fn cursor_word_forward(&mut self) {
+        self.move_cursor_to(self.next_word_boundary(self.cursor_abs));
+    }
+
+    pub fn cursor_word_back(&mut self) {
+        self.move_cursor_to(self.prev_word_boundary(self.cursor_abs));
+    }
+
+    /// Place the cursor at an absolute position, recomputing its row and column
+    pub fn move_cursor_to(&mut self, pos: u32) {
+        let pos = pos.min(self.content.len() as u32);
+        self.cursor_abs = pos;
+        self.cursor_row = self
+            .content
+            .range(0..pos as usize)
+            .filter(|fch| fch.ch == '\n')
+            .count() as u32;
+        self.cursor_col = pos - self.line_start(pos);
+        self.scroll_to_cursor();
+    }
+
+    /// Position of the bracket next to the cursor, preferring the one after it
+    pub(crate) fn adjacent_bracket(&self) -> Option<u32> {
+        let is_bracket = |pos: u32| {
+            self.content.get(pos as usize).is_some_and(|fch| {
+                BRACKET_PAIRS
+                    .iter()
+                    .any(|&(open, close)| fch.ch == open || fch.ch == close)
+            })
+        };
+        if is_bracket(self.cursor_abs) {
+            Some(self.cursor_abs)
+        } else if self.cursor_abs > 0 && is_bracket(self.cursor_abs - 1) {
+            Some(self.cursor_abs - 1)
+        } else {
+            None
+        }
+    }
+
+    /// Position of the balanced partner of the bracket next to the cursor
+    pub fn matching_bracket(&self) -> Option<u32> {
+        let pos = self.adjacent_bracket()?;
+        let ch = self.content[pos as usize].ch;
+        let &(open, close) = BRACKET_PAIRS
+            .iter()
+            .find(|&&(open, close)| ch == open || ch == close)?;
+        let mut depth = 0;
+        if ch == open {
+            let after = self.content.range(pos as usize..self.content.len());
+            for (idx, fch) in after.enumerate() {
+                if fch.ch == open {
+                    depth += 1;
+                } else if fch.ch == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(pos + idx as u32);
+                    }
+                }
+            }
+        } else {
+            let before = self.content.range(0..pos as usize + 1).rev();
+            for (idx, fch) in before.enumerate() {
+                if fch.ch == close {
+                    depth += 1;
+                } else if fch.ch == open {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(pos - idx as u32);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    #[inline]
+    pub fn find(&self) -> Option<&str> {
+        self.find.as_deref()
+    }
+    /// Highlight every occurrence of `query`, or stop searching with `None`
+    pub fn set_find(&mut self, query: Option<String>) {
+        self.find = query;
+    }
+    #[inline]
+    pub fn set_find_case_insensitive(&mut self, enabled: bool) {
+        self.find_case_insensitive = enabled;
+    }
+
+    /// Every occurrence of the find query in the buffer
+    pub fn find_matches(&self) -> Vec<Range<u32>> {
+        match &self.find {
+            Some(query) => self.matches_of(query),
+            None => Vec::new(),
+        }
+    }
+
+    /// Every non-overlapping occurrence of `query` in the buffer
+    fn matches_of(&self, query: &str) -> Vec<Range<u32>> {
+        let query: Vec<char> = query.chars().collect();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let text: Vec<char> = self.content.iter().map(|fch| fch.ch).collect();
+        let same = |a: char, b: char| {
+            a == b || (self.find_case_insensitive && a.to_lowercase().eq(b.to_lowercase()))
+        };
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start + query.len() <= text.len() {
+            let window = &text[start..start + query.len()];
+            if window.iter().zip(&query).all(|(&a, &b)| same(a, b)) {
+                matches.push(start as u32..(start + query.len()) as u32);
+                start += query.len();
+            } else {
+                start += 1;
+            }
+        }
+        matches
+    }
+
+    /// Replace the next occurrence of `query` at or after the cursor, wrapping around at the end.
+    /// Returns false if there is none.
+    pub fn replace_next(&mut self, query: &str, with: &[Rc<FontChar>]) -> bool {
+        let matches = self.matches_of(query);
+        let next = matches
+            .iter()
+            .find(|found| found.start >= self.cursor_abs)
+            .or(matches.first())
+            .cloned();
+        let Some(found) = next else {
+            return false;
+        };
+        let op = self.replace_range(found, with);
+        self.push_edit(op);
+        true
+    }
+
+    /// Replace every occurrence of `query` as a single undo step, returning how many there were
+    pub fn replace_all(&mut self, query: &str, with: &[Rc<FontChar>]) -> usize {
+        // back to front, so the earlier matches stay where they are
+        let ops: Vec<EditOp> = self
+            .matches_of(query)
+            .into_iter()
+            .rev()
+            .map(|found| self.replace_range(found, with))
+            .collect();
+        let count = ops.len();
+        if count > 0 {
+            self.push_edit(EditOp::Group(ops));
+        }
+        count
+    }
+
+    /// Swap the characters in `range` for `with`, leaving the cursor after them
+    fn replace_range(&mut self, range: Range<u32>, with: &[Rc<FontChar>]) -> EditOp {
+        let old = self.apply_delete(range.start, range.len());
+        self.apply_insert(range.start, with);
+        EditOp::Replace {
+            pos: range.start,
+            old,
+            new: with.to_vec(),
+        }
+    }
+
+    /// Move the cursor to the next match after it, wrapping around at the end.
+    /// Returns false if there are no matches.
+    pub fn find_next(&mut self) -> bool {
+        let matches = self.find_matches();
+        let next = matches
+            .iter()
+            .find(|found| found.start > self.cursor_abs)
+            .or(matches.first());
+        match next {
+            Some(found) => {
+                self.move_cursor_to(found.start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the cursor to the previous match before it, wrapping around at the start.
+    /// Returns false if there are no matches.
+    pub fn find_prev(&mut self) -> bool {
+        let matches = self.find_matches();
+        let prev = matches
+            .iter()
+            .rev()
+            .find(|found| found.start < self.cursor_abs)
+            .or(matches.last());
+        match prev {
+            Some(found) => {
+                self.move_cursor_to(found.start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Put the cursor at the start of the 1-based `line`, or of the last line if there are fewer
+    pub fn goto_line(&mut self, line: usize) {
+        let start = self
+            .content
+            .iter()
+            .enumerate()
+            .filter(|(_, fch)| fch.ch == '\n')
+            .map(|(idx, _)| idx + 1)
+            .take(line.saturating_sub(1))
+            .last()
+            .unwrap_or(0);
+        self.move_cursor_to(start as u32);
+    }
+
+    /// Remove the highlighted region and drop the mark.
+    /// Returns false if there was no selection.
+    pub fn delete_selection(&mut self) -> bool {
+        let Some((start, end)) = self.selection_range() else {
+            return false;
+        };
+        self.highlight_mark = u32::MAX;
+        self.delete_range(start, end)
+    }
+
+    /// Remove everything between `start` and `end` as a single undo step
+    fn delete_range(&mut self, start: u32, end: u32) -> bool {
+        if start >= end {
+            return false;
+        }
+        let text = self.apply_delete(start, (end - start) as usize);
+        self.push_edit(EditOp::Delete { pos: start, text });
+        true
+    }
+
+    /// Remove the word in front of the cursor together with any whitespace after it
+    pub fn delete_word_back(&mut self) -> bool {
+        let start = self.prev_word_boundary(self.cursor_abs);
+        self.delete_range(start, self.cursor_abs)
+    }
+
+    #[inline]
+    pub fn push_char(&mut self, fch: Rc<FontChar>) {
+        // typing replaces the selection
+        if self.highlight_mark != u32::MAX {
+            self.delete_selection();
+        }
+        let pos = self.cursor_abs;
+        if self.overwrite && fch.ch != '\n' {
+            // line breaks are never overwritten, the line grows instead
+            let under = self.content.get(pos as usize).cloned();
+            if let Some(old) = under.filter(|old| old.ch != '\n') {
+                self.mark_line_dirty(self.cursor_row as usize, false);
+                self.content.remove(pos as usize);
+                self.content.insert(pos as usize, fch.clone());
+                self.step_forward();
+                self.scroll_to_cursor();
+                self.record_replace(pos, old, fch);
+                return;
+            }
+        }
+        self.mark_line_dirty(self.cursor_row as usize, fch.ch == '\n');
+        self.content.insert(self.cursor_abs as usize, fch.clone());
+        self.step_forward();
+        self.scroll_to_cursor();
+        self.record_insert(pos, fch);
+    }
+    /// Insert a line break and repeat the indentation of the current line after it
+    pub fn insert_newline_with_indent(&mut self, newline: Rc<FontChar>) {
+        let start = self.line_start(self.cursor_abs);
+        let indent: Vec<Rc<FontChar>> = self
+            .content
+            .range(start as usize..self.cursor_abs as usize)
+            .take_while(|fch| fch.ch == ' ' || fch.ch == '\t')
+            .cloned()
+            .collect();
+        self.push_char(newline);
+        self.push_string(indent);
+    }
+
+    /// Insert a copy of the current line below it and move the cursor onto the copy.
+    /// `newline` separates the copies if the line is the last one and has no line break.
+    pub fn duplicate_line(&mut self, newline: Rc<FontChar>) {
+        let start = self.line_start(self.cursor_abs);
+        let end = self.line_end(self.cursor_abs);
+        let col = self.cursor_abs - start;
+        let line = self.content.range(start as usize..end as usize).cloned();
+        let (pos, text): (u32, Vec<Rc<FontChar>>) = match self.content.get(end as usize) {
+            Some(line_break) => (end + 1, line.chain([line_break.clone()]).collect()),
+            None => (end, [newline].into_iter().chain(line).collect()),
+        };
+        let copy_start = if (end as usize) < self.content.len() {
+            pos
+        } else {
+            pos + 1
+        };
+        self.highlight_mark = u32::MAX;
+        self.apply_insert(pos, &text);
+        self.push_edit(EditOp::Insert { pos, text });
+        self.move_cursor_to(copy_start + col);
+    }
+
+    /// Swap the lines in `first..line_break` and `line_break + 1..second_end` as one undo step
+    fn swap_lines(&mut self, first: u32, line_break: u32, second_end: u32) {
+        let content = &self.content;
+        let text: Vec<Rc<FontChar>> = content
+            .range(line_break as usize + 1..second_end as usize)
+            .chain(content.range(line_break as usize..line_break as usize + 1))
+            .chain(content.range(first as usize..line_break as usize))
+            .cloned()
+            .collect();
+        let op = self.replace_range(first..second_end, &text);
+        self.push_edit(op);
+    }
+
+    /// Swap the current line with the one above it, keeping the cursor on the moved line.
+    /// Returns false on the first line.
+    pub fn move_line_up(&mut self) -> bool {
+        let start = self.line_start(self.cursor_abs);
+        if start == 0 {
+            return false;
+        }
+        let end = self.line_end(self.cursor_abs);
+        let col = self.cursor_abs - start;
+        let prev_start = self.line_start(start - 1);
+        self.highlight_mark = u32::MAX;
+        self.swap_lines(prev_start, start - 1, end);
+        self.move_cursor_to(prev_start + col);
+        true
+    }
+
+    /// Swap the current line with the one below it, keeping the cursor on the moved line.
+    /// Returns false on the last line.
+    pub fn move_line_down(&mut self) -> bool {
+        let end = self.line_end(self.cursor_abs);
+        if end as usize >= self.content.len() {
+            return false;
+        }
+        let start = self.line_start(self.cursor_abs);
+        let col = self.cursor_abs - start;
+        let next_end = self.line_end(end + 1);
+        self.highlight_mark = u32::MAX;
+        self.swap_lines(start, end, next_end);
+        self.move_cursor_to(start + (next_end - end) + col);
+        true
+    }
+
+    /// Starts of the lines touched by the selection, or of the cursor's line if there is none.
+    /// A line the selection merely ends in front of is left out.
+    fn selected_line_starts(&self) -> Vec<u32> {
+        let (start, end) = self
+            .selection_range()
+            .unwrap_or((self.cursor_abs, self.cursor_abs));
+        let mut starts = vec![self.line_start(start)];
+        for (idx, fch) in self.content.range(start as usize..end as usize).enumerate() {
+            let pos = start + idx as u32;
+            if fch.ch == '\n' && pos + 1 < end {
+                starts.push(pos + 1);
+            }
+        }
+        starts
+    }
+
+    /// True if the selection covers more than one line
+    pub fn selection_spans_lines(&self) -> bool {
+        self.selected_line_starts().len() > 1
+    }
+
+    /// Apply line-wise edits as one undo step, given as (line start, inserted, removed) and
+    /// ordered by line. Mark and cursor stay on the characters they were on.
+    fn edit_line_starts(&mut self, edits: Vec<(u32, Vec<Rc<FontChar>>, usize)>) {
+        let adjust = |pos: u32| {
+            edits.iter().fold(pos, |moved, (start, text, removed)| {
+                let inserted = if *start <= pos { text.len() as u32 } else { 0 };
+                let removed = (*removed as u32).min(pos.saturating_sub(*start));
+                moved + inserted - removed
+            })
+        };
+        let mark = (self.highlight_mark != u32::MAX).then(|| adjust(self.highlight_mark));
+        let cursor = adjust(self.cursor_abs);
+
+        let mut ops = Vec::new();
+        for (start, text, removed) in edits.into_iter().rev() {
+            if removed > 0 {
+                let text = self.apply_delete(start, removed);
+                ops.push(EditOp::Delete { pos: start, text });
+            }
+            if !text.is_empty() {
+                self.apply_insert(start, &text);
+                ops.push(EditOp::Insert { pos: start, text });
+            }
+        }
+        if !ops.is_empty() {
+            self.push_edit(EditOp::Group(ops));
+        }
+        self.move_cursor_to(cursor);
+        self.highlight_mark = mark.unwrap_or(u32::MAX);
+    }
+
+    /// Remove spaces and tabs at the end of every line, as one undo step
+    pub fn trim_now(&mut self) {
+        let mut edits = Vec::new();
+        let mut start = 0;
+        for idx in 0..self.content.line_count() {
+            let line = self.content.line(idx).unwrap();
+            let text_len = match line.iter().last() {
+                Some(fch) if fch.ch == '\n' => line.len() - 1,
+                _ => line.len(),
+            };
+            let trailing = line
+                .range(0..text_len)
+                .rev()
+                .take_while(|fch| matches!(fch.ch, ' ' | '\t'))
+                .count();
+            if trailing > 0 {
+                edits.push(((start + text_len - trailing) as u32, Vec::new(), trailing));
+            }
+            start += line.len();
+        }
+        self.edit_line_starts(edits);
+    }
+
+    /// Put `indent` in front of every selected line
+    pub fn indent_selection(&mut self, indent: &[Rc<FontChar>]) {
+        let edits = self
+            .selected_line_starts()
+            .into_iter()
+            .map(|start| (start, indent.to_vec(), 0))
+            .collect();
+        self.edit_line_starts(edits);
+    }
+
+    /// Remove one tab or up to `tab_width` spaces from the start of every selected line
+    pub fn dedent_selection(&mut self) {
+        let edits = self
+            .selected_line_starts()
+            .into_iter()
+            .map(|start| {
+                let mut rest = self.content.range(start as usize..self.content.len());
+                let removed = match rest.next() {
+                    Some(fch) if fch.ch == '\t' => 1,
+                    Some(fch) if fch.ch == ' ' => {
+                        1 + rest
+                            .take(self.tab_width.max(1) as usize - 1)
+                            .take_while(|fch| fch.ch == ' ')
+                            .count()
+                    }
+                    _ => 0,
+                };
+                (start, Vec::new(), removed)
+            })
+            .collect();
+        self.edit_line_starts(edits);
+    }
+
+    /// Comment out every selected line, or uncomment them if all of them already are.
+    /// Empty lines are left alone.
+    pub fn toggle_comment(&mut self, font: &mut FontDef) {
+        let prefix: Vec<char> = self.comment_prefix.chars().collect();
+        if prefix.is_empty() {
+            return;
+        }
+        let lines: Vec<u32> = self
+            .selected_line_starts()
+            .into_iter()
+            .filter(|&start| self.line_end(start) > start)
+            .collect();
+        let commented = lines.iter().all(|&start| {
+            let len = self.content.len();
+            let line = self.content.range(start as usize..len).map(|fch| fch.ch);
+            line.take(prefix.len()).eq(prefix.iter().copied())
+        });
+        let edits = if commented {
+            lines
+                .into_iter()
+                .map(|start| (start, Vec::new(), prefix.len()))
+                .collect()
+        } else {
+            let glyphs = font.get_string_lossy(self.comment_prefix.clone());
+            lines
+                .into_iter()
+                .map(|start| (start, glyphs.clone(), 0))
+                .collect()
+        };
+        self.edit_line_starts(edits);
+    }
+
+    /// Remove the grapheme cluster after the cursor
+    pub fn delete_char(&mut self) -> Option<Rc<FontChar>> {
+        if self.cursor_abs as usize >= self.content.len() {
+            return None;
+        }
+        self.cursor_forward();
+        self.pop_char()
+    }
+    /// Remove the grapheme cluster in front of the cursor, returning its base character
+    pub fn pop_char(&mut self) -> Option<Rc<FontChar>> {
+        let start = self.prev_cluster_boundary(self.cursor_abs);
+        let mut removed = None;
+        while self.cursor_abs > start {
+            removed = self.pop_single_char();
+        }
+        self.scroll_to_cursor();
+        removed
+    }
+
+    fn pop_single_char(&mut self) -> Option<Rc<FontChar>> {
+        // nothing in front of the cursor to remove
+        if self.cursor_abs == 0 || self.cursor_abs as usize > self.content.len() {
+            return None;
+        }
+        // the character in front of the original cursor position
+        let pos = self.cursor_abs - 1;
+        let fch = self.content.remove(pos as usize);
+        self.cursor_abs = pos;
+        if fch.ch == '\n' {
+            // the cursor ends up where the line break was, at the end of the line above
+            self.cursor_row = self.cursor_row.saturating_sub(1);
+            self.cursor_col = pos - self.line_start(pos);
+        } else {
+            self.cursor_col = self.cursor_col.saturating_sub(1);
+        }
+        self.mark_line_dirty(self.cursor_row as usize, fch.ch == '\n');
+        self.record_delete(pos, fch.clone());
+        Some(fch)
+    }
+
+    // #region Undo And Redo
+    /// Consecutive typing is merged into a single undo step, a newline starts a new one
+    fn record_insert(&mut self, pos: u32, fch: Rc<FontChar>) {
+        self.redo_stack.clear();
+        if let Some(EditOp::Insert {
+            pos: last_pos,
+            text,
+        }) = self.undo_stack.last_mut()
+        {
+            if *last_pos + text.len() as u32 == pos && fch.ch != '\n' {
+                text.push(fch);
+                return;
+            }
+        }
+        self.undo_stack.push(EditOp::Insert {
+            pos,
+            text: vec![fch],
+        });
+    }
+
+    /// Consecutive backspaces are merged into a single undo step
+    fn record_delete(&mut self, pos: u32, fch: Rc<FontChar>) {
+        self.redo_stack.clear();
+        if let Some(EditOp::Delete {
+            pos: last_pos,
+            text,
+        }) = self.undo_stack.last_mut()
+        {
+            if pos + 1 == *last_pos {
+                text.insert(0, fch);
+                *last_pos = pos;
+                return;
+            }
+        }
+        self.undo_stack.push(EditOp::Delete {
+            pos,
+            text: vec![fch],
+        });
+    }
+
+    /// Consecutive overwritten characters are merged into a single undo step
+    fn record_replace(&mut self, pos: u32, old: Rc<FontChar>, new: Rc<FontChar>) {
+        self.redo_stack.clear();
+        if let Some(EditOp::Replace {
+            pos: last_pos,
+            old: last_old,
+            new: last_new,
+        }) = self.undo_stack.last_mut()
+        {
+            if *last_pos + last_new.len() as u32 == pos && last_old.len() == last_new.len() {
+                last_old.push(old);
+                last_new.push(new);
+                return;
+            }
+        }
+        self.undo_stack.push(EditOp::Replace {
+            pos,
+            old: vec![old],
+            new: vec![new],
+        });
+    }
+
+    fn apply_insert(&mut self, pos: u32, text: &[Rc<FontChar>]) {
+        let newline = text.iter().any(|fch| fch.ch == '\n');
+        self.mark_line_dirty(self.line_of(pos), newline);
+        let pos = pos as usize;
+        self.content.insert_many(pos, text.iter().cloned());
+        self.move_cursor_to((pos + text.len()) as u32);
+    }
+
+    fn apply_delete(&mut self, pos: u32, len: usize) -> Vec<Rc<FontChar>> {
+        let pos = pos as usize;
+        let text = self.content.remove_range(pos..pos + len);
+        let newline = text.iter().any(|fch| fch.ch == '\n');
+        self.mark_line_dirty(self.line_of(pos as u32), newline);
+        self.move_cursor_to(pos as u32);
+        text
+    }
+
+    /// Record an edit that has already been applied as its own undo step
+    fn push_edit(&mut self, op: EditOp) {
+        self.redo_stack.clear();
+        self.undo_stack.push(op);
+    }
+
+    fn revert(&mut self, op: &EditOp) {
+        match op {
+            EditOp::Insert { pos, text } => {
+                self.apply_delete(*pos, text.len());
+            }
+            EditOp::Delete { pos, text } => self.apply_insert(*pos, text),
+            EditOp::Replace { pos, old, new } => {
+                self.apply_delete(*pos, new.len());
+                self.apply_insert(*pos, old);
+            }
+            EditOp::Group(ops) => {
+                for op in ops.iter().rev() {
+                    self.revert(op);
+                }
+            }
+        }
+    }
+
+    fn reapply(&mut self, op: &EditOp) {
+        match op {
+            EditOp::Insert { pos, text } => self.apply_insert(*pos, text),
+            EditOp::Delete { pos, text } => {
+                self.apply_delete(*pos, text.len());
+            }
+            EditOp::Replace { pos, old, new } => {
+                self.apply_delete(*pos, old.len());
+                self.apply_insert(*pos, new);
+            }
+            EditOp::Group(ops) => {
+                for op in ops {
+                    self.reapply(op);
+                }
+            }
+        }
+    }
+
+    /// Revert the last edit, returns false if there was nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(op) = self.undo_stack.pop() else {
+            return false;
+        };
+        self.revert(&op);
+        self.redo_stack.push(op);
+        true
+    }
+
+    /// Reapply the last undone edit, returns false if there was nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let Some(op) = self.redo_stack.pop() else {
+            return false;
+        };
+        self.reapply(&op);
+        self.undo_stack.push(op);
+        true
+    }
+
+    #[inline]
+    pub fn clear_history(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    /// Swap every glyph for its counterpart in a newly built font, keeping the cursor in place
+    pub fn reload_glyphs(&mut self, font: &mut FontDef) {
+        let text = self.get_text();
+        self.content = font.get_string_lossy(text).into();
+        self.revision += 1;
+        self.invalidate_lines();
+        // the history still references glyphs of the old font
+        self.clear_history();
+        self.scroll_to_cursor();
+    }
+    // #endregion
+    #[inline]
+    pub fn push_string<T: Into<Vec<Rc<FontChar>>>>(&mut self, fstr: T) {
+        let fstr: Vec<Rc<FontChar>> = fstr.into();
+        for fch in fstr {
+            self.push_char(fch);
+        }
+    }
+    #[inline]
+    pub fn clear(&mut self) {
+        self.cursor_col = 0;
+        self.cursor_row = 0;
+        self.cursor_abs = 0;
+        self.highlight_mark = u32::MAX;
+        self.content.clear();
+        self.revision += 1;
+        self.clear_history();
+        self.invalidate_lines();
+    }
+
+    /// Ask views to bring the cursor into sight after their next layout
+    #[inline]
+    pub fn scroll_to_cursor(&mut self) {
+        self.follow_cursor = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::rect::Rect;
+
+    fn glyphs(text: &str) -> Vec<Rc<FontChar>> {
+        text.chars()
+            .map(|ch| Rc::new(FontChar::new(ch, Rect::new(0, 0, 8, 10), 8, 0, 0, 8, 0)))
+            .collect()
+    }
+
+    fn glyph(ch: char) -> Rc<FontChar> {
+        glyphs(&ch.to_string()).remove(0)
+    }
+
+    /// A buffer holding `text` with the cursor at its end
+    fn buffer(text: &str) -> EditBuffer {
+        let mut buffer = EditBuffer::new();
+        buffer.push_string(glyphs(text));
+        buffer
+    }
+
+    fn cursor(buffer: &EditBuffer) -> (u32, u32) {
+        (buffer.get_cursor_row(), buffer.get_cursor_col())
+    }
+
+    #[test]
+    fn cursor_up_and_down_clamp_to_shorter_lines() {
+        let mut buffer = buffer("long line\nab\nanother long line");
+        buffer.move_cursor_to(7);
+        buffer.cursor_down();
+        assert_eq!(cursor(&buffer), (1, 2));
+        assert_eq!(buffer.cursor_abs, 12);
+        buffer.cursor_down();
+        assert_eq!(cursor(&buffer), (2, 2));
+        buffer.cursor_up();
+        buffer.cursor_up();
+        assert_eq!(cursor(&buffer), (0, 2));
+        assert_eq!(buffer.cursor_abs, 2);
+    }
+
+    #[test]
+    fn cursor_up_and_down_stop_at_the_ends() {
+        let mut buffer = buffer("one\ntwo");
+        buffer.move_cursor_to(1);
+        buffer.cursor_up();
+        assert_eq!(cursor(&buffer), (0, 1));
+        assert_eq!(buffer.cursor_abs, 1);
+        buffer.cursor_down();
+        buffer.cursor_down();
+        assert_eq!(cursor(&buffer), (1, 1));
+        assert_eq!(buffer.cursor_abs, 5);
+    }
+
+    #[test]
+    fn home_and_end_stay_on_the_current_line() {
+        let mut buffer = buffer(
+            "first
+second line
+last",
+        );
+        buffer.move_cursor_to(9);
+        buffer.cursor_home();
+        assert_eq!(cursor(&buffer), (1, 0));
+        assert_eq!(buffer.cursor_abs, 6);
+        buffer.cursor_end();
+        assert_eq!(cursor(&buffer), (1, 11));
+        assert_eq!(buffer.cursor_abs, 17);
+        buffer.cursor_down();
+        buffer.cursor_end();
+        assert_eq!(cursor(&buffer), (2, 4));
+        assert_eq!(buffer.cursor_abs, buffer.char_count() as u32);
+    }
+
+    #[test]
+    fn selected_text_covers_mark_to_cursor_either_way() {
+        let mut buffer = buffer("hello\nworld");
+        assert_eq!(buffer.selected_text(), None);
+        buffer.move_cursor_to(3);
+        buffer.update_selection(true);
+        buffer.move_cursor_to(8);
+        assert_eq!(buffer.selection_range(), Some((3, 8)));
+        assert_eq!(buffer.selected_text().as_deref(), Some("lo\nwo"));
+        buffer.update_selection(false);
+        buffer.update_selection(true);
+        buffer.move_cursor_to(1);
+        assert_eq!(buffer.selected_text().as_deref(), Some("ello\nwo"));
+    }
+
+    #[test]
+    fn update_selection_sets_extends_and_clears_the_mark() {
+        let mut buffer = buffer("hello");
+        buffer.move_cursor_to(1);
+        buffer.update_selection(true);
+        assert_eq!(buffer.highlight_mark, 1);
+        buffer.cursor_forward();
+        // extending again keeps the mark where the selection started
+        buffer.update_selection(true);
+        buffer.cursor_forward();
+        assert_eq!(buffer.highlight_mark, 1);
+        assert_eq!(buffer.selection_range(), Some((1, 3)));
+        buffer.update_selection(false);
+        assert_eq!(buffer.highlight_mark, u32::MAX);
+        assert_eq!(buffer.selection_range(), None);
+    }
+
+    #[test]
+    fn select_all_spans_every_line() {
+        let mut buffer = buffer("one\ntwo\nthree");
+        buffer.move_cursor_to(2);
+        buffer.select_all();
+        assert_eq!(buffer.selection_range(), Some((0, 13)));
+        assert_eq!(buffer.selected_text().as_deref(), Some("one\ntwo\nthree"));
+        assert_eq!(cursor(&buffer), (2, 5));
+
+        let mut empty = EditBuffer::new();
+        empty.select_all();
+        assert_eq!(empty.selection_range(), None);
+        assert_eq!(cursor(&empty), (0, 0));
+    }
+
+    #[test]
+    fn typing_replaces_the_selection() {
+        let mut buffer = buffer("one two");
+        buffer.move_cursor_to(4);
+        buffer.update_selection(true);
+        buffer.move_cursor_to(7);
+        buffer.push_char(glyph('x'));
+        assert_eq!(buffer.get_text(), "one x");
+        assert_eq!(buffer.cursor_abs, 5);
+        assert_eq!(buffer.selection_range(), None);
+    }
+
+    #[test]
+    fn typing_marks_only_the_edited_line_dirty() {
+        let mut buffer = buffer("one\ntwo\nthree");
+        buffer.dirty_lines.clear();
+        buffer.dirty_from = None;
+        buffer.move_cursor_to(5);
+        buffer.push_char(glyph('x'));
+        assert_eq!(buffer.dirty_lines(), &HashSet::from([1]));
+        assert_eq!(buffer.dirty_from, None);
+        // a line break moves every line below it
+        buffer.push_char(glyph('\n'));
+        assert_eq!(buffer.dirty_from, Some(1));
+    }
+
+    #[test]
+    fn backspace_removes_a_whole_combining_cluster() {
+        // an e followed by a combining acute accent
+        let mut buffer = buffer("ae\u{301}");
+        buffer.pop_char();
+        assert_eq!(buffer.get_text(), "a");
+        assert_eq!(cursor(&buffer), (0, 1));
+        // and undoing brings back both characters
+        buffer.undo();
+        assert_eq!(buffer.get_text(), "ae\u{301}");
+    }
+
+    #[test]
+    fn matching_bracket_in_nested_pairs() {
+        let mut nested = buffer("([])");
+        for (cursor, partner) in [(0, 3), (1, 2), (2, 1), (3, 0), (4, 0)] {
+            nested.move_cursor_to(cursor);
+            assert_eq!(
+                nested.matching_bracket(),
+                Some(partner),
+                "cursor at {cursor}"
+            );
+        }
+        let mut unmatched = buffer("(a");
+        unmatched.move_cursor_to(0);
+        assert_eq!(unmatched.matching_bracket(), None);
+        unmatched.move_cursor_to(2);
+        assert_eq!(unmatched.matching_bracket(), None);
+    }
+
+    #[test]
+    fn overwrite_replaces_mid_line_but_never_a_line_break() {
+        let mut buffer = buffer("abc\nd");
+        buffer.move_cursor_to(1);
+        buffer.push_char(glyph('x'));
+        assert_eq!(buffer.get_text(), "axbc\nd");
+
+        buffer.set_overwrite(true);
+        buffer.move_cursor_to(1);
+        buffer.push_char(glyph('y'));
+        assert_eq!(buffer.get_text(), "aybc\nd");
+        buffer.move_cursor_to(4);
+        // at the end of the line the line grows instead
+        buffer.push_char(glyph('z'));
+        assert_eq!(buffer.get_text(), "aybcz\nd");
+        assert_eq!(cursor(&buffer), (0, 5));
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.get_text(), "axbc\nd");
+    }
+
+    #[test]
+    fn status_shows_the_selection_and_the_insert_mode() {
+        let mut buffer = buffer("one\ntwo");
+        assert_eq!(
+            buffer.status_string(),
+            "Line: 1; Char: 3; Lines: 2; Chars: 7; INS"
+        );
+        buffer.update_selection(true);
+        buffer.move_cursor_to(2);
+        assert_eq!(
+            buffer.status_string(),
+            "Line: 0; Char: 2; Lines: 2; Chars: 7; Selected: 5; INS"
+        );
+        buffer.update_selection(false);
+        buffer.set_overwrite(true);
+        assert_eq!(
+            buffer.status_string(),
+            "Line: 0; Char: 2; Lines: 2; Chars: 7; OVR"
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_text() {
+        let mut buffer = buffer("abc");
+        buffer.move_cursor_to(1);
+        buffer.pop_char();
+        assert_eq!(buffer.get_text(), "bc");
+        assert!(buffer.undo());
+        assert_eq!(buffer.get_text(), "abc");
+        assert!(buffer.undo());
+        assert_eq!(buffer.get_text(), "");
+        assert!(!buffer.undo());
+        assert!(buffer.redo());
+        assert_eq!(buffer.get_text(), "abc");
+        assert!(buffer.redo());
+        assert_eq!(buffer.get_text(), "bc");
+        assert!(!buffer.redo());
+    }
+
+    #[test]
+    fn new_edit_clears_the_redo_stack() {
+        let mut buffer = buffer("ab");
+        buffer.pop_char();
+        assert!(buffer.undo());
+        buffer.push_string(glyphs("x"));
+        assert!(!buffer.redo());
+        assert_eq!(buffer.get_text(), "abx");
+    }
+
+    #[test]
+    fn typed_characters_coalesce_into_one_undo_step() {
+        let mut buffer = buffer("one");
+        buffer.push_string(glyphs("\ntwo"));
+        assert_eq!(buffer.undo_stack.len(), 2);
+        assert!(buffer.undo());
+        assert_eq!(buffer.get_text(), "one");
+        assert!(buffer.undo());
+        assert_eq!(buffer.get_text(), "");
+    }
+
+    #[test]
+    fn cursor_back_over_a_line_break_ends_up_after_the_previous_line() {
+        let mut buffer = buffer("first\nsecond line\nthird");
+        buffer.move_cursor_to(18);
+        assert_eq!(cursor(&buffer), (2, 0));
+        buffer.cursor_back();
+        assert_eq!(cursor(&buffer), (1, "second line".len() as u32));
+        buffer.cursor_back();
+        assert_eq!(cursor(&buffer), (1, 10));
+
+        buffer.move_cursor_to(6);
+        buffer.cursor_back();
+        assert_eq!(cursor(&buffer), (0, "first".len() as u32));
+        // the column matches the position all the way back to the start
+        while buffer.cursor_abs > 0 {
+            buffer.cursor_back();
+            assert_eq!(buffer.get_cursor_col(), buffer.cursor_abs);
+        }
+    }
+
+    #[test]
+    fn cursor_back_and_pop_char_on_an_empty_buffer_do_nothing() {
+        let mut buffer = EditBuffer::new();
+        buffer.cursor_back();
+        assert!(buffer.pop_char().is_none());
+        assert!(buffer.delete_char().is_none());
+        assert_eq!(cursor(&buffer), (0, 0));
+        assert_eq!(buffer.cursor_abs, 0);
+        assert_eq!(buffer.get_text(), "");
+        assert!(!buffer.undo());
+    }
+
+    #[test]
+    fn word_motion_skips_runs_of_spaces() {
+        let mut buffer = buffer("foo   bar  baz");
+        buffer.move_cursor_to(0);
+        buffer.cursor_word_forward();
+        assert_eq!(buffer.cursor_abs, 6);
+        buffer.cursor_word_forward();
+        assert_eq!(buffer.cursor_abs, 11);
+        buffer.cursor_word_forward();
+        assert_eq!(buffer.cursor_abs, 14);
+        buffer.cursor_word_back();
+        assert_eq!(buffer.cursor_abs, 11);
+        buffer.cursor_word_back();
+        assert_eq!(buffer.cursor_abs, 6);
+        buffer.move_cursor_to(4);
+        buffer.cursor_word_back();
+        assert_eq!(buffer.cursor_abs, 0);
+    }
+
+    #[test]
+    fn delete_word_back_mid_line() {
+        let mut buffer = buffer("let value = 1;\nnext");
+        buffer.move_cursor_to(12);
+        assert!(buffer.delete_word_back());
+        assert_eq!(buffer.get_text(), "let value 1;\nnext");
+        assert_eq!(buffer.cursor_abs, 10);
+        assert_eq!(cursor(&buffer), (0, 10));
+        assert!(buffer.delete_word_back());
+        assert_eq!(buffer.get_text(), "let 1;\nnext");
+        assert_eq!(cursor(&buffer), (0, 4));
+    }
+
+    #[test]
+    fn enter_repeats_the_indentation() {
+        let mut buffer = buffer("fn main() {\n    \tlet x = 1;");
+        buffer.insert_newline_with_indent(glyph('\n'));
+        assert_eq!(buffer.get_text(), "fn main() {\n    \tlet x = 1;\n    \t");
+        assert_eq!(cursor(&buffer), (2, 5));
+        // only the indentation in front of the cursor is repeated
+        buffer.move_cursor_to(2);
+        buffer.insert_newline_with_indent(glyph('\n'));
+        assert_eq!(buffer.get_text(), "fn\n main() {\n    \tlet x = 1;\n    \t");
+        assert_eq!(cursor(&buffer), (1, 0));
+    }
+
+    #[test]
+    fn find_visits_every_occurrence_in_turn() {
+        let mut buffer = buffer("cat dog\ncat\nconcat");
+        buffer.set_find(Some(String::from("cat")));
+        assert_eq!(buffer.find_matches(), vec![0..3, 8..11, 15..18]);
+        buffer.move_cursor_to(0);
+        assert!(buffer.find_next());
+        assert_eq!(buffer.cursor_abs, 8);
+        assert!(buffer.find_next());
+        assert_eq!(buffer.cursor_abs, 15);
+        // wraps around to the first one
+        assert!(buffer.find_next());
+        assert_eq!(buffer.cursor_abs, 0);
+        assert!(buffer.find_prev());
+        assert_eq!(buffer.cursor_abs, 15);
+        buffer.set_find(Some(String::from("bird")));
+        assert!(!buffer.find_next());
+    }
+
+    #[test]
+    fn find_wraps_around_from_the_last_match() {
+        let mut buffer = buffer("one two one two");
+        buffer.set_find(Some(String::from("two")));
+        // on the last match
+        buffer.move_cursor_to(12);
+        assert!(buffer.find_next());
+        assert_eq!(buffer.cursor_abs, 4);
+        // past the last match, at the end of the text
+        buffer.move_cursor_to(15);
+        assert!(buffer.find_next());
+        assert_eq!(buffer.cursor_abs, 4);
+        // and backwards from the first one
+        assert!(buffer.find_prev());
+        assert_eq!(buffer.cursor_abs, 12);
+    }
+
+    #[test]
+    fn goto_line_lands_at_the_start_of_the_line() {
+        let text: Vec<String> = (1..=10).map(|line| format!("line {line}")).collect();
+        let mut buffer = buffer(&text.join("\n"));
+        buffer.goto_line(5);
+        assert_eq!(cursor(&buffer), (4, 0));
+        assert_eq!(buffer.cursor_abs, 4 * 7);
+        buffer.goto_line(1);
+        assert_eq!(cursor(&buffer), (0, 0));
+        // past the end goes to the last line
+        buffer.goto_line(42);
+        assert_eq!(cursor(&buffer), (9, 0));
+    }
+
+    #[test]
+    fn replace_one_then_all() {
+        let mut buffer = buffer("a cat, a cat, a cat");
+        buffer.move_cursor_to(3);
+        assert!(buffer.replace_next("cat", &glyphs("dog")));
+        assert_eq!(buffer.get_text(), "a cat, a dog, a cat");
+        assert_eq!(buffer.replace_all("cat", &glyphs("cow")), 2);
+        assert_eq!(buffer.get_text(), "a cow, a dog, a cow");
+        assert_eq!(buffer.replace_all("cat", &glyphs("cow")), 0);
+        // all replacements are undone at once
+        assert!(buffer.undo());
+        assert_eq!(buffer.get_text(), "a cat, a dog, a cat");
+    }
+
+    #[test]
+    fn replace_with_nothing_deletes_the_matches() {
+        let mut buffer = buffer("x--y--z");
+        assert_eq!(buffer.replace_all("--", &[]), 2);
+        assert_eq!(buffer.get_text(), "xyz");
+        assert!(!buffer.replace_next("--", &[]));
+    }
+
+    #[test]
+    fn duplicate_a_middle_line() {
+        let mut buffer = buffer("one\ntwo\nthree");
+        buffer.move_cursor_to(5);
+        buffer.duplicate_line(glyph('\n'));
+        assert_eq!(buffer.get_text(), "one\ntwo\ntwo\nthree");
+        assert_eq!(cursor(&buffer), (2, 1));
+        assert_eq!(buffer.cursor_abs, 9);
+    }
+
+    #[test]
+    fn move_a_middle_line_down_and_back() {
+        let mut buffer = buffer("one\ntwo\nthree\nfour");
+        buffer.move_cursor_to(5);
+        assert!(buffer.move_line_down());
+        assert_eq!(buffer.get_text(), "one\nthree\ntwo\nfour");
+        assert_eq!(cursor(&buffer), (2, 1));
+        assert!(buffer.move_line_up());
+        assert_eq!(buffer.get_text(), "one\ntwo\nthree\nfour");
+        assert_eq!(cursor(&buffer), (1, 1));
+        buffer.move_cursor_to(15);
+        assert!(!buffer.move_line_down());
+    }
+
+    #[test]
+    fn indent_and_dedent_a_two_line_selection() {
+        let mut buffer = buffer("alpha\nbeta\ngamma");
+        buffer.move_cursor_to(2);
+        buffer.update_selection(true);
+        buffer.move_cursor_to(8);
+        buffer.indent_selection(&glyphs("    "));
+        assert_eq!(buffer.get_text(), "    alpha\n    beta\ngamma");
+        assert_eq!(buffer.selected_text().as_deref(), Some("pha\n    be"));
+        buffer.dedent_selection();
+        assert_eq!(buffer.get_text(), "alpha\nbeta\ngamma");
+    }
+
+    #[test]
+    fn toggle_comment_twice_restores_the_text() {
+        let mut font = crate::res_man::tests::monospace_font(8);
+        let original = "fn a() {}\n\nfn b() {}\n// note";
+        let mut buffer = buffer(original);
+        buffer.select_all();
+        buffer.toggle_comment(&mut font);
+        // empty lines are left alone
+        assert_eq!(
+            buffer.get_text(),
+            "// fn a() {}\n\n// fn b() {}\n// // note"
+        );
+        buffer.toggle_comment(&mut font);
+        assert_eq!(buffer.get_text(), original);
+    }
+
+    #[test]
+    fn line_and_char_counts() {
+        let empty = EditBuffer::new();
+        assert_eq!((empty.line_count(), empty.char_count()), (1, 0));
+        let trailing = buffer("one\n");
+        assert_eq!((trailing.line_count(), trailing.char_count()), (2, 4));
+        let multi = buffer("one\ntwo\n\nfour");
+        assert_eq!((multi.line_count(), multi.char_count()), (4, 13));
+    }
+
+    #[test]
+    fn pop_char_at_the_start_mid_line_and_across_a_newline() {
+        let mut buffer = buffer("ab\ncd");
+        buffer.move_cursor_to(0);
+        assert!(buffer.pop_char().is_none());
+        assert_eq!(buffer.get_text(), "ab\ncd");
+
+        buffer.move_cursor_to(2);
+        assert_eq!(buffer.pop_char().unwrap().ch, 'b');
+        assert_eq!(buffer.get_text(), "a\ncd");
+        assert_eq!(cursor(&buffer), (0, 1));
+
+        buffer.move_cursor_to(2);
+        assert_eq!(buffer.pop_char().unwrap().ch, '\n');
+        assert_eq!(buffer.get_text(), "acd");
+        assert_eq!(buffer.line_count(), 1);
+
+        buffer.move_cursor_to(1);
+        assert_eq!(buffer.delete_char().unwrap().ch, 'c');
+        assert_eq!(buffer.get_text(), "ad");
+        assert_eq!(cursor(&buffer), (0, 1));
+    }
+
+    #[test]
+    fn backspace_at_the_start_of_a_line_joins_it_to_the_previous() {
+        let mut buffer = buffer("first\nsecond\nthird");
+        buffer.move_cursor_to(6);
+        assert_eq!(cursor(&buffer), (1, 0));
+        buffer.pop_char();
+        assert_eq!(buffer.get_text(), "firstsecond\nthird");
+        assert_eq!(cursor(&buffer), (0, 5));
+        assert_eq!(buffer.cursor_abs, 5);
+        buffer.cursor_down();
+        assert_eq!(cursor(&buffer), (1, 5));
+    }
+
+    #[test]
+    fn edits_without_a_screen() {
+        let mut buffer = EditBuffer::new();
+        buffer.push_string(glyphs("held"));
+        buffer.cursor_back();
+        buffer.cursor_back();
+        buffer.push_string(glyphs("llo woor"));
+        assert_eq!(buffer.get_text(), "hello woorld");
+        assert_eq!(cursor(&buffer), (0, 10));
+        buffer.cursor_back();
+        buffer.cursor_back();
+        buffer.delete_char();
+        buffer.move_cursor_to(4);
+        buffer.cursor_forward();
+        buffer.push_char(glyph(','));
+        assert_eq!(buffer.get_text(), "hello, world");
+        assert_eq!(buffer.cursor_abs, 6);
+
+        buffer.update_selection(true);
+        buffer.move_cursor_to(12);
+        assert!(buffer.delete_selection());
+        assert_eq!(buffer.get_text(), "hello,");
+        assert_eq!(buffer.cursor_abs, 6);
+        assert!(buffer.is_dirty());
+    }
+}
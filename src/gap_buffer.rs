@@ -0,0 +1,208 @@
+use std::iter::{Chain, Rev};
+use std::ops::{Index, Range};
+use std::slice;
+
+pub type Iter<'a, T> = Chain<slice::Iter<'a, T>, Rev<slice::Iter<'a, T>>>;
+
+/// A sequence that is cheap to edit around a single moving position.
+/// Elements before the gap live in `front`, elements after it are stored in reverse in `back`,
+/// so inserting or removing next to the gap never shifts the rest of the buffer.
+#[derive(Debug, Clone)]
+pub struct GapBuffer<T> {
+    front: Vec<T>,
+    back: Vec<T>,
+}
+
+impl<T> Default for GapBuffer<T> {
+    fn default() -> Self {
+        GapBuffer {
+            front: Vec::new(),
+            back: Vec::new(),
+        }
+    }
+}
+
+impl<T> GapBuffer<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.front.len() + self.back.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Move the gap so that it starts right before `pos`
+    fn move_gap(&mut self, pos: usize) {
+        while self.front.len() > pos {
+            self.back.push(self.front.pop().unwrap());
+        }
+        while self.front.len() < pos {
+            match self.back.pop() {
+                Some(elem) => self.front.push(elem),
+                None => break,
+            }
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx < self.front.len() {
+            self.front.get(idx)
+        } else {
+            let back_idx = idx - self.front.len();
+            if back_idx < self.back.len() {
+                self.back.get(self.back.len() - 1 - back_idx)
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, pos: usize, elem: T) {
+        assert!(pos <= self.len(), "insertion index {pos} out of bounds");
+        self.move_gap(pos);
+        self.front.push(elem);
+    }
+
+    pub fn insert_many<I: IntoIterator<Item = T>>(&mut self, pos: usize, elems: I) {
+        assert!(pos <= self.len(), "insertion index {pos} out of bounds");
+        self.move_gap(pos);
+        self.front.extend(elems);
+    }
+
+    pub fn remove(&mut self, pos: usize) -> T {
+        assert!(pos < self.len(), "removal index {pos} out of bounds");
+        self.move_gap(pos);
+        self.back.pop().unwrap()
+    }
+
+    /// Remove all elements in `range`, returning them in order
+    pub fn remove_range(&mut self, range: Range<usize>) -> Vec<T> {
+        assert!(range.end <= self.len(), "removal range out of bounds");
+        self.move_gap(range.start);
+        let at = self.back.len() - range.len();
+        let mut removed = self.back.split_off(at);
+        removed.reverse();
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.front.clear();
+        self.back.clear();
+    }
+
+    /// Iterate over the elements in `range`
+    pub fn range(&self, range: Range<usize>) -> Iter<'_, T> {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "range out of bounds"
+        );
+        let split = self.front.len();
+        let front = &self.front[range.start.min(split)..range.end.min(split)];
+        let back_start = range.start.saturating_sub(split);
+        let back_end = range.end.saturating_sub(split);
+        let back = &self.back[self.back.len() - back_end..self.back.len() - back_start];
+        front.iter().chain(back.iter().rev())
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.range(0..self.len())
+    }
+}
+
+impl<T> From<Vec<T>> for GapBuffer<T> {
+    fn from(front: Vec<T>) -> Self {
+        GapBuffer {
+            front,
+            back: Vec::new(),
+        }
+    }
+}
+
+impl<T> Index<usize> for GapBuffer<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        self.get(idx)
+            .unwrap_or_else(|| panic!("index {idx} out of bounds"))
+    }
+}
+
+impl<'a, T> IntoIterator for &'a GapBuffer<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_100k_characters_keeps_them_in_order() {
+        let mut buffer: GapBuffer<u32> = (0..10).collect::<Vec<_>>().into();
+        // type in the middle of the text, like a cursor would
+        for n in 0..100_000 {
+            buffer.insert(5 + n as usize, 10 + n);
+        }
+        assert_eq!(buffer.len(), 100_010);
+        assert_eq!(buffer[4], 4);
+        assert_eq!(buffer[5], 10);
+        assert_eq!(buffer[100_004], 100_009);
+        assert_eq!(buffer[100_005], 5);
+        assert_eq!(buffer.iter().filter(|&&n| n >= 10).count(), 100_000);
+    }
+
+    #[test]
+    fn random_edits_match_a_string() {
+        // xorshift, so the sequence is the same on every run
+        let mut state = 0x2545_f491_u32;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize % bound.max(1)
+        };
+        let mut buffer = GapBuffer::new();
+        let mut expected: Vec<char> = Vec::new();
+        for _ in 0..5_000 {
+            let len = expected.len();
+            match next(4) {
+                0 | 1 => {
+                    let pos = next(len + 1);
+                    let ch = char::from(b'a' + next(26) as u8);
+                    buffer.insert(pos, ch);
+                    expected.insert(pos, ch);
+                }
+                2 if len > 0 => {
+                    let pos = next(len);
+                    assert_eq!(buffer.remove(pos), expected.remove(pos));
+                }
+                _ if len > 0 => {
+                    let start = next(len);
+                    let end = start + next((len - start).min(8) + 1);
+                    let removed = buffer.remove_range(start..end);
+                    assert_eq!(removed, expected.drain(start..end).collect::<Vec<_>>());
+                }
+                _ => {}
+            }
+            assert_eq!(buffer.len(), expected.len());
+            let start = next(expected.len() + 1);
+            let end = start + next(expected.len() - start + 1);
+            assert!(buffer.range(start..end).eq(&expected[start..end]));
+        }
+        assert_eq!(
+            buffer.iter().collect::<String>(),
+            expected.iter().collect::<String>()
+        );
+    }
+}
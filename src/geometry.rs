@@ -0,0 +1,209 @@
+use std::os::raw::c_int;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, RenderTarget, Texture};
+use sdl2::sys;
+
+use crate::res_man::FontChar;
+
+/// Layout of `SDL_Vertex`, the rust bindings of SDL do not wrap it yet
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 2],
+    /// Red, green, blue and alpha, multiplied with the texture
+    pub color: [u8; 4],
+    /// Normalized texture coordinates
+    pub tex_coord: [f32; 2],
+}
+
+extern "C" {
+    fn SDL_RenderGeometry(
+        renderer: *mut sys::SDL_Renderer,
+        texture: *mut sys::SDL_Texture,
+        vertices: *const Vertex,
+        num_vertices: c_int,
+        indices: *const c_int,
+        num_indices: c_int,
+    ) -> c_int;
+}
+
+/// Glyph quads that are drawn from the same atlas in a single draw call
+#[derive(Debug, Clone, Default)]
+pub struct GlyphBatch {
+    pub atlas: usize,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<c_int>,
+}
+
+impl GlyphBatch {
+    pub fn new(atlas: usize) -> Self {
+        GlyphBatch {
+            atlas,
+            ..Default::default()
+        }
+    }
+
+    /// Add two triangles drawing `src` of an atlas with the given size to `dst`
+    pub fn push_quad(&mut self, src: Rect, dst: Rect, atlas_size: (u32, u32), color: Color) {
+        let (atlas_width, atlas_height) = (atlas_size.0.max(1) as f32, atlas_size.1.max(1) as f32);
+        let (left, top) = (dst.left() as f32, dst.top() as f32);
+        let (right, bottom) = (dst.right() as f32, dst.bottom() as f32);
+        let (u0, v0) = (
+            src.left() as f32 / atlas_width,
+            src.top() as f32 / atlas_height,
+        );
+        let (u1, v1) = (
+            src.right() as f32 / atlas_width,
+            src.bottom() as f32 / atlas_height,
+        );
+        let color = [color.r, color.g, color.b, color.a];
+        let first = self.vertices.len() as c_int;
+        self.vertices.extend_from_slice(&[
+            Vertex {
+                position: [left, top],
+                color,
+                tex_coord: [u0, v0],
+            },
+            Vertex {
+                position: [right, top],
+                color,
+                tex_coord: [u1, v0],
+            },
+            Vertex {
+                position: [right, bottom],
+                color,
+                tex_coord: [u1, v1],
+            },
+            Vertex {
+                position: [left, bottom],
+                color,
+                tex_coord: [u0, v1],
+            },
+        ]);
+        self.indices
+            .extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    /// Draw all quads with the atlas texture, which should be the one of `self.atlas`
+    pub fn render<T: RenderTarget>(
+        &self,
+        canvas: &mut Canvas<T>,
+        atlas: &Texture<'_>,
+    ) -> Result<(), String> {
+        if self.indices.is_empty() {
+            return Ok(());
+        }
+        // SAFETY: both pointers come from live wrappers and the slices outlive the call
+        let ret = unsafe {
+            SDL_RenderGeometry(
+                canvas.raw(),
+                atlas.raw(),
+                self.vertices.as_ptr(),
+                self.vertices.len() as c_int,
+                self.indices.as_ptr(),
+                self.indices.len() as c_int,
+            )
+        };
+        if ret != 0 {
+            return Err(sdl2::get_error());
+        }
+        Ok(())
+    }
+}
+
+/// Group consecutive glyphs from the same atlas into batches, each glyph tinted with its color.
+/// `atlas_size` returns the size of an atlas texture, glyphs of unknown atlases are skipped.
+pub fn batch_glyphs<'a, I, F>(glyphs: I, mut atlas_size: F) -> Vec<GlyphBatch>
+where
+    I: IntoIterator<Item = (&'a FontChar, Rect, Color)>,
+    F: FnMut(usize) -> Option<(u32, u32)>,
+{
+    let mut batches: Vec<GlyphBatch> = Vec::new();
+    let mut size = (0, 0);
+    for (fch, dst, color) in glyphs {
+        if batches.last().map(|batch| batch.atlas) != Some(fch.atlas) {
+            let Some(new_size) = atlas_size(fch.atlas) else {
+                continue;
+            };
+            size = new_size;
+            batches.push(GlyphBatch::new(fch.atlas));
+        }
+        if let Some(batch) = batches.last_mut() {
+            batch.push_quad(fch.bbox, dst, size, color);
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATLAS: usize = 7;
+    const OTHER_ATLAS: usize = 8;
+
+    fn glyph(ch: char, bbox: Rect, atlas: usize) -> FontChar {
+        FontChar::new(ch, bbox, bbox.width(), 0, 0, 0, atlas)
+    }
+
+    #[test]
+    fn quads_map_the_glyph_bbox_onto_the_destination() {
+        let a = glyph('a', Rect::new(0, 0, 8, 10), ATLAS);
+        let b = glyph('b', Rect::new(8, 10, 8, 10), ATLAS);
+        let red = Color::RGBA(255, 0, 0, 255);
+        let batches = batch_glyphs(
+            [
+                (&a, Rect::new(100, 50, 8, 10), red),
+                (&b, Rect::new(108, 50, 8, 10), Color::WHITE),
+            ],
+            |_| Some((16, 20)),
+        );
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.atlas, ATLAS);
+        assert_eq!(batch.vertices.len(), 8);
+        assert_eq!(batch.indices, [0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]);
+
+        let positions: Vec<[f32; 2]> = batch.vertices.iter().map(|v| v.position).collect();
+        assert_eq!(
+            positions[..4],
+            [[100.0, 50.0], [108.0, 50.0], [108.0, 60.0], [100.0, 60.0]]
+        );
+        let tex_coords: Vec<[f32; 2]> = batch.vertices.iter().map(|v| v.tex_coord).collect();
+        assert_eq!(
+            tex_coords[..4],
+            [[0.0, 0.0], [0.5, 0.0], [0.5, 0.5], [0.0, 0.5]]
+        );
+        assert_eq!(
+            tex_coords[4..],
+            [[0.5, 0.5], [1.0, 0.5], [1.0, 1.0], [0.5, 1.0]]
+        );
+        assert!(batch.vertices[..4]
+            .iter()
+            .all(|v| v.color == [255, 0, 0, 255]));
+        assert!(batch.vertices[4..].iter().all(|v| v.color == [255; 4]));
+    }
+
+    #[test]
+    fn switching_atlases_starts_a_new_batch() {
+        let a = glyph('a', Rect::new(0, 0, 8, 10), ATLAS);
+        let b = glyph('b', Rect::new(0, 0, 8, 10), OTHER_ATLAS);
+        let unknown = glyph('?', Rect::new(0, 0, 8, 10), 99);
+        let dst = Rect::new(0, 0, 8, 10);
+        let batches = batch_glyphs(
+            [
+                (&a, dst, Color::WHITE),
+                (&b, dst, Color::WHITE),
+                (&unknown, dst, Color::WHITE),
+                (&a, dst, Color::WHITE),
+            ],
+            |atlas| (atlas != 99).then_some((16, 16)),
+        );
+        let atlases: Vec<usize> = batches.iter().map(|batch| batch.atlas).collect();
+        assert_eq!(atlases, [ATLAS, OTHER_ATLAS, ATLAS]);
+        // glyphs of an unknown atlas are skipped
+        assert!(batches.iter().all(|batch| batch.vertices.len() == 4));
+    }
+}
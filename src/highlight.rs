@@ -0,0 +1,268 @@
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+
+use sdl2::pixels::Color;
+
+use crate::edit_buffer::BRACKET_PAIRS;
+
+/// Turns text into colored byte ranges, for syntax highlighting.
+/// Ranges are byte offsets into `text`, later ones win where they overlap.
+pub trait Highlighter {
+    fn highlight(&self, text: &str) -> Vec<(Range<usize>, Color)>;
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+/// Keywords, literals and comments of Rust, without any understanding of the grammar
+#[derive(Debug, Clone)]
+pub struct RustHighlighter {
+    pub keyword: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+}
+
+impl Default for RustHighlighter {
+    fn default() -> Self {
+        RustHighlighter {
+            keyword: Color::RGB(200, 120, 220),
+            string: Color::RGB(120, 180, 90),
+            number: Color::RGB(220, 150, 70),
+            comment: Color::RGB(128, 128, 128),
+        }
+    }
+}
+
+impl Highlighter for RustHighlighter {
+    fn highlight(&self, text: &str) -> Vec<(Range<usize>, Color)> {
+        let bytes = text.as_bytes();
+        let mut spans = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let start = pos;
+            match bytes[pos] {
+                b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+                    pos = text[pos..].find('\n').map_or(bytes.len(), |end| pos + end);
+                    spans.push((start..pos, self.comment));
+                }
+                b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                    pos = text[pos + 2..]
+                        .find("*/")
+                        .map_or(bytes.len(), |end| pos + 2 + end + 2);
+                    spans.push((start..pos, self.comment));
+                }
+                b'"' => {
+                    pos += 1;
+                    while pos < bytes.len() && bytes[pos] != b'"' {
+                        // an escape never ends the string
+                        pos += if bytes[pos] == b'\\' { 2 } else { 1 };
+                    }
+                    pos = (pos + 1).min(bytes.len());
+                    spans.push((start..pos, self.string));
+                }
+                b'0'..=b'9' => {
+                    while pos < bytes.len()
+                        && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_')
+                    {
+                        pos += 1;
+                    }
+                    spans.push((start..pos, self.number));
+                }
+                ch if ch.is_ascii_alphabetic() || ch == b'_' => {
+                    while pos < bytes.len()
+                        && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_')
+                    {
+                        pos += 1;
+                    }
+                    if RUST_KEYWORDS.contains(&&text[start..pos]) {
+                        spans.push((start..pos, self.keyword));
+                    }
+                }
+                // skip whole characters, so multi byte ones are never split
+                _ => pos += text[pos..].chars().next().map_or(1, char::len_utf8),
+            }
+        }
+        spans
+    }
+}
+
+/// Colors brackets by how deeply they are nested, cycling through a palette
+#[derive(Debug, Clone)]
+pub struct RainbowBrackets {
+    pub palette: Vec<Color>,
+    /// Color of brackets without a partner
+    pub unmatched: Color,
+}
+
+impl Default for RainbowBrackets {
+    fn default() -> Self {
+        RainbowBrackets {
+            palette: vec![
+                Color::RGB(230, 190, 60),
+                Color::RGB(200, 110, 220),
+                Color::RGB(80, 160, 240),
+            ],
+            unmatched: Color::RGB(230, 50, 50),
+        }
+    }
+}
+
+impl Highlighter for RainbowBrackets {
+    fn highlight(&self, text: &str) -> Vec<(Range<usize>, Color)> {
+        if self.palette.is_empty() {
+            return Vec::new();
+        }
+        let mut spans: Vec<(Range<usize>, Color)> = Vec::new();
+        // closing bracket still expected and the span of its opening one
+        let mut open: Vec<(char, usize)> = Vec::new();
+        for (pos, ch) in text.char_indices() {
+            let range = pos..pos + ch.len_utf8();
+            if let Some(&(_, close)) = BRACKET_PAIRS.iter().find(|(opening, _)| *opening == ch) {
+                let color = self.palette[open.len() % self.palette.len()];
+                open.push((close, spans.len()));
+                spans.push((range, color));
+            } else if BRACKET_PAIRS.iter().any(|(_, close)| *close == ch) {
+                if open.last().map(|(close, _)| *close) == Some(ch) {
+                    open.pop();
+                    spans.push((range, self.palette[open.len() % self.palette.len()]));
+                } else {
+                    spans.push((range, self.unmatched));
+                }
+            }
+        }
+        for (_, span) in open {
+            spans[span].1 = self.unmatched;
+        }
+        spans
+    }
+}
+
+/// Several highlighters applied in order, so later ones win where they overlap
+pub struct Layered(pub Vec<Rc<dyn Highlighter>>);
+
+impl Highlighter for Layered {
+    fn highlight(&self, text: &str) -> Vec<(Range<usize>, Color)> {
+        self.0
+            .iter()
+            .flat_map(|highlighter| highlighter.highlight(text))
+            .collect()
+    }
+}
+
+/// The built-in highlighter for the language of a file, judged by its extension,
+/// with rainbow brackets on top if they are enabled
+pub fn highlighter_for(path: Option<&Path>, rainbow_brackets: bool) -> Option<Rc<dyn Highlighter>> {
+    let language: Option<Rc<dyn Highlighter>> =
+        match path.and_then(Path::extension).and_then(|ext| ext.to_str()) {
+            Some("rs") => Some(Rc::new(RustHighlighter::default())),
+            _ => None,
+        };
+    let rainbow: Option<Rc<dyn Highlighter>> =
+        rainbow_brackets.then(|| Rc::new(RainbowBrackets::default()) as Rc<dyn Highlighter>);
+    match (language, rainbow) {
+        (Some(language), Some(rainbow)) => Some(Rc::new(Layered(vec![language, rainbow]))),
+        (language, rainbow) => language.or(rainbow),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of every span with the given color
+    fn colored<'a>(text: &'a str, spans: &[(Range<usize>, Color)], color: Color) -> Vec<&'a str> {
+        spans
+            .iter()
+            .filter(|(_, span_color)| *span_color == color)
+            .map(|(range, _)| &text[range.clone()])
+            .collect()
+    }
+
+    #[test]
+    fn keywords_are_marked_but_not_identifiers_containing_them() {
+        let rust = RustHighlighter::default();
+        let text = "pub fn format(self) -> Self { let iffy = 42; }";
+        let spans = rust.highlight(text);
+        assert_eq!(
+            colored(text, &spans, rust.keyword),
+            ["pub", "fn", "self", "Self", "let"]
+        );
+        assert_eq!(colored(text, &spans, rust.number), ["42"]);
+    }
+
+    #[test]
+    fn keywords_in_strings_and_comments_are_not_marked() {
+        let rust = RustHighlighter::default();
+        let text = "let s = \"fn \\\" in\"; // if else\n/* match\nloop */ mut";
+        let spans = rust.highlight(text);
+        assert_eq!(colored(text, &spans, rust.keyword), ["let", "mut"]);
+        assert_eq!(colored(text, &spans, rust.string), ["\"fn \\\" in\""]);
+        assert_eq!(
+            colored(text, &spans, rust.comment),
+            ["// if else", "/* match\nloop */"]
+        );
+        // an unterminated string or comment runs to the end of the text
+        let text = "\"fn while";
+        assert_eq!(rust.highlight(text), [(0..text.len(), rust.string)]);
+    }
+
+    #[test]
+    fn brackets_are_colored_by_depth() {
+        let rainbow = RainbowBrackets::default();
+        let [outer, middle, inner] = rainbow.palette[..] else {
+            panic!("the default palette has three colors");
+        };
+        let colors: Vec<Color> = rainbow
+            .highlight("([{x}])")
+            .into_iter()
+            .map(|(_, color)| color)
+            .collect();
+        assert_eq!(colors, [outer, middle, inner, inner, middle, outer]);
+        // ranges are byte offsets, also after multi byte characters
+        assert_eq!(rainbow.highlight("é()"), [(2..3, outer), (3..4, outer)]);
+    }
+
+    #[test]
+    fn unmatched_brackets_get_their_own_color() {
+        let rainbow = RainbowBrackets::default();
+        let outer = rainbow.palette[0];
+        let unmatched = rainbow.unmatched;
+        assert_eq!(
+            rainbow.highlight(")(]"),
+            [(0..1, unmatched), (1..2, unmatched), (2..3, unmatched)]
+        );
+        // a bracket left open does not disturb the pairs after it
+        assert_eq!(
+            rainbow.highlight("{()"),
+            [
+                (0..1, unmatched),
+                (1..2, rainbow.palette[1]),
+                (2..3, rainbow.palette[1])
+            ]
+        );
+        assert_eq!(rainbow.highlight("())")[2], (2..3, unmatched));
+        assert_eq!(rainbow.highlight("())")[0], (0..1, outer));
+    }
+
+    #[test]
+    fn depth_wraps_around_the_palette() {
+        let rainbow = RainbowBrackets::default();
+        let spans = rainbow.highlight("(((())))");
+        let depths: Vec<usize> = spans
+            .iter()
+            .map(|(_, color)| rainbow.palette.iter().position(|c| c == color).unwrap())
+            .collect();
+        assert_eq!(depths, [0, 1, 2, 0, 0, 2, 1, 0]);
+        let empty = RainbowBrackets {
+            palette: Vec::new(),
+            ..RainbowBrackets::default()
+        };
+        assert!(empty.highlight("(())").is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+
+/// Repeats a held navigation or deletion key at a fixed rate,
+/// instead of relying on the key repeat settings of the OS.
+#[derive(Debug, Clone)]
+pub struct KeyRepeat {
+    delay: Duration,
+    interval: Duration,
+    held: Option<(Keycode, Mod)>,
+    next: Option<Instant>,
+}
+
+impl KeyRepeat {
+    /// Repeat a key `delay` after it was pressed, then every `interval`
+    pub fn new(delay: Duration, interval: Duration) -> Self {
+        KeyRepeat {
+            delay,
+            interval: interval.max(Duration::from_millis(1)),
+            held: None,
+            next: None,
+        }
+    }
+
+    /// Keys that are repeated while held
+    pub fn repeats(code: Keycode) -> bool {
+        matches!(
+            code,
+            Keycode::Left
+                | Keycode::Right
+                | Keycode::Up
+                | Keycode::Down
+                | Keycode::Home
+                | Keycode::End
+                | Keycode::PageUp
+                | Keycode::PageDown
+                | Keycode::Backspace
+                | Keycode::Delete
+        )
+    }
+
+    pub fn press(&mut self, code: Keycode, keymod: Mod, now: Instant) {
+        if Self::repeats(code) {
+            self.held = Some((code, keymod));
+            self.next = Some(now + self.delay);
+        }
+    }
+
+    pub fn release(&mut self, code: Keycode) {
+        if matches!(self.held, Some((held, _)) if held == code) {
+            self.held = None;
+            self.next = None;
+        }
+    }
+
+    /// Keep track of presses and releases, and swallow the repeats of the OS for held keys
+    pub fn filter(&mut self, event: Event, now: Instant) -> Option<Event> {
+        match event {
+            Event::KeyDown {
+                keycode: Some(code),
+                repeat: true,
+                ..
+            } if Self::repeats(code) => return None,
+            Event::KeyDown {
+                keycode: Some(code),
+                keymod,
+                repeat: false,
+                ..
+            } => self.press(code, keymod, now),
+            Event::KeyUp {
+                keycode: Some(code),
+                ..
+            } => self.release(code),
+            _ => {}
+        }
+        Some(event)
+    }
+
+    /// Time until the next repeat is due, if a key is held
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.next.map(|next| next.saturating_duration_since(now))
+    }
+
+    /// The key press to repeat, if one is due at `now`
+    pub fn poll(&mut self, now: Instant) -> Option<Event> {
+        let next = self.next?;
+        if now < next {
+            return None;
+        }
+        // skip the repeats that were missed instead of firing them all at once
+        let mut due = next + self.interval;
+        while due <= now {
+            due += self.interval;
+        }
+        self.next = Some(due);
+        let (code, keymod) = self.held?;
+        Some(Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(code),
+            scancode: None,
+            keymod,
+            repeat: true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(300);
+    const INTERVAL: Duration = Duration::from_millis(50);
+
+    fn repeated_key(event: Option<Event>) -> Option<Keycode> {
+        match event {
+            Some(Event::KeyDown {
+                keycode,
+                repeat: true,
+                ..
+            }) => keycode,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn nothing_repeats_before_the_delay() {
+        let start = Instant::now();
+        let mut key_repeat = KeyRepeat::new(DELAY, INTERVAL);
+        key_repeat.press(Keycode::Left, Mod::NOMOD, start);
+        assert!(key_repeat.poll(start).is_none());
+        assert!(key_repeat.poll(start + DELAY - INTERVAL).is_none());
+        assert_eq!(key_repeat.timeout(start), Some(DELAY));
+        assert_eq!(
+            repeated_key(key_repeat.poll(start + DELAY)),
+            Some(Keycode::Left)
+        );
+    }
+
+    #[test]
+    fn held_key_repeats_once_per_interval() {
+        let start = Instant::now();
+        let mut key_repeat = KeyRepeat::new(DELAY, INTERVAL);
+        key_repeat.press(Keycode::Backspace, Mod::NOMOD, start);
+        let first = start + DELAY;
+        assert!(key_repeat.poll(first).is_some());
+        // polling again within the interval does not repeat
+        assert!(key_repeat.poll(first).is_none());
+        assert!(key_repeat.poll(first + INTERVAL / 2).is_none());
+        assert_eq!(key_repeat.timeout(first), Some(INTERVAL));
+        assert!(key_repeat.poll(first + INTERVAL).is_some());
+        assert!(key_repeat.poll(first + INTERVAL * 2).is_some());
+
+        key_repeat.release(Keycode::Backspace);
+        assert!(key_repeat.poll(first + INTERVAL * 3).is_none());
+        assert_eq!(key_repeat.timeout(first), None);
+    }
+
+    #[test]
+    fn missed_intervals_are_skipped() {
+        let start = Instant::now();
+        let mut key_repeat = KeyRepeat::new(DELAY, INTERVAL);
+        key_repeat.press(Keycode::Down, Mod::NOMOD, start);
+        // the loop was busy for several intervals, that is one repeat and not a burst
+        let late = start + DELAY + INTERVAL * 5 + INTERVAL / 2;
+        assert!(key_repeat.poll(late).is_some());
+        assert!(key_repeat.poll(late).is_none());
+        assert_eq!(key_repeat.timeout(late), Some(INTERVAL / 2));
+    }
+
+    #[test]
+    fn only_navigation_and_deletion_keys_repeat() {
+        let start = Instant::now();
+        let mut key_repeat = KeyRepeat::new(DELAY, INTERVAL);
+        key_repeat.press(Keycode::A, Mod::NOMOD, start);
+        assert_eq!(key_repeat.timeout(start), None);
+        assert!(key_repeat.poll(start + DELAY).is_none());
+
+        // the repeats of the OS are swallowed for the keys we repeat ourselves
+        let os_repeat = |keycode| Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: Mod::NOMOD,
+            repeat: true,
+        };
+        assert!(key_repeat.filter(os_repeat(Keycode::Left), start).is_none());
+        assert!(key_repeat.filter(os_repeat(Keycode::A), start).is_some());
+    }
+}
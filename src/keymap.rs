@@ -0,0 +1,173 @@
+use sdl2::keyboard::{Keycode, Mod};
+
+/// What a key press outside of the find and goto prompts does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    SwitchFont,
+    CycleAtlasDebug,
+    ToggleDebugOverlay,
+    Screenshot,
+    NextBuffer,
+    PrevBuffer,
+    ToggleSplit,
+    FocusOther,
+    CloseBuffer,
+    Find,
+    FindNext,
+    FindPrev,
+    GotoLine,
+    Save,
+    DuplicateLine,
+    ToggleComment,
+    ToggleOverwrite,
+    SelectAll,
+    Copy,
+    Paste,
+    Undo,
+    Redo,
+    ZoomIn,
+    ZoomOut,
+    Newline,
+    /// Indent a selection spanning lines, insert a tab otherwise
+    Tab,
+    Dedent,
+    Backspace,
+    DeleteWordBack,
+    Delete,
+    MoveLineUp,
+    MoveLineDown,
+    // movements extend the selection if `select` is set
+    CursorForward {
+        select: bool,
+    },
+    CursorBack {
+        select: bool,
+    },
+    WordForward {
+        select: bool,
+    },
+    WordBack {
+        select: bool,
+    },
+    CursorUp {
+        select: bool,
+    },
+    CursorDown {
+        select: bool,
+    },
+    Home {
+        select: bool,
+    },
+    End {
+        select: bool,
+    },
+}
+
+/// The action bound to `code` pressed with the modifiers in `keymod`, if any
+pub fn key_action(code: Keycode, keymod: Mod) -> Option<KeyAction> {
+    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+    let alt = keymod.intersects(Mod::LALTMOD | Mod::RALTMOD);
+    let select = shift;
+    let action = match code {
+        Keycode::F if alt => KeyAction::SwitchFont,
+        Keycode::F if ctrl => KeyAction::Find,
+        Keycode::F11 => KeyAction::CycleAtlasDebug,
+        Keycode::F12 if ctrl => KeyAction::ToggleDebugOverlay,
+        Keycode::F12 => KeyAction::Screenshot,
+        Keycode::Tab if ctrl && shift => KeyAction::PrevBuffer,
+        Keycode::Tab if ctrl => KeyAction::NextBuffer,
+        Keycode::Backslash if ctrl => KeyAction::ToggleSplit,
+        Keycode::F6 => KeyAction::FocusOther,
+        Keycode::W if ctrl => KeyAction::CloseBuffer,
+        Keycode::F3 if shift => KeyAction::FindPrev,
+        Keycode::F3 => KeyAction::FindNext,
+        Keycode::G if ctrl => KeyAction::GotoLine,
+        Keycode::S if ctrl => KeyAction::Save,
+        Keycode::D if ctrl => KeyAction::DuplicateLine,
+        Keycode::Slash if ctrl => KeyAction::ToggleComment,
+        Keycode::Insert => KeyAction::ToggleOverwrite,
+        Keycode::A if ctrl => KeyAction::SelectAll,
+        Keycode::C if ctrl => KeyAction::Copy,
+        Keycode::V if ctrl => KeyAction::Paste,
+        Keycode::Z if ctrl => KeyAction::Undo,
+        Keycode::Y if ctrl => KeyAction::Redo,
+        Keycode::Equals | Keycode::Plus | Keycode::KpPlus if ctrl => KeyAction::ZoomIn,
+        Keycode::Minus | Keycode::KpMinus if ctrl => KeyAction::ZoomOut,
+        Keycode::Return | Keycode::Return2 | Keycode::KpEnter => KeyAction::Newline,
+        Keycode::Tab if shift => KeyAction::Dedent,
+        Keycode::Tab => KeyAction::Tab,
+        Keycode::Backspace if ctrl => KeyAction::DeleteWordBack,
+        Keycode::Backspace => KeyAction::Backspace,
+        Keycode::Delete => KeyAction::Delete,
+        Keycode::Right if ctrl => KeyAction::WordForward { select },
+        Keycode::Left if ctrl => KeyAction::WordBack { select },
+        Keycode::Right => KeyAction::CursorForward { select },
+        Keycode::Left => KeyAction::CursorBack { select },
+        Keycode::Home => KeyAction::Home { select },
+        Keycode::End => KeyAction::End { select },
+        Keycode::Up if alt => KeyAction::MoveLineUp,
+        Keycode::Down if alt => KeyAction::MoveLineDown,
+        Keycode::Up => KeyAction::CursorUp { select },
+        Keycode::Down => KeyAction::CursorDown { select },
+        _ => return None,
+    };
+    Some(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ctrl_f_finds_and_alt_f_switches_the_font() {
+        assert_eq!(key_action(Keycode::F, Mod::LCTRLMOD), Some(KeyAction::Find));
+        assert_eq!(key_action(Keycode::F, Mod::RCTRLMOD), Some(KeyAction::Find));
+        assert_eq!(
+            key_action(Keycode::F, Mod::LALTMOD),
+            Some(KeyAction::SwitchFont)
+        );
+        assert_eq!(
+            key_action(Keycode::F, Mod::RALTMOD),
+            Some(KeyAction::SwitchFont)
+        );
+        // a plain F is typed, not bound
+        assert_eq!(key_action(Keycode::F, Mod::NOMOD), None);
+        assert_eq!(key_action(Keycode::F, Mod::LSHIFTMOD), None);
+    }
+
+    #[test]
+    fn shift_extends_the_selection_of_movements() {
+        assert_eq!(
+            key_action(Keycode::Left, Mod::LSHIFTMOD | Mod::LCTRLMOD),
+            Some(KeyAction::WordBack { select: true })
+        );
+        assert_eq!(
+            key_action(Keycode::Down, Mod::NOMOD),
+            Some(KeyAction::CursorDown { select: false })
+        );
+        assert_eq!(
+            key_action(Keycode::Down, Mod::LALTMOD),
+            Some(KeyAction::MoveLineDown)
+        );
+    }
+
+    #[test]
+    fn keypad_enter_is_a_newline_like_return() {
+        for keymod in [Mod::NOMOD, Mod::LSHIFTMOD, Mod::NUMMOD] {
+            let newline = key_action(Keycode::Return, keymod);
+            assert_eq!(newline, Some(KeyAction::Newline));
+            assert_eq!(key_action(Keycode::KpEnter, keymod), newline);
+            assert_eq!(key_action(Keycode::Return2, keymod), newline);
+        }
+        // keypad plus and minus zoom like their main keyboard counterparts
+        assert_eq!(
+            key_action(Keycode::KpPlus, Mod::LCTRLMOD),
+            key_action(Keycode::Equals, Mod::LCTRLMOD)
+        );
+        assert_eq!(
+            key_action(Keycode::KpMinus, Mod::LCTRLMOD),
+            key_action(Keycode::Minus, Mod::LCTRLMOD)
+        );
+    }
+}
@@ -1,16 +1,38 @@
 #![deny(rust_2018_idioms)]
+mod buffer_set;
+mod config;
+mod edit_buffer;
+mod gap_buffer;
+mod geometry;
+mod highlight;
+mod key_repeat;
+mod keymap;
+mod packer;
 mod res_man;
 mod screen_manager;
+#[cfg(feature = "shaping")]
+mod shaping;
+mod theme;
 
-use res_man::{FontChar, FontDef, ResourceLoader, ResourceManager};
+use buffer_set::{BufferSet, SPLIT_GAP};
+use config::Config;
+use key_repeat::KeyRepeat;
+use keymap::{key_action, KeyAction};
+use log::{debug, error, info, warn};
+use theme::Theme;
+
+use packer::ShelfPacker;
+use res_man::{coverage_to_rgba, FontChar, FontDef, FontStyle, ResourceLoader, ResourceManager};
 use sdl2;
 
 use sdl2::event::Event;
 use sdl2::event::EventType;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::mouse::MouseButton;
+use sdl2::mouse::MouseWheelDirection;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 use sdl2::render::Canvas;
 use sdl2::render::Texture;
 use sdl2::render::TextureCreator;
@@ -19,16 +41,27 @@ use sdl2::video::Window;
 use sdl2::video::WindowContext;
 
 use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::screen_manager::Renderable;
+use crate::edit_buffer::LineEnding;
+use crate::screen_manager::{CaretShape, Renderable, TextScreen};
 
 const ANSI_CHAR_RANGE: u32 = 0x80;
+const ANSI_RANGE: Range<u32> = 0..ANSI_CHAR_RANGE;
+const LATIN1_SUPPLEMENT_RANGE: Range<u32> = 0xA0..0x100;
 const FONT_SIZE: u32 = 32;
+const FONT_SIZE_STEP: u32 = 2;
+const FONT_SIZE_MIN: u32 = 8;
 const FONT_SPACING: u32 = 2 * (FONT_SIZE / 64); // scales with font_size
 const ATLAS_MAX_WIDTH: u32 = 16384;
 const ATLAS_MAX_HEIGHT: u32 = 16384;
+/// Cached line textures kept at most, the least recently drawn are rendered anew when needed
+const LINE_TEXTURE_CAPACITY: usize = 256;
+/// Used when the configured font cannot be read, and for glyphs a font lacks
+const BUNDLED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
 
 // type RefTexture<'a> = Rc<RefCell<Texture<'a>>>;
 
@@ -37,28 +70,22 @@ impl Renderable for FontChar {
         let dst = target
             .loaded_font
             .get_char_aligned_rect(x as i32, y as i32, self);
-        target
-            .canvas
-            .copy(
-                &target
-                    .texture_manager
-                    .get(&usize::MAX)
-                    .unwrap_or_else(|| {
-                        panic!("Failed to get texture atlas!");
-                    })
-                    .clone()
-                    .borrow(),
-                self.bbox,
-                dst,
+        let atlas = target
+            .texture_manager
+            .get(&self.atlas)
+            .ok_or_else(|| format!("No texture atlas {} for char {:?}", self.atlas, self.ch))?;
+        let mut atlas = atlas.borrow_mut();
+        let fg = target.theme.foreground;
+        atlas.set_color_mod(fg.r, fg.g, fg.b);
+        atlas.set_blend_mode(BlendMode::Blend);
+        target.canvas.copy(&atlas, self.bbox, dst).map(|_| {
+            Rect::new(
+                x as i32,
+                y as i32,
+                self._ax,
+                target.loaded_font.glyph_height,
             )
-            .map(|_| {
-                Rect::new(
-                    x as i32,
-                    y as i32,
-                    self._ax,
-                    target.loaded_font.glyph_height,
-                )
-            })
+        })
     }
 }
 
@@ -71,209 +98,902 @@ impl<'a, T> ResourceLoader<'a, Texture<'a>> for TextureCreator<T> {
             Err(err) => Err(format!("Failed to load texture from surface: {err}")),
         }
     }
-    fn create(&'a self, w: u32, h: u32) -> Texture<'a> {
-        self.create_texture_target(PixelFormatEnum::RGB24, w, h)
-            .unwrap()
+    fn create(&'a self, w: u32, h: u32) -> Result<Texture<'a>, String> {
+        self.create_texture_target(PixelFormatEnum::RGBA8888, w, h)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Union of the screen areas that changed since the last frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DirtyRegion {
+    bounds: Option<Rect>,
+}
+
+impl DirtyRegion {
+    pub fn mark(&mut self, rect: Rect) {
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => bounds.union(rect),
+            None => rect,
+        });
+    }
+
+    #[inline]
+    pub fn take(&mut self) -> Option<Rect> {
+        self.bounds.take()
     }
 }
 
 pub struct Renderer<'a> {
     canvas: Canvas<Window>,
     texture_manager: TextureManager<'a, WindowContext>,
+    /// Rendered lines of the screens with a line cache
+    line_textures: TextureManager<'a, WindowContext>,
     loaded_font: FontDef,
+    font_path: String,
+    /// Contents of the loaded font file, kept to rebuild the atlas at other sizes
+    font_data: Rc<Vec<u8>>,
+    font_size: u32,
+    char_ranges: Vec<Range<u32>>,
+    style_paths: HashMap<FontStyle, String>,
+    fallback_paths: Vec<String>,
+    theme: Theme,
+    dirty: DirtyRegion,
     width: u32,
     height: u32,
+    atlas_max_width: u32,
+    atlas_max_height: u32,
+    /// Draw glyph boxes, baselines and glyph origins on top of the text
+    debug_overlay: bool,
+    /// Atlas or glyph page shown over the whole window, if any
+    atlas_debug: Option<usize>,
     _cursor_enabled: bool,
 }
 
-impl<'a> Renderer<'a> {
+/// Settings for a `Renderer`, its font atlas is built along with it if a font is given
+pub struct RendererBuilder<'a> {
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    width: u32,
+    height: u32,
+    font_path: Option<String>,
+    font_size: u32,
+    char_ranges: Vec<Range<u32>>,
+    fallback_paths: Vec<String>,
+    atlas_max_width: u32,
+    atlas_max_height: u32,
+    theme: Theme,
+}
+
+impl<'a> RendererBuilder<'a> {
     pub fn new(
         canvas: Canvas<Window>,
         texture_creator: &'a TextureCreator<WindowContext>,
         width: u32,
         height: u32,
     ) -> Self {
-        Renderer {
-            canvas: canvas,
-            loaded_font: FontDef::default(),
-            texture_manager: TextureManager::new(&texture_creator),
+        RendererBuilder {
+            canvas,
+            texture_creator,
             width,
             height,
-            _cursor_enabled: false,
+            font_path: None,
+            font_size: FONT_SIZE,
+            char_ranges: vec![ANSI_RANGE],
+            fallback_paths: Vec::new(),
+            atlas_max_width: ATLAS_MAX_WIDTH,
+            atlas_max_height: ATLAS_MAX_HEIGHT,
+            theme: Theme::default(),
         }
     }
 
-    pub fn build_atlas<A: Into<String>>(&mut self, font_path: A, font_size: u32) {
-        use freetype::face::LoadFlag;
-        use freetype::Library;
+    pub fn font_path<A: Into<String>>(mut self, font_path: A) -> Self {
+        self.font_path = Some(font_path.into());
+        self
+    }
+
+    pub fn font_size(mut self, font_size: u32) -> Self {
+        self.font_size = font_size;
+        self
+    }
+
+    pub fn char_ranges(mut self, char_ranges: &[Range<u32>]) -> Self {
+        self.char_ranges = char_ranges.to_vec();
+        self
+    }
+
+    /// Fonts to take glyphs from that the main font lacks, tried in order.
+    /// The bundled font is always tried last.
+    pub fn fallback_fonts(mut self, font_paths: &[String]) -> Self {
+        self.fallback_paths = font_paths.to_vec();
+        self
+    }
+
+    /// Largest texture the font atlas may use
+    pub fn atlas_max_size(mut self, width: u32, height: u32) -> Self {
+        self.atlas_max_width = width;
+        self.atlas_max_height = height;
+        self
+    }
+
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
 
-        // these variables will be used to determine the effective width and height of a character
-        let mut max_ascent: u32 = 0;
-        let mut max_descent: u32 = 0;
-        let mut max_forward: u32 = 0;
-        let mut max_back: u32 = 0;
-        let mut max_width: u32 = 0;
+    pub fn build(self) -> Result<Renderer<'a>, String> {
+        let mut renderer = Renderer {
+            canvas: self.canvas,
+            loaded_font: FontDef::default(),
+            font_path: String::new(),
+            font_data: Rc::default(),
+            font_size: self.font_size,
+            char_ranges: self.char_ranges,
+            style_paths: HashMap::new(),
+            fallback_paths: self.fallback_paths,
+            theme: self.theme,
+            dirty: DirtyRegion::default(),
+            texture_manager: TextureManager::new(self.texture_creator),
+            line_textures: TextureManager::with_capacity(
+                self.texture_creator,
+                LINE_TEXTURE_CAPACITY,
+            ),
+            width: self.width,
+            height: self.height,
+            atlas_max_width: self.atlas_max_width,
+            atlas_max_height: self.atlas_max_height,
+            debug_overlay: false,
+            atlas_debug: None,
+            _cursor_enabled: false,
+        };
+        if let Some(font_path) = self.font_path {
+            if let Err(err) = renderer.rebuild_atlas_from(&font_path) {
+                warn!("Could not load font {font_path}, using the bundled font: {err}");
+                renderer.build_atlas_from_bytes(BUNDLED_FONT, renderer.font_size)?;
+            }
+        }
+        renderer.mark_all_dirty();
+        Ok(renderer)
+    }
+}
 
-        let lib = Library::init()
+impl<'a> Renderer<'a> {
+    #[inline]
+    pub fn new(
+        canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        RendererBuilder::new(canvas, texture_creator, width, height)
+            .build()
             .map_err(|err| {
-                eprintln!("Could not initialize FreeType: {err}");
+                error!("Could not create renderer: {err}");
             })
-            .unwrap();
+            .unwrap()
+    }
 
-        // load first font in ttf file
-        let font_face = lib
-            .new_face(font_path.into(), 0)
-            .map_err(|err| {
-                eprintln!("Could not load font: {err}");
+    #[inline]
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.mark_all_dirty();
+    }
+
+    /// Schedule `rect` to be cleared and redrawn in the next frame
+    #[inline]
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        self.dirty.mark(rect);
+    }
+
+    #[inline]
+    pub fn mark_all_dirty(&mut self) {
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+    }
+
+    /// Area to redraw this frame, if anything changed
+    #[inline]
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+
+    /// Rasterize the requested code points of the font file at `font_path` into texture atlases.
+    /// If no ranges are given, the ones of the previous font are loaded, initially only ANSI.
+    pub fn build_atlas<A: Into<String>>(
+        &mut self,
+        font_path: A,
+        font_size: u32,
+        char_ranges: Option<&[Range<u32>]>,
+    ) -> Result<(), String> {
+        let font_path: String = font_path.into();
+        if let Some(ranges) = char_ranges {
+            self.char_ranges = ranges.to_vec();
+        }
+        let (data, font_path) = read_font_or_bundled(font_path);
+        self.build_atlas_from_data(Rc::new(data), font_size)?;
+        self.font_path = font_path;
+        Ok(())
+    }
+
+    /// Like `build_atlas`, but with a font that is already in memory, e.g. from `include_bytes!`
+    pub fn build_atlas_from_bytes(&mut self, data: &[u8], font_size: u32) -> Result<(), String> {
+        self.build_atlas_from_data(Rc::new(data.to_vec()), font_size)?;
+        self.font_path = String::from("<memory>");
+        Ok(())
+    }
+
+    /// Build the atlases from the font file contents in `data`.
+    /// On error the previous font stays loaded, unless the atlas textures could not be created.
+    fn build_atlas_from_data(&mut self, data: Rc<Vec<u8>>, font_size: u32) -> Result<(), String> {
+        let font_data = Rc::clone(&data);
+        let rasterized = rasterize_font(data, font_size, &self.char_ranges, self.atlas_limits())?;
+
+        let mut atlas_surfaces: Vec<Surface<'_>> =
+            Vec::with_capacity(rasterized.page_heights.len());
+        for page_height in &rasterized.page_heights {
+            atlas_surfaces.push(
+                Surface::new(rasterized.width, *page_height, PixelFormatEnum::RGBA8888)
+                    .map_err(|err| format!("Could not create atlas surface: {err}"))?,
+            );
+        }
+        for (ch, mut rgba, pitch, page, place) in rasterized.bitmaps {
+            // loading and blittering this on the CPU should be plenty fast
+            let mut letter = Surface::from_data(
+                &mut rgba[..],
+                place.width(),
+                place.height(),
+                pitch,
+                PixelFormatEnum::RGBA8888,
+            )
+            .map_err(|err| format!("Could not create surface for char {ch:?}: {err}"))?;
+            // copy the coverage as is instead of blending it onto the empty atlas
+            letter.set_blend_mode(BlendMode::None)?;
+            letter
+                .blit(None, &mut atlas_surfaces[page], place)
+                .map_err(|err| format!("Could not blit to texture atlas: {err}"))?;
+        }
+
+        // drop the atlases and glyph pages of the previous font
+        let old_textures = self.loaded_font.atlas_count() + self.loaded_font.page_count();
+        for idx in 0..old_textures.max(1) {
+            self.texture_manager.remove(&FontDef::atlas_key(idx));
+        }
+        for (atlas_idx, surface) in atlas_surfaces.iter().enumerate() {
+            self.texture_manager
+                .load(FontDef::atlas_key(atlas_idx), surface)
+                .map_err(|err| format!("Could not create texture from surface: {err}"))?;
+        }
+        self.font_data = font_data;
+        self.font_size = font_size;
+        self.loaded_font = rasterized.font;
+        debug!(
+            "Font metrics at {font_size}px: ascent {}, descent {}, line gap {}",
+            self.loaded_font.ascent(),
+            self.loaded_font.descent(),
+            self.loaded_font.line_gap()
+        );
+
+        for (style, style_path) in self.style_paths.clone() {
+            self.load_style_face(style, &style_path);
+        }
+        self.load_fallback_faces();
+        Ok(())
+    }
+
+    /// Set up the fallback chain of the loaded font, ending with the bundled font
+    fn load_fallback_faces(&mut self) {
+        use freetype::Library;
+
+        let Ok(lib) = Library::init().map_err(|err| error!("Could not initialize FreeType: {err}"))
+        else {
+            return;
+        };
+        let faces = self
+            .fallback_paths
+            .iter()
+            .map(|font_path| {
+                lib.new_face(font_path, 0)
+                    .map_err(|err| warn!("Could not load fallback font {font_path}: {err}"))
             })
-            .unwrap();
-        //font_face.set_char_size(40*64, 0, 96, 96).unwrap();
-        font_face
-            .set_pixel_sizes(font_size, 0)
-            .map_err(|err| {
-                eprintln!("Failed to set pixel sizes: {err}");
+            .chain([lib
+                .new_memory_face(BUNDLED_FONT.to_vec(), 0)
+                .map_err(|err| warn!("Could not load the bundled font: {err}"))]);
+        for face in faces.flatten() {
+            match face.set_pixel_sizes(self.font_size, 0) {
+                Ok(()) => self.loaded_font.add_fallback_face(face),
+                Err(err) => error!("Failed to set pixel sizes: {err}"),
+            }
+        }
+        debug!(
+            "Falling back to {} faces for missing glyphs",
+            self.loaded_font.fallback_face_count()
+        );
+    }
+
+    /// Use the font file at `font_path` for text of the given style
+    pub fn add_style<A: Into<String>>(&mut self, style: FontStyle, font_path: A) {
+        let font_path: String = font_path.into();
+        self.load_style_face(style, &font_path);
+        self.style_paths.insert(style, font_path);
+    }
+
+    fn load_style_face(&mut self, style: FontStyle, font_path: &str) {
+        use freetype::Library;
+
+        let face = Library::init()
+            .map_err(|err| error!("Could not initialize FreeType: {err}"))
+            .and_then(|lib| {
+                lib.new_face(font_path, 0)
+                    .map_err(|err| error!("Could not load {style:?} font {font_path}: {err}"))
             })
-            .unwrap();
-        font_face
-            .load_glyph(0, LoadFlag::RENDER)
-            .map_err(|err| eprintln!("Could not load first glyph from font: {err}"))
-            .unwrap();
+            .and_then(|face| {
+                face.set_pixel_sizes(self.font_size, 0)
+                    .map(|_| face)
+                    .map_err(|err| error!("Failed to set pixel sizes: {err}"))
+            });
+        if let Ok(face) = face {
+            self.loaded_font.add_style_face(style, face);
+        }
+    }
+
+    /// Largest atlas texture that is both allowed and supported by the renderer
+    fn atlas_limits(&self) -> (u32, u32) {
+        let info = self.canvas.info();
+        clamp_atlas_limits(
+            (self.atlas_max_width, self.atlas_max_height),
+            (info.max_texture_width, info.max_texture_height),
+        )
+    }
+
+    /// Rebuild the atlas of the current font at a different size
+    pub fn rebuild_atlas(&mut self, font_size: u32) -> Result<(), String> {
+        let data = Rc::clone(&self.font_data);
+        self.build_atlas_from_data(data, font_size)
+    }
+
+    /// Build the atlas of another font, keeping the size and code points of the current one
+    pub fn rebuild_atlas_from<A: Into<String>>(&mut self, font_path: A) -> Result<(), String> {
+        self.build_atlas(font_path, self.font_size, None)
+    }
+
+    /// Write the canvas contents to a PNG file.
+    /// Call this once the frame is drawn but before it is presented,
+    /// SDL leaves the back buffer undefined after a present.
+    pub fn save_screenshot(&self, path: &Path) -> Result<(), String> {
+        let (width, height) = self.canvas.output_size()?;
+        // let SDL convert from the window format, so we do not have to
+        let pixels = self.canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+        let row_len = width as usize * 3;
+        let pitch = pixels.len() / height.max(1) as usize;
+        let mut data = Vec::<u8>::with_capacity(row_len * height as usize);
+        for row in pixels.chunks(pitch) {
+            data.extend_from_slice(&row[..row_len]);
+        }
+        let image = image::RgbImage::from_raw(width, height, data)
+            .ok_or_else(|| String::from("Pixel buffer does not match the canvas size"))?;
+        image
+            .save(path)
+            .map_err(|err| format!("Could not save screenshot to {}: {err}", path.display()))
+    }
+
+    /// Show the next atlas or glyph page in the inspector, closing it after the last one
+    pub fn cycle_atlas_debug(&mut self) {
+        let textures = self.loaded_font.atlas_count() + self.loaded_font.page_count();
+        self.atlas_debug = match self.atlas_debug {
+            None => Some(0),
+            Some(idx) if idx + 1 < textures => Some(idx + 1),
+            Some(_) => None,
+        };
+        self.mark_all_dirty();
+    }
 
-        let mut map: HashMap<usize, Rc<FontChar>> = Default::default();
-        let metrics = font_face
-            .size_metrics()
-            .expect("Could not get font metrics: No value returned.");
-        let atlas_glyph_height = metrics.height as u32 >> 6;
-        let mut _atlas_rows = 0;
-        let mut _atlas_cols = 0;
-        let mut _atlas_width = 0;
-        let glyph_total_width = ANSI_CHAR_RANGE * font_size;
-        if glyph_total_width > ATLAS_MAX_WIDTH {
-            _atlas_rows = (glyph_total_width / ATLAS_MAX_WIDTH) + 1;
-            _atlas_cols = ATLAS_MAX_WIDTH / font_size;
-            _atlas_width = ATLAS_MAX_WIDTH;
-        } else if glyph_total_width % ATLAS_MAX_WIDTH == 0 {
-            _atlas_rows = glyph_total_width / ATLAS_MAX_WIDTH;
-            _atlas_cols = ATLAS_MAX_WIDTH / font_size;
-            _atlas_width = ATLAS_MAX_WIDTH;
-        } else {
-            _atlas_rows = 1;
-            _atlas_cols = ANSI_CHAR_RANGE;
-            _atlas_width = glyph_total_width;
+    /// Draw the atlas selected in the inspector over the window, scaled to fit
+    pub fn draw_atlas_debug(&mut self) -> Result<(), String> {
+        let Some(idx) = self.atlas_debug else {
+            return Ok(());
         };
-        let atlas_height = atlas_glyph_height * _atlas_rows;
+        let atlas = self
+            .texture_manager
+            .get(&FontDef::atlas_key(idx))
+            .ok_or_else(|| format!("Atlas {idx} has no texture"))?;
+        let atlas = atlas.borrow();
+        let query = atlas.query();
+        let window = Rect::new(0, 0, self.width, self.height);
+        self.canvas.set_draw_color(self.theme.background);
+        self.canvas.fill_rect(window)?;
+        self.canvas
+            .copy(&atlas, None, letterbox(query.width, query.height, window))
+    }
 
-        if atlas_height > ATLAS_MAX_HEIGHT {
-            panic!("Texture size exceeded limit of {ATLAS_MAX_WIDTH}x{ATLAS_MAX_HEIGHT}");
+    /// Copy glyphs rasterized since the last frame into their atlas textures
+    pub fn upload_glyphs(&mut self) -> Result<(), String> {
+        for page in self.loaded_font.pending_pages.drain(..) {
+            self.texture_manager
+                .create(page.atlas, page.width, page.height)
+                .map_err(|err| format!("Could not create glyph page {}: {err}", page.atlas))?;
+        }
+        for upload in self.loaded_font.pending_uploads.drain(..) {
+            if let Some(texture) = self.texture_manager.get(&upload.atlas) {
+                texture
+                    .borrow_mut()
+                    .update(upload.dest, &upload.pixels, upload.pitch)
+                    .unwrap_or_else(|err| {
+                        error!("Could not upload glyph to atlas {}: {err}", upload.atlas);
+                    });
+            }
         }
+        Ok(())
+    }
+}
 
-        let mut master_surface: Surface<'_> =
-            Surface::new(_atlas_width, atlas_height, PixelFormatEnum::RGB24)
-                .map_err(|err| {
-                    eprintln!("Could not create atlas surface: {err}");
-                })
-                .unwrap();
+//pub fn reinit_window_surface(window_surface: &mut WindowSurfaceRef, )
 
-        let src = Rect::new(0, 0, font_size, atlas_glyph_height);
+/// Contents of the font file at `font_path` and the path to remember for it.
+/// Falls back to the bundled font, remembered as `<memory>`, if the file cannot be read.
+fn read_font_or_bundled(font_path: String) -> (Vec<u8>, String) {
+    match std::fs::read(&font_path) {
+        Ok(data) => (data, font_path),
+        Err(err) => {
+            warn!("Could not read font {font_path}, using the bundled font: {err}");
+            (BUNDLED_FONT.to_vec(), String::from("<memory>"))
+        }
+    }
+}
 
-        for y in 0.._atlas_rows {
-            for x in 0.._atlas_cols {
-                let ch = y * _atlas_rows + x;
-                font_face
-                    .load_char(ch as usize, LoadFlag::RENDER)
-                    .map_err(|err| eprintln!("Could not load char: {err}"))
-                    .unwrap();
+/// The `requested` atlas size, shrunk to what the renderer `supported`.
+/// A supported size of 0 means the renderer does not know its limit.
+fn clamp_atlas_limits(requested: (u32, u32), supported: (u32, u32)) -> (u32, u32) {
+    let clamp = |limit: u32, supported: u32| match supported {
+        0 => limit,
+        supported => limit.min(supported),
+    };
+    (
+        clamp(requested.0, supported.0),
+        clamp(requested.1, supported.1),
+    )
+}
 
-                let glyph = font_face.glyph();
+/// Largest rect with the aspect ratio of `width` x `height` that fits centered into `area`
+fn letterbox(width: u32, height: u32, area: Rect) -> Rect {
+    let (width, height) = (width.max(1) as u64, height.max(1) as u64);
+    let (area_width, area_height) = (area.width() as u64, area.height() as u64);
+    // compare the ratios without dividing, so no precision is lost
+    let (fit_width, fit_height) = if width * area_height > height * area_width {
+        (area_width, height * area_width / width)
+    } else {
+        (width * area_height / height, area_height)
+    };
+    Rect::new(
+        area.x() + ((area_width - fit_width) / 2) as i32,
+        area.y() + ((area_height - fit_height) / 2) as i32,
+        fit_width as u32,
+        fit_height as u32,
+    )
+}
 
-                if glyph.bitmap_top() > max_ascent as i32 {
-                    max_ascent = glyph.bitmap_top() as u32;
-                }
-                if ((glyph.metrics().height as i32 >> 6) - glyph.bitmap_top()) > max_descent as i32
-                {
-                    max_descent =
-                        ((glyph.metrics().height as i32 >> 6) - glyph.bitmap_top()) as u32;
-                }
-                if glyph.bitmap_left() > max_back as i32 {
-                    max_back = glyph.bitmap_left() as u32;
-                }
-                if ((glyph.metrics().width as i32 >> 6) - glyph.bitmap_left()) > max_forward as i32
-                {
-                    max_forward =
-                        ((glyph.metrics().width as i32 >> 6) - glyph.bitmap_left()) as u32;
-                }
-                if (glyph.metrics().width as u32 >> 6) > max_width {
-                    max_width = glyph.metrics().width as u32 >> 6;
-                }
+/// A font rasterized and packed into atlas pages, with nothing uploaded yet
+struct RasterizedFont {
+    /// The glyphs, positioned where `bitmaps` go in the atlases
+    font: FontDef,
+    /// Coverage of every glyph with a non-empty bitmap, with its pitch, page and place
+    bitmaps: Vec<(char, Vec<u8>, u32, usize, Rect)>,
+    /// Width of every page
+    width: u32,
+    page_heights: Vec<u32>,
+}
 
-                let mut rgb = Vec::<u8>::with_capacity(glyph.bitmap().buffer().len() * 3);
-                for pixel in glyph.bitmap().buffer() {
-                    rgb.extend_from_slice(&[*pixel, *pixel, *pixel]);
-                }
+/// Rasterize the code points in `char_ranges` from the font file contents in `data`
+/// and pack them into pages of at most `limits` pixels.
+/// Only FreeType is involved, turning the pages into textures is up to the caller.
+fn rasterize_font(
+    data: Rc<Vec<u8>>,
+    font_size: u32,
+    char_ranges: &[Range<u32>],
+    limits: (u32, u32),
+) -> Result<RasterizedFont, String> {
+    use freetype::face::LoadFlag;
+    use freetype::Library;
 
-                // loading and blittering this on the CPU should be plenty fast
-                let letter = Surface::from_data(
-                    &mut rgb[..],
-                    glyph.bitmap().width() as u32,
-                    glyph.bitmap().rows() as u32,
-                    glyph.bitmap().pitch() as u32 * 3,
-                    PixelFormatEnum::RGB24,
-                )
-                .unwrap();
-                let dest = Rect::new(
-                    (x * font_size) as i32,
-                    (y * atlas_glyph_height) as i32,
-                    font_size,
-                    atlas_glyph_height,
-                );
-                letter
-                    .blit(src, &mut master_surface, dest)
-                    .map_err(|err| {
-                        eprintln!("Could not blit to texture atlas: {err}");
-                    })
-                    .unwrap();
-
-                // add to map
-                let bbox = Rect::new(
-                    (x * FONT_SIZE) as i32,
-                    (y * atlas_height) as i32,
-                    glyph.metrics().width as u32 >> 6,
-                    glyph.metrics().height as u32 >> 6,
-                );
-                let entry = FontChar::new(
-                    char::from_u32(ch as u32).unwrap(),
-                    bbox,
-                    glyph.advance().x as u32 >> 6,
-                    glyph.advance().y as u32 >> 6,
-                    glyph.bitmap_left(),
-                    glyph.bitmap_top(),
-                );
-                map.insert(ch as usize, Rc::new(entry));
+    #[cfg(feature = "shaping")]
+    let shaping_data = Rc::clone(&data);
+
+    // these variables will be used to determine the effective width and height of a character
+    let mut max_ascent: u32 = 0;
+    let mut max_descent: u32 = 0;
+    let mut max_forward: u32 = 0;
+    let mut max_back: u32 = 0;
+    let mut max_width: u32 = 0;
+
+    let lib = Library::init().map_err(|err| format!("Could not initialize FreeType: {err}"))?;
+
+    // load first font in ttf file
+    let font_face = lib
+        .new_memory_face(data, 0)
+        .map_err(|err| format!("Could not load font: {err}"))?;
+    //font_face.set_char_size(40*64, 0, 96, 96).unwrap();
+    font_face
+        .set_pixel_sizes(font_size, 0)
+        .map_err(|err| format!("Failed to set pixel sizes: {err}"))?;
+    font_face
+        .load_glyph(0, LoadFlag::RENDER)
+        .map_err(|err| format!("Could not load first glyph from font: {err}"))?;
+    // fonts without a replacement character borrow the one of the bundled font
+    let replacement_face = match font_face.get_char_index(char::REPLACEMENT_CHARACTER as usize) {
+        0 => lib
+            .new_memory_face(BUNDLED_FONT.to_vec(), 0)
+            .and_then(|face| face.set_pixel_sizes(font_size, 0).map(|_| face))
+            .map_err(|err| warn!("Could not load the bundled font: {err}"))
+            .ok(),
+        _ => None,
+    };
+
+    // collect every requested code point the font can actually provide.
+    // Control characters are kept even without a glyph, the layout code relies on them.
+    let mut chars: Vec<char> = char_ranges
+        .iter()
+        .flat_map(|range| range.clone())
+        .filter_map(char::from_u32)
+        .filter(|ch| ch.is_control() || font_face.get_char_index(*ch as usize) != 0)
+        .collect();
+    // the replacement character is always rasterized, if the font lacks it
+    // FreeType hands us the .notdef glyph, which usually is a box.
+    if !chars.contains(&char::REPLACEMENT_CHARACTER) {
+        chars.push(char::REPLACEMENT_CHARACTER);
+    }
+    let metrics = font_face
+        .size_metrics()
+        .ok_or("Could not get font metrics: No value returned.")?;
+    let atlas_glyph_height = metrics.height as u32 >> 6;
+    if font_size == 0 || atlas_glyph_height == 0 {
+        return Err(format!("Font has no usable glyphs at {font_size}px"));
+    }
+
+    // rasterize everything first, the packer needs the size of every bitmap
+    let mut glyphs: Vec<(FontChar, Vec<u8>, u32)> = Vec::with_capacity(chars.len());
+    let mut sizes: Vec<(u32, u32)> = Vec::with_capacity(chars.len());
+    for ch in chars.iter().copied() {
+        let face = match &replacement_face {
+            Some(face) if ch == char::REPLACEMENT_CHARACTER => face,
+            _ => &font_face,
+        };
+        if let Err(err) = face.load_char(ch as usize, LoadFlag::RENDER) {
+            error!("Could not load char {ch:?}, skipping: {err}");
+            continue;
+        }
+
+        let glyph = face.glyph();
+
+        if glyph.bitmap_top() > max_ascent as i32 {
+            max_ascent = glyph.bitmap_top() as u32;
+        }
+        if ((glyph.metrics().height as i32 >> 6) - glyph.bitmap_top()) > max_descent as i32 {
+            max_descent = ((glyph.metrics().height as i32 >> 6) - glyph.bitmap_top()) as u32;
+        }
+        if glyph.bitmap_left() > max_back as i32 {
+            max_back = glyph.bitmap_left() as u32;
+        }
+        if ((glyph.metrics().width as i32 >> 6) - glyph.bitmap_left()) > max_forward as i32 {
+            max_forward = ((glyph.metrics().width as i32 >> 6) - glyph.bitmap_left()) as u32;
+        }
+        if (glyph.metrics().width as u32 >> 6) > max_width {
+            max_width = glyph.metrics().width as u32 >> 6;
+        }
+
+        let bitmap = glyph.bitmap();
+        sizes.push((bitmap.width() as u32, bitmap.rows() as u32));
+        // the position is filled in once the glyph is packed
+        let bbox = Rect::new(0, 0, bitmap.width() as u32, bitmap.rows() as u32);
+        let entry = FontChar {
+            advance: glyph.advance().x as f32 / 64.0,
+            ..FontChar::new(
+                ch,
+                bbox,
+                glyph.advance().x as u32 >> 6,
+                glyph.advance().y as u32 >> 6,
+                glyph.bitmap_left(),
+                glyph.bitmap_top(),
+                0,
+            )
+        };
+        glyphs.push((
+            entry,
+            coverage_to_rgba(bitmap.buffer()),
+            bitmap.pitch() as u32 * 4,
+        ));
+    }
+
+    // the GPU may not support textures as large as requested,
+    // glyphs that do not fit into one texture spill over into further atlases
+    let (limit_width, limit_height) = limits;
+    let packing = ShelfPacker::new(limit_width, limit_height).pack(&sizes)?;
+    let atlas_count = packing.page_heights.len();
+    if atlas_count > 1 {
+        debug!(
+            "Splitting {} glyphs across {atlas_count} atlases",
+            glyphs.len()
+        );
+    }
+
+    let mut map: HashMap<usize, Rc<FontChar>> = Default::default();
+    let mut bitmaps = Vec::with_capacity(glyphs.len());
+    for (((mut entry, rgba, pitch), (width, rows)), (page, place)) in
+        glyphs.into_iter().zip(sizes).zip(packing.places)
+    {
+        let ch = entry.ch;
+        if width > 0 && rows > 0 {
+            bitmaps.push((ch, rgba, pitch, page, place));
+        }
+        entry.bbox = place;
+        entry.atlas = FontDef::atlas_key(page);
+        map.insert(ch as usize, Rc::new(entry));
+    }
+
+    // kerning is looked up between glyph indices, so only pairs of real glyphs matter
+    let mut kerning: HashMap<(char, char), i32> = Default::default();
+    if font_face.has_kerning() {
+        use freetype::face::KerningMode;
+        let glyph_indices: Vec<(char, u32)> = chars
+            .iter()
+            .map(|ch| (*ch, font_face.get_char_index(*ch as usize)))
+            .filter(|(_, idx)| *idx != 0)
+            .collect();
+        for (left, left_idx) in &glyph_indices {
+            for (right, right_idx) in &glyph_indices {
+                if let Ok(delta) =
+                    font_face.get_kerning(*left_idx, *right_idx, KerningMode::KerningDefault)
+                {
+                    if delta.x != 0 {
+                        kerning.insert((*left, *right), (delta.x >> 6) as i32);
+                    }
+                }
             }
         }
+    }
 
-        self.texture_manager
-            .load(usize::MAX, &master_surface)
-            .map_err(|err| {
-                eprintln!("Could not create texture from surface: {err}");
-            })
-            .unwrap();
+    let mut font = FontDef::new(
+        map,
+        max_ascent + max_descent,
+        max_width, //max_forward + max_back,
+        FONT_SPACING,
+        max_ascent,
+        max_descent,
+        max_back,
+        max_forward,
+        font_size,
+    );
+    font.set_kerning(kerning);
+    font.set_vertical_metrics(
+        (metrics.ascender >> 6) as i32,
+        -(metrics.descender >> 6) as i32,
+        atlas_glyph_height,
+    );
+    font.set_atlas_count(atlas_count);
+    font.set_face(font_face, font_size, atlas_glyph_height);
+    #[cfg(feature = "shaping")]
+    font.set_shaping_data(shaping_data);
+    Ok(RasterizedFont {
+        font,
+        bitmaps,
+        width: packing.width,
+        page_heights: packing.page_heights,
+    })
+}
 
-        self.loaded_font = FontDef::new(
-            map,
-            max_ascent + max_descent,
-            max_width, //max_forward + max_back,
-            FONT_SPACING,
-            max_ascent,
-            max_descent,
-            font_size,
-            max_back,
-            max_forward,
+/// Insert typed or pasted `text` at the cursor, replacing any pending composition
+fn insert_text(text_box: &mut screen_manager::TextScreen, font: &mut FontDef, text: String) {
+    text_box.clear_composition();
+    text_box.push_string(font.get_string_lossy(text));
+}
+
+/// Replace the contents of `text_box` with the file at `path`, placing the cursor at the start.
+/// Line breaks are normalized to `'\n'`, the style of the file is kept for saving.
+fn open_file(
+    text_box: &mut screen_manager::TextScreen,
+    font: &mut FontDef,
+    path: &Path,
+) -> Result<(), String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not open file {}: {err}", path.display()))?;
+    let line_ending = LineEnding::detect(&text);
+    let text = text.replace("\r\n", "\n");
+    text_box.clear();
+    text_box.set_line_ending(line_ending);
+    text_box.push_string(font.get_string_lossy(text));
+    text_box.move_cursor_to(0);
+    text_box.clear_history();
+    text_box.mark_clean();
+    Ok(())
+}
+
+/// Write the contents of `text_box` to `path`, with the line breaks it was loaded with.
+/// The buffer itself is left alone, whitespace is only trimmed from what is written.
+fn save_file(
+    text_box: &mut screen_manager::TextScreen,
+    path: &Path,
+    config: &Config,
+) -> Result<(), String> {
+    let mut text = text_box.get_text();
+    if config.trim_trailing_whitespace || config.ensure_final_newline {
+        text = trim_trailing_whitespace(
+            &text,
+            config.trim_trailing_whitespace,
+            config.ensure_final_newline,
         );
     }
+    let text = match text_box.line_ending() {
+        LineEnding::Lf => text,
+        line_ending => text.replace('\n', line_ending.as_str()),
+    };
+    std::fs::write(path, text)
+        .map_err(|err| format!("Could not save file {}: {err}", path.display()))?;
+    text_box.mark_clean();
+    Ok(())
 }
 
-//pub fn reinit_window_surface(window_surface: &mut WindowSurfaceRef, )
+/// Strip spaces and tabs at the end of every line of `text`,
+/// and/or make it end in exactly one line break
+fn trim_trailing_whitespace(text: &str, trim_lines: bool, ensure_final_newline: bool) -> String {
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    if trim_lines {
+        for line in &mut lines {
+            *line = line.trim_end_matches([' ', '\t']);
+        }
+    }
+    if ensure_final_newline {
+        while lines.len() > 1 && lines[lines.len() - 1].is_empty() {
+            lines.pop();
+        }
+        if !text.is_empty() {
+            lines.push("");
+        }
+    }
+    lines.join("\n")
+}
+
+/// With NumLock off the keypad moves the cursor, so turn its keys into the navigation keys.
+/// With NumLock on the digits arrive as text input.
+fn keypad_navigation(event: Event) -> Event {
+    use sdl2::keyboard::Mod;
+
+    let navigation = |code: Keycode, keymod: Mod| {
+        if keymod.contains(Mod::NUMMOD) {
+            return code;
+        }
+        match code {
+            Keycode::Kp4 => Keycode::Left,
+            Keycode::Kp6 => Keycode::Right,
+            Keycode::Kp8 => Keycode::Up,
+            Keycode::Kp2 => Keycode::Down,
+            Keycode::Kp7 => Keycode::Home,
+            Keycode::Kp1 => Keycode::End,
+            Keycode::Kp9 => Keycode::PageUp,
+            Keycode::Kp3 => Keycode::PageDown,
+            Keycode::Kp0 => Keycode::Insert,
+            Keycode::KpPeriod => Keycode::Delete,
+            code => code,
+        }
+    };
+    match event {
+        Event::KeyDown {
+            timestamp,
+            window_id,
+            keycode: Some(code),
+            scancode,
+            keymod,
+            repeat,
+        } => Event::KeyDown {
+            timestamp,
+            window_id,
+            keycode: Some(navigation(code, keymod)),
+            scancode,
+            keymod,
+            repeat,
+        },
+        Event::KeyUp {
+            timestamp,
+            window_id,
+            keycode: Some(code),
+            scancode,
+            keymod,
+            repeat,
+        } => Event::KeyUp {
+            timestamp,
+            window_id,
+            keycode: Some(navigation(code, keymod)),
+            scancode,
+            keymod,
+            repeat,
+        },
+        event => event,
+    }
+}
+
+/// File name of a buffer, marked with a `*` if it has unsaved changes
+fn buffer_name(file_path: Option<&Path>, dirty: bool) -> String {
+    let name = file_path
+        .and_then(|path| path.file_name())
+        .map_or(String::from("untitled"), |name| {
+            name.to_string_lossy().into_owned()
+        });
+    let marker = if dirty { "*" } else { "" };
+    format!("{name}{marker}")
+}
+
+fn window_title(file_path: Option<&Path>, dirty: bool) -> String {
+    format!("{} - Saute Text Editor", buffer_name(file_path, dirty))
+}
+
+/// One entry per open buffer, the active one in brackets
+fn tab_strip_text(buffers: &BufferSet) -> String {
+    buffers
+        .iter()
+        .enumerate()
+        .map(|(idx, screen)| {
+            let name = buffer_name(screen.path(), screen.is_dirty());
+            if idx == buffers.active_index() {
+                format!("[{name}]")
+            } else {
+                format!(" {name} ")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Height left for the text between the tab strip at the top and the status bar at the bottom
+/// Show `text` on `screen`, only rebuilding its glyphs if it differs from the `shown` text.
+/// Returns true if the screen was rebuilt.
+fn refresh_text(
+    screen: &mut TextScreen,
+    font: &mut FontDef,
+    shown: &mut Option<String>,
+    text: String,
+) -> bool {
+    if shown.as_ref() == Some(&text) {
+        return false;
+    }
+    screen.clear();
+    screen.push_string(font.get_string_lossy(text.as_str()));
+    *shown = Some(text);
+    true
+}
+
+fn text_height(renderer: &Renderer<'_>) -> usize {
+    renderer
+        .height
+        .saturating_sub(3 * renderer.loaded_font.line_height()) as usize
+}
+
+/// How long the event loop may sleep at `now`: until the caret blinks, a held key repeats
+/// or an edited buffer is highlighted again, whichever comes first
+fn event_timeout(now: Instant, buffers: &BufferSet, key_repeat: &KeyRepeat) -> Duration {
+    let blink_timeout = buffers.active().blink_timeout(now);
+    [
+        key_repeat.timeout(now),
+        buffers
+            .iter()
+            .filter_map(|screen| screen.highlight_timeout(now))
+            .min(),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(blink_timeout, Duration::min)
+}
+
+/// An empty buffer set up according to the config
+fn new_text_screen(config: &Config, renderer: &Renderer<'_>) -> TextScreen {
+    let mut text_box = TextScreen::new(
+        renderer.width as usize,
+        text_height(renderer),
+        renderer.loaded_font.line_height() as usize,
+    );
+    text_box.set_tab_width(config.tab_width);
+    text_box.set_wrap_indent(config.wrap_indent, config.hanging_indent);
+    text_box.cursor_enable();
+    text_box.set_caret_shape(config.caret_shape());
+    text_box.set_highlight_current_line(true);
+    text_box.set_highlighter(highlight::highlighter_for(None, config.rainbow_brackets));
+    text_box
+}
 
 pub fn main() -> Result<(), ()> {
     use sdl2::keyboard::Mod;
@@ -282,153 +1002,630 @@ pub fn main() -> Result<(), ()> {
     if cfg!(debug_assertions) {
         env::set_var("RUST_BACKTRACE", "1");
     }
+    // show info and above unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    const WIDTH: u32 = 800;
-    const HEIGHT: u32 = 600;
-    const FONT_FILE_DEFAULT: &'static str = "/home/janick/tests/saute/fonts/Arial.ttf";
-    const FONT_FILE_ALT: &'static str = "/home/janick/tests/saute/fonts/Consolas.ttf";
+    const SCREENSHOT_FILE: &'static str = "saute-screenshot.png";
+    const FONT_FILE_ALT: &'static str = "fonts/Consolas.ttf";
     let mut using_alt_font = false;
+    let config = Config::load();
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context
         .video()
-        .map_err(|err| eprintln!("Could not create video context: {err}"))
+        .map_err(|err| error!("Could not create video context: {err}"))
         .unwrap();
 
     let window = video_subsystem
-        .window("Saute Text Editor", WIDTH, HEIGHT)
+        .window(
+            "Saute Text Editor",
+            config.window_width,
+            config.window_height,
+        )
         .position_centered()
         .resizable()
         .build()
-        .map_err(|err| eprintln!("Could not build window: {err}"))
+        .map_err(|err| error!("Could not build window: {err}"))
         .unwrap();
 
-    let window_canvas = window
-        .into_canvas()
+    let mut canvas_builder = window.into_canvas();
+    if config.vsync() {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let window_canvas = canvas_builder
         .build()
         .map_err(|err| {
-            eprintln!("Failed to get window canvas: {err}");
+            error!("Failed to get window canvas: {err}");
         })
         .unwrap();
     let texman = window_canvas.texture_creator();
 
-    let mut renderer = Renderer::new(window_canvas, &texman, WIDTH, HEIGHT);
-    println!("[INFO] Loading font {FONT_FILE_DEFAULT}");
-    renderer.build_atlas(FONT_FILE_DEFAULT, FONT_SIZE);
+    info!("Loading font {}", config.font_path);
+    let mut renderer = RendererBuilder::new(
+        window_canvas,
+        &texman,
+        config.window_width,
+        config.window_height,
+    )
+    .font_path(&config.font_path)
+    .font_size(config.font_size)
+    .fallback_fonts(&config.fallback_fonts)
+    .char_ranges(&[ANSI_RANGE, LATIN1_SUPPLEMENT_RANGE])
+    .theme(config.theme())
+    .build()
+    .map_err(|err| {
+        error!("Could not load a font: {err}");
+    })
+    .unwrap();
     let mut event_pump = sdl_context
         .event_pump()
-        .map_err(|err| eprintln!("Failed to get event pump: {err}"))
+        .map_err(|err| error!("Failed to get event pump: {err}"))
         .unwrap();
 
-    renderer.canvas.set_draw_color::<_>(Color::RGB(0, 0, 0));
+    renderer
+        .canvas
+        .set_draw_color::<_>(renderer.theme.background);
     renderer.canvas.clear();
     renderer.canvas.present();
 
     event_pump.enable_event(EventType::TextInput);
-
-    let mut text_box = screen_manager::TextScreen::new(
-        WIDTH as usize,
-        (HEIGHT - 2 * renderer.loaded_font.glyph_height) as usize,
-        renderer.loaded_font.glyph_height as usize,
-    );
+    let clipboard = video_subsystem.clipboard();
 
     let mut debug_info_text = screen_manager::TextScreen::new(
-        WIDTH as usize,
-        renderer.loaded_font.glyph_height as usize,
-        renderer.loaded_font.glyph_height as usize,
+        config.window_width as usize,
+        renderer.loaded_font.line_height() as usize,
+        renderer.loaded_font.line_height() as usize,
     );
-    let debug_info_render_height = HEIGHT - renderer.loaded_font.glyph_height;
     debug_info_text.cursor_disable();
+    let mut tab_strip = screen_manager::TextScreen::new(
+        config.window_width as usize,
+        renderer.loaded_font.line_height() as usize,
+        renderer.loaded_font.line_height() as usize,
+    );
+    tab_strip.cursor_disable();
+    // names shown in the tab strip, None if they have to be rebuilt
+    let mut tab_strip_status: Option<String> = None;
+
+    // every file given on the command line is opened in its own buffer
+    let mut screens: Vec<TextScreen> = Vec::new();
+    for path in env::args().skip(1).map(PathBuf::from) {
+        let mut text_box = new_text_screen(&config, &renderer);
+        match open_file(&mut text_box, &mut renderer.loaded_font, &path) {
+            Ok(()) => info!("Opened file {}", path.display()),
+            Err(err) => warn!("{err}, starting with an empty buffer"),
+        }
+        text_box.set_highlighter(highlight::highlighter_for(
+            Some(&path),
+            config.rainbow_brackets,
+        ));
+        text_box.set_path(Some(path));
+        screens.push(text_box);
+    }
+    let mut screens = screens.into_iter();
+    let mut buffers = BufferSet::new(
+        screens
+            .next()
+            .unwrap_or_else(|| new_text_screen(&config, &renderer)),
+    );
+    for text_box in screens {
+        buffers.open(text_box);
+    }
+    let mut shown_title = String::new();
+    let mut key_repeat = KeyRepeat::new(
+        Duration::from_millis(config.key_repeat_delay_ms),
+        Duration::from_millis(config.key_repeat_interval_ms),
+    );
 
     let mut keybind_handled = false;
     let mut need_update: bool = true;
-    text_box.cursor_enable();
+    let mut take_screenshot = false;
+    // typed text goes to the find prompt instead of the buffer
+    let mut find_prompt = false;
+    // line number typed after Ctrl+G
+    let mut goto_prompt: Option<String> = None;
+    // cursor position shown in the debug bar, None if it has to be rebuilt
+    let mut debug_info_status: Option<String> = None;
+    // set after Ctrl+W was refused for a buffer with unsaved changes, the next one closes it
+    let mut confirm_close = false;
+    let frame_duration = config.frame_duration();
+    let mut last_frame = Instant::now();
+    renderer.mark_all_dirty();
     'running: loop {
-        for event in event_pump.poll_iter() {
+        // sleep until there is input, the cursor has to blink or a held key repeats
+        let timeout = event_timeout(Instant::now(), &buffers, &key_repeat);
+        let first_event = event_pump.wait_event_timeout(timeout.as_millis().max(1) as u32);
+        let now = Instant::now();
+        let repeated = key_repeat.poll(now);
+        let events: Vec<Event> = first_event
+            .into_iter()
+            .chain(event_pump.poll_iter())
+            .map(keypad_navigation)
+            .filter_map(|event| key_repeat.filter(event, now))
+            .chain(repeated)
+            .collect();
+        for event in events {
+            let text_box = buffers.active_mut();
             match event {
+                Event::KeyDown {
+                    keycode: Some(code),
+                    keymod,
+                    ..
+                } if find_prompt => {
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    match code {
+                        Keycode::Escape => {
+                            text_box.set_find(None);
+                            find_prompt = false;
+                        }
+                        Keycode::Return | Keycode::KpEnter => find_prompt = false,
+                        Keycode::F3 if shift => {
+                            text_box.find_prev();
+                        }
+                        Keycode::F3 => {
+                            text_box.find_next();
+                        }
+                        Keycode::Backspace => {
+                            let mut query = text_box.find().unwrap_or_default().to_owned();
+                            query.pop();
+                            text_box.set_find(Some(query));
+                        }
+                        _ => {}
+                    }
+                    need_update = true;
+                }
+                Event::KeyDown {
+                    keycode: Some(code),
+                    ..
+                } if goto_prompt.is_some() => {
+                    match code {
+                        Keycode::Escape => goto_prompt = None,
+                        Keycode::Return | Keycode::KpEnter => {
+                            let line = goto_prompt.take().and_then(|input| input.parse().ok());
+                            if let Some(line) = line {
+                                text_box.update_selection(false);
+                                text_box.goto_line(line);
+                            }
+                        }
+                        Keycode::Backspace => {
+                            if let Some(input) = &mut goto_prompt {
+                                input.pop();
+                            }
+                        }
+                        _ => {}
+                    }
+                    need_update = true;
+                }
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
                 Event::KeyDown {
-                    keycode: Some(Keycode::F),
+                    keycode: Some(code),
                     keymod,
                     ..
                 } => {
-                    if !keymod.contains(Mod::LALTMOD) {
-                        break;
+                    let action = key_action(code, keymod);
+                    if action != Some(KeyAction::CloseBuffer) {
+                        confirm_close = false;
                     }
-                    println!("[INFO] Switching font!");
-                    renderer.texture_manager.clear();
-                    let font_path = if using_alt_font {
-                        using_alt_font = false;
-                        FONT_FILE_DEFAULT
-                    } else {
-                        using_alt_font = true;
-                        FONT_FILE_ALT
+                    let Some(action) = action else {
+                        continue;
                     };
-                    renderer.build_atlas(font_path, FONT_SIZE);
-                    let str = text_box.get_text();
-                    text_box.clear();
-                    text_box.push_string(renderer.loaded_font.get_string(str)?);
-                    need_update = true;
-                    keybind_handled = true;
-                }
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(code) = keycode {
-                        // normal event
-                        match code {
-                            Keycode::Return | Keycode::Return2 => {
-                                let fch = renderer
-                                    .loaded_font
-                                    .get_char('\n' as usize)
-                                    .map_err(|_| {
-                                        eprintln!("Failed to get char '\\n' from texture atlas");
-                                    })
-                                    .unwrap();
-                                text_box.push_char(fch);
+                    match action {
+                        KeyAction::SwitchFont => {
+                            info!("Switching font!");
+                            let font_path = if using_alt_font {
+                                using_alt_font = false;
+                                config.font_path.as_str()
+                            } else {
+                                using_alt_font = true;
+                                FONT_FILE_ALT
+                            };
+                            if let Err(err) = renderer.rebuild_atlas_from(font_path) {
+                                error!("Could not switch to font {font_path}: {err}");
+                            }
+                            for text_box in buffers.iter_mut() {
+                                text_box.reload_glyphs(&mut renderer.loaded_font);
+                            }
+                            debug_info_status = None;
+                            tab_strip_status = None;
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::CycleAtlasDebug => {
+                            renderer.cycle_atlas_debug();
+                            need_update = true;
+                        }
+                        KeyAction::ToggleDebugOverlay => {
+                            renderer.debug_overlay = !renderer.debug_overlay;
+                            info!(
+                                "Debug overlay {}",
+                                if renderer.debug_overlay { "on" } else { "off" }
+                            );
+                            // the overlay is drawn into the cached lines
+                            for text_box in buffers.iter_mut() {
+                                text_box.invalidate_lines();
+                            }
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::Screenshot => {
+                            // the whole frame has to be current for the screenshot
+                            take_screenshot = true;
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::NextBuffer | KeyAction::PrevBuffer => {
+                            if action == KeyAction::PrevBuffer {
+                                buffers.prev_buffer();
+                            } else {
+                                buffers.next_buffer();
+                            }
+                            debug_info_status = None;
+                            tab_strip_status = None;
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::ToggleSplit => {
+                            if buffers.is_split() {
+                                buffers.unsplit();
+                            } else {
+                                // the other pane needs a buffer of its own
+                                if buffers.len() == 1 {
+                                    buffers.open(new_text_screen(&config, &renderer));
+                                    buffers.prev_buffer();
+                                }
+                                if let Err(err) = buffers.split() {
+                                    error!("Could not split the window: {err}");
+                                }
+                            }
+                            debug_info_status = None;
+                            tab_strip_status = None;
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::FocusOther => {
+                            buffers.focus_other();
+                            debug_info_status = None;
+                            tab_strip_status = None;
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::CloseBuffer => {
+                            if text_box.is_dirty() && !confirm_close {
+                                warn!("The buffer has unsaved changes, press Ctrl+W again to close it anyway");
+                                confirm_close = true;
+                            } else {
+                                // closing the last buffer leaves an empty one behind
+                                if buffers.len() == 1 {
+                                    buffers.open(new_text_screen(&config, &renderer));
+                                    buffers.prev_buffer();
+                                }
+                                match buffers.close(true) {
+                                    Ok(mut closed) => closed.release_line_cache(&mut renderer),
+                                    Err(err) => error!("Could not close the buffer: {err}"),
+                                }
+                                confirm_close = false;
+                                debug_info_status = None;
+                                tab_strip_status = None;
+                                renderer.mark_all_dirty();
                                 need_update = true;
-                                keybind_handled = true;
                             }
-                            Keycode::Backspace => {
+                        }
+                        KeyAction::Find => {
+                            text_box.set_find(Some(String::new()));
+                            find_prompt = true;
+                            need_update = true;
+                        }
+                        KeyAction::FindNext | KeyAction::FindPrev => {
+                            text_box.update_selection(false);
+                            need_update |= if action == KeyAction::FindPrev {
+                                text_box.find_prev()
+                            } else {
+                                text_box.find_next()
+                            };
+                        }
+                        KeyAction::GotoLine => {
+                            goto_prompt = Some(String::new());
+                            find_prompt = false;
+                            need_update = true;
+                        }
+                        KeyAction::Save => match text_box.path().map(Path::to_path_buf) {
+                            Some(path) => match save_file(text_box, &path, &config) {
+                                Ok(()) => info!("Saved file {}", path.display()),
+                                Err(err) => error!("{err}"),
+                            },
+                            None => warn!("No file to save to, open one first"),
+                        },
+                        KeyAction::DuplicateLine => {
+                            let fch = renderer
+                                .loaded_font
+                                .get_char('\n' as usize)
+                                .map_err(|_| {
+                                    error!("Failed to get char '\\n' from texture atlas");
+                                })
+                                .unwrap();
+                            text_box.duplicate_line(fch);
+                            need_update = true;
+                        }
+                        KeyAction::ToggleComment => {
+                            text_box.toggle_comment(&mut renderer.loaded_font);
+                            need_update = true;
+                        }
+                        KeyAction::ToggleOverwrite => {
+                            let overwrite = !text_box.overwrite();
+                            text_box.set_overwrite(overwrite);
+                            text_box.set_caret_shape(if overwrite {
+                                CaretShape::Block
+                            } else {
+                                config.caret_shape()
+                            });
+                            need_update = true;
+                        }
+                        KeyAction::SelectAll => {
+                            text_box.select_all();
+                            need_update = true;
+                        }
+                        KeyAction::Copy => {
+                            if let Some(text) = text_box.selected_text() {
+                                clipboard.set_clipboard_text(&text).unwrap_or_else(|err| {
+                                    error!("Could not copy selection to clipboard: {err}");
+                                });
+                            }
+                        }
+                        KeyAction::Paste => {
+                            if clipboard.has_clipboard_text() {
+                                match clipboard.clipboard_text() {
+                                    Ok(text) if !text.is_empty() => {
+                                        insert_text(text_box, &mut renderer.loaded_font, text);
+                                        need_update = true;
+                                    }
+                                    Ok(_) => {}
+                                    Err(err) => {
+                                        error!("Could not paste from clipboard: {err}");
+                                    }
+                                }
+                            }
+                        }
+                        KeyAction::Undo => need_update |= text_box.undo(),
+                        KeyAction::Redo => need_update |= text_box.redo(),
+                        KeyAction::ZoomIn | KeyAction::ZoomOut => {
+                            let font_size = if action == KeyAction::ZoomIn {
+                                renderer.font_size + FONT_SIZE_STEP
+                            } else {
+                                renderer
+                                    .font_size
+                                    .saturating_sub(FONT_SIZE_STEP)
+                                    .max(FONT_SIZE_MIN)
+                            };
+                            if let Err(err) = renderer.rebuild_atlas(font_size) {
+                                error!("Could not change the font size to {font_size}: {err}");
+                            }
+                            for text_box in buffers.iter_mut() {
+                                text_box.reload_glyphs(&mut renderer.loaded_font);
+                                text_box.set_height(text_height(&renderer));
+                            }
+                            debug_info_status = None;
+                            tab_strip_status = None;
+                            tab_strip.set_row_height(renderer.loaded_font.line_height() as usize);
+                            debug_info_text
+                                .set_row_height(renderer.loaded_font.line_height() as usize);
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        KeyAction::Newline => {
+                            let fch = renderer
+                                .loaded_font
+                                .get_char('\n' as usize)
+                                .map_err(|_| {
+                                    error!("Failed to get char '\\n' from texture atlas");
+                                })
+                                .unwrap();
+                            text_box.insert_newline_with_indent(fch);
+                            need_update = true;
+                        }
+                        KeyAction::Dedent => {
+                            text_box.dedent_selection();
+                            need_update = true;
+                        }
+                        KeyAction::Tab if text_box.selection_spans_lines() => {
+                            let indent = if text_box.insert_spaces_for_tab() {
+                                " ".repeat(text_box.tab_width().max(1) as usize)
+                            } else {
+                                String::from("\t")
+                            };
+                            text_box
+                                .indent_selection(&renderer.loaded_font.get_string_lossy(indent));
+                            need_update = true;
+                        }
+                        KeyAction::Tab => {
+                            let tab = if text_box.insert_spaces_for_tab() {
+                                let tab_width = text_box.tab_width().max(1);
+                                " ".repeat(
+                                    (tab_width - text_box.get_cursor_col() % tab_width) as usize,
+                                )
+                            } else {
+                                String::from("\t")
+                            };
+                            text_box.push_string(renderer.loaded_font.get_string_lossy(tab));
+                            need_update = true;
+                        }
+                        KeyAction::DeleteWordBack => {
+                            if !text_box.delete_selection() {
+                                text_box.delete_word_back();
+                            }
+                            need_update = true;
+                        }
+                        KeyAction::Backspace => {
+                            if !text_box.delete_selection() {
                                 text_box.pop_char();
-                                need_update = true;
-                                keybind_handled = true;
                             }
-                            Keycode::Right => {
-                                text_box.cursor_forward();
-                                need_update = true;
-                                keybind_handled = true;
+                            need_update = true;
+                        }
+                        KeyAction::Delete => {
+                            if !text_box.delete_selection() {
+                                text_box.delete_char();
                             }
-
-                            Keycode::Left => {
-                                text_box.cursor_back();
-                                need_update = true;
-                                keybind_handled = true;
+                            need_update = true;
+                        }
+                        KeyAction::MoveLineUp => need_update |= text_box.move_line_up(),
+                        KeyAction::MoveLineDown => need_update |= text_box.move_line_down(),
+                        KeyAction::CursorForward { select }
+                        | KeyAction::CursorBack { select }
+                        | KeyAction::WordForward { select }
+                        | KeyAction::WordBack { select }
+                        | KeyAction::CursorUp { select }
+                        | KeyAction::CursorDown { select }
+                        | KeyAction::Home { select }
+                        | KeyAction::End { select } => {
+                            text_box.update_selection(select);
+                            match action {
+                                KeyAction::CursorForward { .. } => text_box.cursor_forward(),
+                                KeyAction::CursorBack { .. } => text_box.cursor_back(),
+                                KeyAction::WordForward { .. } => text_box.cursor_word_forward(),
+                                KeyAction::WordBack { .. } => text_box.cursor_word_back(),
+                                KeyAction::CursorUp { .. } => text_box.cursor_up(),
+                                KeyAction::CursorDown { .. } => text_box.cursor_down(),
+                                KeyAction::Home { .. } => text_box.cursor_home(),
+                                _ => text_box.cursor_end(),
                             }
-                            _ => {}
+                            need_update = true;
                         }
                     }
+                    keybind_handled = true;
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    // clicking into the other pane of a split view focuses it
+                    let Some((idx, pane_x)) = buffers.pane_at(x, renderer.width) else {
+                        continue;
+                    };
+                    if idx != buffers.active_index() {
+                        buffers.focus_other();
+                        debug_info_status = None;
+                        tab_strip_status = None;
+                        renderer.mark_all_dirty();
+                    }
+                    let text_box = buffers.active_mut();
+                    let (x, y) = (
+                        x - pane_x as i32,
+                        y - renderer.loaded_font.line_height() as i32,
+                    );
+                    let pos = text_box.pos_from_pixel(x, y, &renderer.loaded_font);
+                    text_box.move_cursor_to(pos);
+                    text_box.set_highlight_mark(pos);
+                    need_update = true;
+                }
+                Event::MouseMotion {
+                    mousestate, x, y, ..
+                } if mousestate.left() => {
+                    let pane_x = buffers
+                        .panes(renderer.width)
+                        .into_iter()
+                        .find(|(idx, _, _)| *idx == buffers.active_index())
+                        .map_or(0, |(_, pane_x, _)| pane_x);
+                    let text_box = buffers.active_mut();
+                    let (x, y) = (
+                        x - pane_x as i32,
+                        y - renderer.loaded_font.line_height() as i32,
+                    );
+                    let pos = text_box.pos_from_pixel(x, y, &renderer.loaded_font);
+                    if pos != text_box.get_cursor_abs() {
+                        text_box.move_cursor_to(pos);
+                        need_update = true;
+                    }
+                }
+                // a plain click does not select anything
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } if text_box.get_highlight_mark() == text_box.get_cursor_abs() => {
+                    text_box.set_highlight_mark(u32::MAX);
+                }
+                Event::DropFile { filename, .. } => {
+                    let mut text_box = new_text_screen(&config, &renderer);
+                    match open_file(
+                        &mut text_box,
+                        &mut renderer.loaded_font,
+                        Path::new(&filename),
+                    ) {
+                        Ok(()) => {
+                            info!("Opened dropped file {filename}");
+                            let path = PathBuf::from(filename);
+                            text_box.set_highlighter(highlight::highlighter_for(
+                                Some(&path),
+                                config.rainbow_brackets,
+                            ));
+                            text_box.set_path(Some(path));
+                            buffers.open(text_box);
+                            tab_strip_status = None;
+                            renderer.mark_all_dirty();
+                            need_update = true;
+                        }
+                        Err(err) => warn!("{err}, ignoring dropped file"),
+                    }
+                }
+                Event::MouseWheel { y, direction, .. } => {
+                    let y = match direction {
+                        MouseWheelDirection::Flipped => -y,
+                        _ => y,
+                    };
+                    if y > 0 {
+                        text_box.scroll_up(y as usize);
+                    } else {
+                        text_box.scroll_down(y.unsigned_abs() as usize);
+                    }
+                    need_update = true;
                 }
                 Event::TextInput { text, .. } => {
+                    // the text of a key press that was bound to an action
                     if keybind_handled {
-                        break;
+                        keybind_handled = false;
+                        continue;
                     }
-                    println!("[INFO] Event::TextInput triggered");
-                    text_box.push_string(renderer.loaded_font.get_string(text)?);
+                    debug!("Event::TextInput triggered");
+                    if let Some(input) = &mut goto_prompt {
+                        input.extend(text.chars().filter(char::is_ascii_digit));
+                        need_update = true;
+                        continue;
+                    }
+                    if find_prompt {
+                        let mut query = text_box.find().unwrap_or_default().to_owned();
+                        query.push_str(&text);
+                        text_box.set_find(Some(query));
+                        need_update = true;
+                        continue;
+                    }
+                    insert_text(text_box, &mut renderer.loaded_font, text);
+                    need_update = true;
+                }
+                Event::TextEditing { text, .. } => {
+                    if goto_prompt.is_some() || find_prompt {
+                        continue;
+                    }
+                    text_box.set_composition(text);
                     need_update = true;
                 }
                 Event::Window { win_event, .. } => {
                     use sdl2::event::WindowEvent;
                     match win_event {
                         WindowEvent::SizeChanged(w, h) | WindowEvent::Resized(w, h) => {
-                            println!(
-                                "[INFO] Window resized to {w}x{h}, need to reinit window surface"
-                            );
+                            info!("Window resized to {w}x{h}, need to reinit window surface");
                             renderer.width = w as u32;
                             renderer.height = h as u32;
-                            text_box.set_width(w as usize);
+                            for text_box in buffers.iter_mut() {
+                                text_box.set_width(w as usize);
+                                text_box.set_height(text_height(&renderer));
+                                text_box.scroll_to_cursor();
+                            }
+                            tab_strip.set_width(w as usize);
+                            debug_info_text.set_width(w as usize);
+                            renderer.mark_all_dirty();
                             need_update = true;
                         }
                         _ => {}
@@ -438,45 +1635,805 @@ pub fn main() -> Result<(), ()> {
             }
         }
         keybind_handled = false;
+        let panes = buffers.panes(renderer.width);
+        for (idx, _, width) in &panes {
+            if let Some(screen) = buffers.get_mut(*idx) {
+                screen.set_width(*width as usize);
+            }
+        }
+        let now = Instant::now();
+        for screen in buffers.iter_mut() {
+            need_update |= screen.update_highlight(now);
+        }
+        let text_box = buffers.active_mut();
+        let title = window_title(text_box.path(), text_box.is_dirty());
+        if title != shown_title {
+            renderer
+                .canvas
+                .window_mut()
+                .set_title(&title)
+                .unwrap_or_else(|err| {
+                    error!("Could not set window title: {err}");
+                });
+            shown_title = title;
+        }
+        need_update |= text_box.update_blink(Instant::now());
+        let debug_info_render_height = renderer
+            .height
+            .saturating_sub(renderer.loaded_font.line_height());
+        let text_top = renderer.loaded_font.line_height();
         if need_update {
-            println!(
-                "[INFO] Updating screen! {w} x {h}",
-                w = renderer.width,
-                h = renderer.height
+            renderer.upload_glyphs().map_err(|err| error!("{err}"))?;
+            for (idx, x, _) in &panes {
+                if let Some(screen) = buffers.get_mut(*idx) {
+                    let text_damage = screen.damage(*x, text_top, &renderer.loaded_font);
+                    renderer.mark_dirty(text_damage);
+                }
+            }
+            renderer.mark_dirty(Rect::new(0, 0, renderer.width, text_top));
+            renderer.mark_dirty(Rect::new(
+                0,
+                debug_info_render_height as i32,
+                renderer.width,
+                renderer.loaded_font.line_height(),
+            ));
+            need_update = false;
+        }
+        if let Some(dirty) = renderer.take_dirty() {
+            // without vsync, keep frames from following each other faster than the cap
+            if let Some(frame_duration) = frame_duration {
+                let elapsed = last_frame.elapsed();
+                if elapsed < frame_duration {
+                    std::thread::sleep(frame_duration - elapsed);
+                }
+            }
+            debug!(
+                "Updating screen! {w} x {h} at {x}, {y}",
+                w = dirty.width(),
+                h = dirty.height(),
+                x = dirty.x(),
+                y = dirty.y()
             );
-            renderer.canvas.set_draw_color::<_>(Color::RGB(0, 0, 0));
+            renderer.canvas.set_clip_rect(dirty);
             renderer
                 .canvas
-                .fill_rect(Rect::new(0, 0, renderer.width, renderer.height))
-                .unwrap();
+                .set_draw_color::<_>(renderer.theme.background);
+            renderer.canvas.fill_rect(dirty).unwrap();
+
+            for (idx, x, width) in &panes {
+                if let Some(screen) = buffers.get_mut(*idx) {
+                    screen
+                        .render_all(&mut renderer, *x, text_top)
+                        .map_err(|err| {
+                            error!("Could not render text to canvas: {err}");
+                        })
+                        .unwrap();
+                }
+                // divider on the right of the left pane
+                if *x == 0 && panes.len() > 1 {
+                    renderer
+                        .canvas
+                        .set_draw_color::<_>(renderer.theme.scrollbar_track);
+                    renderer
+                        .canvas
+                        .fill_rect(Rect::new(
+                            *width as i32,
+                            text_top as i32,
+                            SPLIT_GAP,
+                            debug_info_render_height.saturating_sub(text_top),
+                        ))
+                        .unwrap();
+                }
+            }
+            let text_box = buffers.active_mut();
 
-            text_box
+            let prompt_text = match text_box.find() {
+                _ if goto_prompt.is_some() => {
+                    let input = goto_prompt.as_deref().unwrap_or_default();
+                    Some(format!("Go to line: {input}"))
+                }
+                Some(query) if find_prompt => {
+                    let matches = text_box.find_matches().len();
+                    Some(format!("Find: {query} ({matches} matches)"))
+                }
+                _ => None,
+            };
+            if let Some(prompt_text) = prompt_text {
+                debug_info_text.clear();
+                debug_info_text.push_string(renderer.loaded_font.get_string_lossy(prompt_text));
+                debug_info_status = None;
+            } else {
+                let mut status = text_box.status_string();
+                if buffers.is_split() {
+                    let pane = if buffers.focus_left() {
+                        "Left"
+                    } else {
+                        "Right"
+                    };
+                    status = format!("{pane} pane; {status}");
+                }
+                refresh_text(
+                    &mut debug_info_text,
+                    &mut renderer.loaded_font,
+                    &mut debug_info_status,
+                    status,
+                );
+            }
+            refresh_text(
+                &mut tab_strip,
+                &mut renderer.loaded_font,
+                &mut tab_strip_status,
+                tab_strip_text(&buffers),
+            );
+            tab_strip
                 .render_all(&mut renderer, 0, 0)
                 .map_err(|err| {
-                    eprintln!("Could not render text to canvas: {err}");
+                    error!("Could not render the tab strip to canvas: {err}");
                 })
                 .unwrap();
 
-            let cursor_col = text_box.get_cursor_col();
-            let cursor_row = text_box.get_cursor_row();
-            let debug_text = renderer
-                .loaded_font
-                .get_string(format!("Line: {cursor_row}; Char: {cursor_col}"))?;
-            debug_info_text.clear();
-            debug_info_text.push_string(debug_text);
             debug_info_text
                 .render_all(&mut renderer, 0, debug_info_render_height)
                 .map_err(|err| {
-                    eprintln!("Could not render cursor position info to canvas: {err}");
+                    error!("Could not render cursor position info to canvas: {err}");
                 })
                 .unwrap();
 
+            renderer.draw_atlas_debug().unwrap_or_else(|err| {
+                error!("Could not draw the atlas inspector: {err}");
+            });
+
+            if take_screenshot {
+                let path = Path::new(SCREENSHOT_FILE);
+                match renderer.save_screenshot(path) {
+                    Ok(()) => info!("Saved screenshot to {SCREENSHOT_FILE}"),
+                    Err(err) => error!("{err}"),
+                }
+                take_screenshot = false;
+            }
+            renderer.canvas.set_clip_rect(None);
             renderer.canvas.present();
-            need_update = false;
+            last_frame = Instant::now();
         }
-
-        std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
-    println!("Final text buffer:\n{text}", text = text_box.get_text());
+    debug!(
+        "Final text buffer:\n{text}",
+        text = buffers.active().get_text()
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRINTABLE_ASCII: Range<u32> = 0x20..0x7f;
+
+    /// SDL may only be initialized by one test at a time
+    static SDL_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Run `test` with a software canvas on a hidden window of the dummy video driver
+    fn with_canvas<R>(width: u32, height: u32, test: impl FnOnce(Canvas<Window>) -> R) -> R {
+        let _guard = SDL_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let sdl_context = sdl2::init().unwrap();
+        let window = sdl_context
+            .video()
+            .unwrap()
+            .window("saute test", width, height)
+            .hidden()
+            .build()
+            .unwrap();
+        test(window.into_canvas().software().build().unwrap())
+    }
+
+    fn rasterize(font_size: u32, limits: (u32, u32)) -> RasterizedFont {
+        rasterize_font(
+            Rc::new(BUNDLED_FONT.to_vec()),
+            font_size,
+            &[PRINTABLE_ASCII],
+            limits,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn glyph_bboxes_have_the_size_of_their_bitmap() {
+        use freetype::face::LoadFlag;
+
+        let font_size = 19;
+        let rasterized = rasterize(font_size, (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT));
+        let mut font = rasterized.font;
+        for (ch, _, _, page, place) in &rasterized.bitmaps {
+            let fch = font.get_char(*ch as usize).unwrap();
+            assert_eq!(fch.bbox, *place);
+            assert_eq!(fch.atlas, FontDef::atlas_key(*page));
+            assert!(fch.bbox.bottom() as u32 <= rasterized.page_heights[*page]);
+        }
+
+        let lib = freetype::Library::init().unwrap();
+        let face = lib.new_memory_face(BUNDLED_FONT.to_vec(), 0).unwrap();
+        face.set_pixel_sizes(font_size, 0).unwrap();
+        face.load_char('A' as usize, LoadFlag::RENDER).unwrap();
+        let bitmap = face.glyph().bitmap();
+        let bbox = font.get_char('A' as usize).unwrap().bbox;
+        assert_eq!(bbox.width(), bitmap.width() as u32);
+        assert_eq!(bbox.height(), bitmap.rows() as u32);
+    }
+
+    #[test]
+    fn every_glyph_is_found_in_a_multi_row_atlas() {
+        // narrow enough for many shelves, low enough for several pages
+        let rasterized = rasterize(24, (128, 96));
+        assert!(rasterized.page_heights.len() > 1);
+        let shelves: std::collections::HashSet<(usize, i32)> = rasterized
+            .bitmaps
+            .iter()
+            .map(|(_, _, _, page, place)| (*page, place.y()))
+            .collect();
+        assert!(shelves.len() > rasterized.page_heights.len());
+        let mut font = rasterized.font;
+        for ch in PRINTABLE_ASCII.filter_map(char::from_u32) {
+            assert_eq!(font.get_char(ch as usize).unwrap().ch, ch);
+        }
+        // all of them came from the atlas, none had to be rasterized on demand
+        assert!(font.pending_uploads.is_empty());
+    }
+
+    #[test]
+    fn latin1_glyphs_come_from_the_atlas_once_requested() {
+        let rasterized = rasterize_font(
+            Rc::new(BUNDLED_FONT.to_vec()),
+            FONT_SIZE,
+            &[ANSI_RANGE, LATIN1_SUPPLEMENT_RANGE],
+            (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT),
+        )
+        .unwrap();
+        let mut font = rasterized.font;
+        assert_eq!(font.get_char(0xE9).unwrap().ch, '\u{e9}');
+        assert!(font.pending_uploads.is_empty());
+    }
+
+    #[test]
+    fn whitespace_is_as_wide_as_the_space_glyph() {
+        let mut font = rasterize(FONT_SIZE, (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT)).font;
+        let space = font.get_char(' ' as usize).unwrap();
+        assert!(space._ax > 0);
+        assert_eq!(font.whitespace_width, space._ax);
+    }
+
+    #[test]
+    fn kerned_pairs_get_their_offset() {
+        let font = rasterize_font(
+            Rc::new(include_bytes!("../fonts/Arial.ttf").to_vec()),
+            FONT_SIZE,
+            &[PRINTABLE_ASCII],
+            (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT),
+        )
+        .unwrap()
+        .font;
+        assert!(font.kerning('A', 'V') < 0);
+        assert_eq!(font.kerning('x', 'x'), 0);
+    }
+
+    #[test]
+    fn cached_quads_match_a_fresh_alignment() {
+        let mut font = rasterize(FONT_SIZE, (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT)).font;
+        // glyphs from the atlas and one rasterized on demand
+        for ch in ['A', 'g', '|', '\u{e9}'] {
+            let fch = font.get_char(ch as usize).unwrap();
+            let fresh = FontDef::aligned_quad(font.max_ascent, font.glyph_height, &fch);
+            assert_eq!(fch.quad, fresh, "quad of {ch:?}");
+            let moved = Rect::new(
+                fresh.x() + 10,
+                fresh.y() + 20,
+                fresh.width(),
+                fresh.height(),
+            );
+            assert_eq!(font.get_char_aligned_rect(10, 20, &fch), moved);
+        }
+    }
+
+    #[test]
+    fn atlas_limits_shrink_to_what_the_renderer_supports() {
+        assert_eq!(
+            clamp_atlas_limits((16384, 16384), (8192, 4096)),
+            (8192, 4096)
+        );
+        assert_eq!(clamp_atlas_limits((256, 128), (8192, 8192)), (256, 128));
+        assert_eq!(clamp_atlas_limits((256, 128), (0, 64)), (256, 64));
+
+        let rasterized = rasterize(FONT_SIZE, (256, 128));
+        assert!(rasterized.page_heights.len() > 1);
+        assert!(rasterized.width <= 256);
+        assert!(rasterized.page_heights.iter().all(|&height| height <= 128));
+        for (_, _, _, page, place) in &rasterized.bitmaps {
+            assert!(place.right() as u32 <= rasterized.width);
+            assert!(place.bottom() as u32 <= rasterized.page_heights[*page]);
+        }
+    }
+
+    #[test]
+    fn wide_coverage_spills_into_more_atlases() {
+        let ranges = [
+            ANSI_RANGE,
+            LATIN1_SUPPLEMENT_RANGE,
+            0x100..0x250,
+            0x370..0x400,
+            0x400..0x500,
+        ];
+        let rasterized = rasterize_font(
+            Rc::new(BUNDLED_FONT.to_vec()),
+            FONT_SIZE,
+            &ranges,
+            (512, 512),
+        )
+        .unwrap();
+        let mut font = rasterized.font;
+        assert!(font.atlas_count() > 1);
+        assert_eq!(font.atlas_count(), rasterized.page_heights.len());
+        for (ch, _, _, page, _) in &rasterized.bitmaps {
+            assert_eq!(
+                font.get_char(*ch as usize).unwrap().atlas,
+                FontDef::atlas_key(*page),
+                "atlas of {ch:?}"
+            );
+        }
+        assert!(font.pending_uploads.is_empty());
+        // glyphs rasterized later go to pages after the atlases
+        let lazy = font.get_char('\u{2192}' as usize).unwrap();
+        assert_eq!(lazy.atlas, FontDef::atlas_key(font.atlas_count()));
+    }
+
+    #[test]
+    fn letterbox_keeps_the_aspect_ratio() {
+        let window = Rect::new(0, 0, 800, 600);
+        // a wide atlas fills the width and is centered vertically
+        assert_eq!(letterbox(1024, 256, window), Rect::new(0, 200, 800, 200));
+        // a tall atlas fills the height and is centered horizontally
+        assert_eq!(letterbox(256, 1024, window), Rect::new(325, 0, 150, 600));
+        assert_eq!(letterbox(400, 300, window), window);
+        // the offset of the area is kept
+        assert_eq!(
+            letterbox(100, 100, Rect::new(10, 20, 200, 100)),
+            Rect::new(60, 20, 100, 100)
+        );
+    }
+
+    #[test]
+    fn font_keeps_the_requested_size_and_metrics() {
+        for font_size in [11, 32, 47] {
+            let font = rasterize(font_size, (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT)).font;
+            assert_eq!(font.font_pixel_size, font_size);
+            assert_eq!(font.glyph_height, font.max_ascent + font.max_descent);
+            assert!(font.max_ascent > font.max_descent);
+        }
+    }
+
+    #[test]
+    fn declared_metrics_match_the_face() {
+        use freetype::Library;
+
+        let lib = Library::init().unwrap();
+        let face = lib.new_memory_face(BUNDLED_FONT.to_vec(), 0).unwrap();
+        face.set_pixel_sizes(FONT_SIZE, 0).unwrap();
+        let metrics = face.size_metrics().unwrap();
+
+        let font = rasterize(FONT_SIZE, (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT)).font;
+        assert_eq!(font.ascent(), (metrics.ascender >> 6) as i32);
+        assert!(font.ascent() > 0);
+        assert!(font.descent() > 0);
+        assert_eq!(
+            font.ascent() + font.descent() + font.line_gap(),
+            font.line_height() as i32
+        );
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    fn ligature_replaces_the_glyphs_of_its_cluster() {
+        let data = shaping::tests::with_not_equal_ligature(BUNDLED_FONT);
+        let mut font = rasterize_font(Rc::new(data), FONT_SIZE, &[ANSI_RANGE], (1024, 1024))
+            .unwrap()
+            .font;
+        let substitutes = font.shape_string("a != b");
+        let [(2, Some(ligature)), (3, None)] = &substitutes[..] else {
+            panic!("unexpected substitutes {substitutes:?}");
+        };
+        // the glyph goes with the first character of the cluster, but is not its plain glyph
+        assert_eq!(ligature.ch, '!');
+        assert!(!Rc::ptr_eq(ligature, &font.get_char('!' as usize).unwrap()));
+        assert!(font.shape_string("a = b").is_empty());
+    }
+
+    #[test]
+    fn empty_or_corrupt_font_is_an_error() {
+        let limits = (ATLAS_MAX_WIDTH, ATLAS_MAX_HEIGHT);
+        for data in [Vec::new(), b"definitely not a font file".repeat(8)] {
+            assert!(rasterize_font(Rc::new(data), FONT_SIZE, &[PRINTABLE_ASCII], limits).is_err());
+        }
+    }
+
+    #[test]
+    fn configured_theme_reaches_the_renderer() {
+        with_canvas(64, 48, |canvas| {
+            let texture_creator = canvas.texture_creator();
+            let config = Config {
+                theme: "light".to_owned(),
+                ..Config::default()
+            };
+            let mut renderer = RendererBuilder::new(canvas, &texture_creator, 64, 48)
+                .theme(config.theme())
+                .build()
+                .unwrap();
+            assert_eq!(renderer.theme, Theme::light());
+            assert_eq!(renderer.theme.background, Theme::light().background);
+
+            renderer.dirty.take();
+            renderer.set_theme(Theme::dark());
+            assert_eq!(renderer.theme.foreground, Theme::dark().foreground);
+            assert_eq!(renderer.dirty.take(), Some(Rect::new(0, 0, 64, 48)));
+        });
+    }
+
+    #[test]
+    fn missing_font_falls_back_to_the_bundled_one() {
+        let (data, font_path) = read_font_or_bundled(String::from("fonts/does-not-exist.ttf"));
+        assert_eq!(font_path, "<memory>");
+        assert_eq!(data, BUNDLED_FONT);
+        let mut font = rasterize_font(Rc::new(data), FONT_SIZE, &[ANSI_RANGE], (1024, 1024))
+            .unwrap()
+            .font;
+        assert!(font.glyph_height > 0);
+        let glyph = font.get_char('a' as usize).unwrap();
+        assert!(glyph.bbox.width() > 0);
+    }
+
+    #[test]
+    fn glyphs_missing_from_the_primary_face_come_from_the_fallback() {
+        use freetype::Library;
+
+        // the math symbols of DejaVu Sans Mono are not in Arial
+        const FOR_ALL: usize = '\u{2200}' as usize;
+        let arial = std::fs::read("fonts/Arial.ttf").unwrap();
+        let lib = Library::init().unwrap();
+        let primary = lib.new_memory_face(arial.clone(), 0).unwrap();
+        assert_eq!(primary.get_char_index(FOR_ALL), 0);
+        let fallback = lib.new_memory_face(BUNDLED_FONT.to_vec(), 0).unwrap();
+        assert_ne!(fallback.get_char_index(FOR_ALL), 0);
+        fallback.set_pixel_sizes(FONT_SIZE, 0).unwrap();
+
+        let mut font = rasterize_font(Rc::new(arial), FONT_SIZE, &[ANSI_RANGE], (1024, 1024))
+            .unwrap()
+            .font;
+        font.add_fallback_face(fallback);
+        assert_eq!(font.fallback_face_count(), 1);
+        let glyph = font.get_char(FOR_ALL).unwrap();
+        assert_eq!(glyph.face, 1);
+        assert!(glyph.bbox.width() > 0);
+        assert!(!Rc::ptr_eq(&glyph, font.fallback.as_ref().unwrap()));
+        // glyphs of the primary face are not affected
+        assert_eq!(font.get_char('a' as usize).unwrap().face, 0);
+    }
+
+    #[test]
+    fn building_from_bytes_fills_the_char_lookup() {
+        with_canvas(64, 48, |canvas| {
+            let texture_creator = canvas.texture_creator();
+            let mut renderer = RendererBuilder::new(canvas, &texture_creator, 64, 48)
+                .build()
+                .unwrap();
+            renderer.build_atlas_from_bytes(BUNDLED_FONT, 16).unwrap();
+            assert_eq!(renderer.font_path, "<memory>");
+            let font = &renderer.loaded_font;
+            for ch in PRINTABLE_ASCII.filter_map(char::from_u32) {
+                assert!(font.char_lookup.contains_key(&(ch as usize)), "{ch:?}");
+            }
+            assert!(renderer
+                .texture_manager
+                .get(&FontDef::atlas_key(0))
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn zooming_in_grows_the_glyphs_and_the_rows() {
+        with_canvas(64, 48, |canvas| {
+            let texture_creator = canvas.texture_creator();
+            let mut renderer = RendererBuilder::new(canvas, &texture_creator, 64, 48)
+                .build()
+                .unwrap();
+            renderer.build_atlas_from_bytes(BUNDLED_FONT, 16).unwrap();
+            let mut text_box = TextScreen::new(64, 48, renderer.loaded_font.glyph_height as usize);
+            text_box.push_string(renderer.loaded_font.get_string_lossy("zoom\nin"));
+            let (glyph_height, row_height) =
+                (renderer.loaded_font.glyph_height, text_box.row_height());
+
+            renderer.rebuild_atlas(16 + FONT_SIZE_STEP).unwrap();
+            text_box.reload_glyphs(&mut renderer.loaded_font);
+            assert!(renderer.loaded_font.glyph_height > glyph_height);
+            assert!(text_box.row_height() > row_height);
+            assert_eq!(text_box.get_text(), "zoom\nin");
+        });
+    }
+
+    #[test]
+    fn event_loop_sleeps_until_the_next_deadline() {
+        let buffers = BufferSet::new(TextScreen::new(800, 600, 10));
+        let mut key_repeat = KeyRepeat::new(Duration::from_millis(300), Duration::from_millis(50));
+        let now = Instant::now();
+        // idle, only the caret blink wakes the loop
+        assert_eq!(
+            event_timeout(now, &buffers, &key_repeat),
+            Duration::from_millis(500)
+        );
+        key_repeat.press(Keycode::Left, sdl2::keyboard::Mod::NOMOD, now);
+        assert_eq!(
+            event_timeout(now, &buffers, &key_repeat),
+            Duration::from_millis(300)
+        );
+        // an overdue repeat does not sleep at all
+        let late = now + Duration::from_millis(400);
+        assert_eq!(event_timeout(late, &buffers, &key_repeat), Duration::ZERO);
+        key_repeat.release(Keycode::Left);
+        assert_eq!(
+            event_timeout(now, &buffers, &key_repeat),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn debug_overlay_only_draws_when_enabled() {
+        with_canvas(64, 48, |canvas| {
+            let texture_creator = canvas.texture_creator();
+            let mut renderer = RendererBuilder::new(canvas, &texture_creator, 64, 48)
+                .build()
+                .unwrap();
+            renderer.build_atlas_from_bytes(BUNDLED_FONT, 16).unwrap();
+            let mut text_box = TextScreen::new(64, 48, renderer.loaded_font.glyph_height as usize);
+            text_box.enable_line_cache(0);
+            text_box.push_string(renderer.loaded_font.get_string_lossy("Hi"));
+            let frame = |renderer: &mut Renderer<'_>, text_box: &mut TextScreen| {
+                text_box.invalidate_lines();
+                text_box.render_all(renderer, 0, 0).unwrap();
+                renderer
+                    .canvas
+                    .read_pixels(None, PixelFormatEnum::RGB24)
+                    .unwrap()
+            };
+
+            let plain = frame(&mut renderer, &mut text_box);
+            renderer.debug_overlay = true;
+            let overlay = frame(&mut renderer, &mut text_box);
+            assert_ne!(plain, overlay);
+            renderer.debug_overlay = false;
+            assert_eq!(frame(&mut renderer, &mut text_box), plain);
+        });
+    }
+
+    #[test]
+    fn screenshot_has_the_size_of_the_canvas() {
+        let path = std::env::temp_dir().join(format!("saute-shot-{}.png", std::process::id()));
+        with_canvas(64, 48, |canvas| {
+            let texture_creator = canvas.texture_creator();
+            let mut renderer = RendererBuilder::new(canvas, &texture_creator, 64, 48)
+                .build()
+                .unwrap();
+            renderer.build_atlas_from_bytes(BUNDLED_FONT, 16).unwrap();
+            renderer.canvas.set_draw_color(renderer.theme.background);
+            renderer.canvas.clear();
+            for (idx, ch) in "Hi".chars().enumerate() {
+                let fch = renderer.loaded_font.get_char(ch as usize).unwrap();
+                fch.render(&mut renderer, 4 + idx as u32 * 10, 4).unwrap();
+            }
+            renderer.save_screenshot(&path).unwrap();
+        });
+        let image = image::open(&path).map(|image| image.to_rgb8());
+        std::fs::remove_file(&path).unwrap();
+        let image = image.unwrap();
+        assert_eq!(image.dimensions(), (64, 48));
+        // the glyphs left their mark on the background
+        let background = Theme::default().background;
+        assert!(image
+            .pixels()
+            .any(|pixel| pixel.0 != [background.r, background.g, background.b]));
+    }
+
+    #[test]
+    fn dirty_region_merges_into_the_bounding_box() {
+        let mut dirty = DirtyRegion::default();
+        assert_eq!(dirty.take(), None);
+        dirty.mark(Rect::new(10, 10, 20, 5));
+        dirty.mark(Rect::new(50, 40, 10, 10));
+        dirty.mark(Rect::new(15, 12, 2, 2));
+        assert_eq!(dirty.take(), Some(Rect::new(10, 10, 50, 40)));
+        // taking it resets the region
+        assert_eq!(dirty.take(), None);
+    }
+
+    #[test]
+    fn pasting_two_lines_moves_the_cursor_down() {
+        let mut font = res_man::tests::monospace_font(8);
+        let mut text_box = TextScreen::new(800, 600, 10);
+        text_box.set_composition("pending".to_owned());
+        insert_text(&mut text_box, &mut font, "first\nsecond".to_owned());
+        assert_eq!(text_box.get_text(), "first\nsecond");
+        assert_eq!(text_box.get_cursor_row(), 1);
+        assert_eq!(text_box.get_cursor_col(), 6);
+        assert_eq!(text_box.composition(), None);
+    }
+
+    #[test]
+    fn composition_stays_out_of_the_text_until_committed() {
+        let mut font = res_man::tests::monospace_font(8);
+        let mut text_box = TextScreen::new(800, 600, 10);
+        insert_text(&mut text_box, &mut font, "ab".to_owned());
+        assert_eq!(text_box.composition(), None);
+
+        // every editing event replaces the composition, the text is left alone
+        text_box.set_composition("k".to_owned());
+        text_box.set_composition("ka".to_owned());
+        assert_eq!(text_box.composition(), Some("ka"));
+        assert_eq!(text_box.get_text(), "ab");
+        assert_eq!(text_box.get_cursor_col(), 2);
+
+        // an empty edit cancels the composition
+        text_box.set_composition(String::new());
+        assert_eq!(text_box.composition(), None);
+        assert_eq!(text_box.get_text(), "ab");
+
+        // the commit arrives as text input and ends the composition
+        text_box.set_composition("ka".to_owned());
+        insert_text(&mut text_box, &mut font, "ka".to_owned());
+        assert_eq!(text_box.composition(), None);
+        assert_eq!(text_box.get_text(), "abka");
+        assert_eq!(text_box.get_cursor_col(), 4);
+    }
+
+    #[test]
+    fn opening_a_file_at_startup_loads_it_with_the_cursor_at_the_start() {
+        let path = std::env::temp_dir().join(format!("saute-open-{}.txt", std::process::id()));
+        std::fs::write(&path, "fn main() {}\n").unwrap();
+        let mut font = res_man::tests::monospace_font(8);
+        let mut text_box = TextScreen::new(800, 600, 10);
+        let opened = open_file(&mut text_box, &mut font, &path);
+        std::fs::remove_file(&path).unwrap();
+        opened.unwrap();
+        assert_eq!(text_box.get_text(), "fn main() {}\n");
+        assert_eq!(
+            (text_box.get_cursor_row(), text_box.get_cursor_col()),
+            (0, 0)
+        );
+
+        // a missing file leaves the buffer empty
+        let mut text_box = TextScreen::new(800, 600, 10);
+        assert!(open_file(&mut text_box, &mut font, &path).is_err());
+        assert_eq!(text_box.get_text(), "");
+    }
+
+    #[test]
+    fn opening_a_file_resets_a_dirty_buffer() {
+        let path = std::env::temp_dir().join(format!("saute-reset-{}.txt", std::process::id()));
+        std::fs::write(&path, "from disk").unwrap();
+        let mut font = res_man::tests::monospace_font(8);
+        let mut text_box = TextScreen::new(800, 600, 10);
+        insert_text(&mut text_box, &mut font, "unsaved\nedits".to_owned());
+        text_box.update_selection(true);
+        text_box.move_cursor_to(2);
+        assert!(text_box.is_dirty());
+
+        let opened = open_file(&mut text_box, &mut font, &path);
+        std::fs::remove_file(&path).unwrap();
+        opened.unwrap();
+        assert_eq!(text_box.get_text(), "from disk");
+        assert!(!text_box.is_dirty());
+        assert_eq!(text_box.get_cursor_row(), 0);
+        assert_eq!(text_box.get_cursor_col(), 0);
+        assert_eq!(text_box.selection_range(), None);
+        // the edits before the file was opened cannot be undone into it
+        assert!(!text_box.undo());
+        assert_eq!(text_box.get_text(), "from disk");
+    }
+
+    #[test]
+    fn window_title_names_the_file_and_marks_changes() {
+        let path = Path::new("/tmp/notes/todo.txt");
+        assert_eq!(
+            window_title(Some(path), false),
+            "todo.txt - Saute Text Editor"
+        );
+        assert_eq!(
+            window_title(Some(path), true),
+            "todo.txt* - Saute Text Editor"
+        );
+        assert_eq!(window_title(None, false), "untitled - Saute Text Editor");
+        assert_eq!(window_title(None, true), "untitled* - Saute Text Editor");
+    }
+
+    #[test]
+    fn status_text_is_only_rebuilt_when_it_changes() {
+        let mut font = res_man::tests::monospace_font(8);
+        let mut status_bar = TextScreen::new(800, 10, 10);
+        let mut shown = None;
+        assert!(refresh_text(
+            &mut status_bar,
+            &mut font,
+            &mut shown,
+            "Ln 1".to_owned()
+        ));
+        let revision = status_bar.revision();
+        assert!(!refresh_text(
+            &mut status_bar,
+            &mut font,
+            &mut shown,
+            "Ln 1".to_owned()
+        ));
+        assert_eq!(status_bar.revision(), revision);
+        assert!(refresh_text(
+            &mut status_bar,
+            &mut font,
+            &mut shown,
+            "Ln 2".to_owned()
+        ));
+        assert_eq!(status_bar.get_text(), "Ln 2");
+        // forgetting the shown text forces a rebuild, e.g. after the font changed
+        shown = None;
+        assert!(refresh_text(
+            &mut status_bar,
+            &mut font,
+            &mut shown,
+            "Ln 2".to_owned()
+        ));
+    }
+
+    #[test]
+    fn trailing_whitespace_is_trimmed_from_every_line() {
+        let text = "tabs\t\t\nmixed \t \n  indent kept\nlast \t";
+        assert_eq!(
+            trim_trailing_whitespace(text, true, false),
+            "tabs\nmixed\n  indent kept\nlast"
+        );
+        // without trimming only the final line break is touched
+        assert_eq!(trim_trailing_whitespace(text, false, false), text);
+        assert_eq!(
+            trim_trailing_whitespace(text, true, true),
+            "tabs\nmixed\n  indent kept\nlast\n"
+        );
+    }
+
+    #[test]
+    fn saved_text_ends_in_exactly_one_line_break() {
+        assert_eq!(trim_trailing_whitespace("end", false, true), "end\n");
+        assert_eq!(trim_trailing_whitespace("end\n", false, true), "end\n");
+        assert_eq!(trim_trailing_whitespace("end\n\n\n", false, true), "end\n");
+        // a final line of only whitespace goes away once trimmed
+        assert_eq!(trim_trailing_whitespace("end\n \t", true, true), "end\n");
+        assert_eq!(
+            trim_trailing_whitespace("end\n \t", false, true),
+            "end\n \t\n"
+        );
+        assert_eq!(trim_trailing_whitespace("", true, true), "");
+    }
+
+    #[test]
+    fn crlf_files_load_as_lf_and_save_as_crlf() {
+        let path = std::env::temp_dir().join(format!("saute-crlf-{}.txt", std::process::id()));
+        let original = "first\r\nsecond\r\n\r\nlast";
+        std::fs::write(&path, original).unwrap();
+        let mut font = res_man::tests::monospace_font(8);
+        let mut text_box = TextScreen::new(800, 600, 10);
+        open_file(&mut text_box, &mut font, &path).unwrap();
+        assert_eq!(text_box.get_text(), "first\nsecond\n\nlast");
+        assert_eq!(text_box.line_ending(), LineEnding::CrLf);
+
+        let config = Config {
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            ..Config::default()
+        };
+        save_file(&mut text_box, &path, &config).unwrap();
+        let saved = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.unwrap(), original);
+    }
+}
@@ -0,0 +1,130 @@
+use sdl2::rect::Rect;
+
+/// Empty pixels kept between neighbouring glyphs, so scaled copies do not bleed into each other
+const GLYPH_PADDING: u32 = 1;
+
+/// Result of packing rectangles into one or more pages
+#[derive(Debug, Clone)]
+pub struct Packing {
+    /// Page and position of every rectangle, in the order they were passed in
+    pub places: Vec<(usize, Rect)>,
+    pub width: u32,
+    /// Used height of every page
+    pub page_heights: Vec<u32>,
+}
+
+/// Shelf packer: rectangles are sorted by height and laid out left to right in rows ("shelves"),
+/// each as high as its first and tallest rectangle. A page that is full continues on the next one.
+#[derive(Debug, Clone)]
+pub struct ShelfPacker {
+    width: u32,
+    max_height: u32,
+}
+
+impl ShelfPacker {
+    /// Pack into pages at most `max_width` x `max_height` pixels big
+    pub fn new(max_width: u32, max_height: u32) -> Self {
+        ShelfPacker {
+            width: max_width,
+            max_height,
+        }
+    }
+
+    /// Pack `(width, height)` sizes. Empty sizes still get a blank pixel of their own.
+    /// The pages are made roughly square instead of using the whole maximum width.
+    pub fn pack(&self, sizes: &[(u32, u32)]) -> Result<Packing, String> {
+        let padded: Vec<(u32, u32)> = sizes
+            .iter()
+            .map(|(w, h)| (w.max(&1) + GLYPH_PADDING, h.max(&1) + GLYPH_PADDING))
+            .collect();
+        if let Some((w, h)) = padded
+            .iter()
+            .find(|(w, h)| *w > self.width || *h > self.max_height)
+        {
+            return Err(format!(
+                "A {w}x{h} glyph does not fit into the atlas limit of {}x{}",
+                self.width, self.max_height
+            ));
+        }
+        let area: u64 = padded.iter().map(|(w, h)| *w as u64 * *h as u64).sum();
+        let widest = padded.iter().map(|(w, _)| *w).max().unwrap_or(1);
+        let width = ((area as f64).sqrt().ceil() as u32)
+            .max(widest)
+            .min(self.width);
+
+        let mut order: Vec<usize> = (0..padded.len()).collect();
+        order.sort_by_key(|idx| std::cmp::Reverse(padded[*idx].1));
+
+        let mut places = vec![(0, Rect::new(0, 0, 1, 1)); sizes.len()];
+        let mut page_heights: Vec<u32> = vec![0];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for idx in order {
+            let (w, h) = padded[idx];
+            if x + w > width {
+                y += shelf_height;
+                x = 0;
+                shelf_height = 0;
+            }
+            if y + h > self.max_height {
+                page_heights.push(0);
+                y = 0;
+                x = 0;
+                shelf_height = 0;
+            }
+            shelf_height = shelf_height.max(h);
+            let page = page_heights.len() - 1;
+            page_heights[page] = page_heights[page].max(y + h);
+            let (sw, sh) = sizes[idx];
+            places[idx] = (page, Rect::new(x as i32, y as i32, sw.max(1), sh.max(1)));
+            x += w;
+        }
+
+        Ok(Packing {
+            places,
+            width,
+            page_heights,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlap(a: &Rect, b: &Rect) -> bool {
+        a.left() < b.right() && b.left() < a.right() && a.top() < b.bottom() && b.top() < a.bottom()
+    }
+
+    fn sizes() -> Vec<(u32, u32)> {
+        (0..200u32)
+            .map(|n| (3 + n * 7 % 19, 5 + n * 11 % 23))
+            .chain([(0, 0), (40, 2)])
+            .collect()
+    }
+
+    #[test]
+    fn packed_places_do_not_overlap() {
+        let sizes = sizes();
+        let packing = ShelfPacker::new(128, 128).pack(&sizes).unwrap();
+        assert!(packing.page_heights.len() > 1);
+        for (idx, ((page, place), (w, h))) in packing.places.iter().zip(&sizes).enumerate() {
+            assert_eq!((place.width(), place.height()), ((*w).max(1), (*h).max(1)));
+            assert!(place.right() as u32 <= packing.width);
+            assert!(place.bottom() as u32 <= packing.page_heights[*page]);
+            for (other_page, other) in &packing.places[idx + 1..] {
+                assert!(page != other_page || !overlap(place, other));
+            }
+        }
+    }
+
+    #[test]
+    fn packing_uses_less_space_than_a_grid() {
+        let sizes = sizes();
+        let packing = ShelfPacker::new(4096, 4096).pack(&sizes).unwrap();
+        assert_eq!(packing.page_heights.len(), 1);
+        let cell_width = sizes.iter().map(|(w, _)| w).max().unwrap() + GLYPH_PADDING;
+        let cell_height = sizes.iter().map(|(_, h)| h).max().unwrap() + GLYPH_PADDING;
+        let grid_area = sizes.len() as u32 * cell_width * cell_height;
+        assert!(packing.width * packing.page_heights[0] < grid_area);
+    }
+}
@@ -1,11 +1,25 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, hash::Hash, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Display,
+    hash::Hash,
+    rc::Rc,
+};
 
+use freetype::Face;
+use log::{debug, error};
 use sdl2::rect::Rect;
 
 pub trait ResourceLoader<'l, R> {
     type Args: ?Sized;
     fn load(&'l self, data: &Self::Args) -> Result<R, String>;
-    fn create(&'l self, w: u32, h: u32) -> R;
+    fn create(&'l self, w: u32, h: u32) -> Result<R, String>;
+}
+
+/// A table entry, stamped with the tick it was last accessed at
+struct Entry<R> {
+    resource: Rc<RefCell<R>>,
+    last_used: Cell<u64>,
 }
 
 pub struct ResourceManager<'l, K, R, L>
@@ -14,7 +28,9 @@ where
     L: ResourceLoader<'l, R>,
 {
     loader: &'l L,
-    table: HashMap<K, Rc<RefCell<R>>>,
+    table: HashMap<K, Entry<R>>,
+    capacity: Option<usize>,
+    clock: Cell<u64>,
 }
 
 impl<'l, K, R, L> ResourceManager<'l, K, R, L>
@@ -26,24 +42,69 @@ where
         ResourceManager {
             table: HashMap::new(),
             loader: loader,
+            capacity: None,
+            clock: Cell::new(0),
+        }
+    }
+
+    /// Create a manager that holds at most `capacity` resources,
+    /// evicting the least recently used one when it overflows
+    pub fn with_capacity(loader: &'l L, capacity: usize) -> Self {
+        ResourceManager {
+            capacity: Some(capacity),
+            ..Self::new(loader)
         }
     }
 
+    fn tick(&self) -> u64 {
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        now
+    }
+
+    fn insert(&mut self, key: K, resource: R) -> Rc<RefCell<R>> {
+        let resource = Rc::new(RefCell::new(resource));
+        let entry = Entry {
+            resource: Rc::clone(&resource),
+            last_used: Cell::new(self.tick()),
+        };
+        self.table.insert(key, entry);
+        self.evict();
+        resource
+    }
+
+    /// Drop least recently used entries until the table fits its capacity.
+    /// Evicted resources stay alive for as long as someone else holds them.
+    fn evict(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        while self.table.len() > capacity {
+            let oldest = self
+                .table
+                .values()
+                .map(|entry| entry.last_used.get())
+                .min()
+                .unwrap();
+            self.table
+                .retain(|_, entry| entry.last_used.get() != oldest);
+        }
+    }
+
+    /// Create a blank resource under `key`.
+    /// Like `load`, this returns an error instead of replacing an existing resource.
     pub fn create<D>(&mut self, key: K, w: u32, h: u32) -> Result<Rc<RefCell<R>>, String>
     where
         L: ResourceLoader<'l, R, Args = D>,
+        D: ?Sized,
         K: 'l + Hash + Eq + Display,
     {
-        println!("Now creating new texture with dimensions {w}x{h} and ID {key}");
-        let tex = self.loader.create(w, h);
-        if let Some(_) = self.table.get(&key) {
-            let resource = Rc::new(RefCell::new(tex));
-            self.table.insert(key, Rc::clone(&resource));
-            Ok(resource.clone())
+        if self.table.contains_key(&key) {
+            Err(format!("Value with ID {key} already exists"))
         } else {
-            let resource = Rc::new(RefCell::new(tex));
-            self.table.insert(key, Rc::clone(&resource));
-            Ok(resource.clone())
+            debug!("Now creating new texture with dimensions {w}x{h} and ID {key}");
+            let tex = self.loader.create(w, h)?;
+            Ok(self.insert(key, tex))
         }
     }
 
@@ -55,17 +116,34 @@ where
         D: ?Sized + 'l,
         K: 'l + Hash + Eq,
     {
-        if let Some(_) = self.table.get(&key) {
+        if self.table.contains_key(&key) {
             Err(String::from("Value already exists"))
         } else {
-            let resource = Rc::new(RefCell::new(self.loader.load(details)?));
-            self.table.insert(key, Rc::clone(&resource));
-            Ok(resource.clone())
+            let resource = self.loader.load(details)?;
+            Ok(self.insert(key, resource))
         }
     }
 
     pub fn get(&self, key: &K) -> Option<Rc<RefCell<R>>> {
-        self.table.get(key).cloned()
+        self.table.get(key).map(|entry| {
+            entry.last_used.set(self.tick());
+            Rc::clone(&entry.resource)
+        })
+    }
+
+    /// Drop a resource from the table, users still holding it keep it alive
+    pub fn remove(&mut self, key: &K) -> Option<Rc<RefCell<R>>> {
+        self.table.remove(key).map(|entry| entry.resource)
+    }
+
+    #[inline]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.table.contains_key(key)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.table.len()
     }
 
     pub fn clear(&mut self) {
@@ -73,14 +151,56 @@ where
     }
 }
 
+/// Glyph slots per lazily allocated atlas page
+const GLYPH_PAGE_COLS: u32 = 32;
+const GLYPH_PAGE_ROWS: u32 = 8;
+
+/// Turn a FreeType coverage bitmap into white `RGBA8888` pixels, the coverage becoming the alpha
+pub fn coverage_to_rgba(coverage: &[u8]) -> Vec<u8> {
+    let mut rgba = Vec::<u8>::with_capacity(coverage.len() * 4);
+    for alpha in coverage {
+        rgba.extend_from_slice(&(0xFFFFFF00 | *alpha as u32).to_ne_bytes());
+    }
+    rgba
+}
+
+/// The top left `width` x `rows` pixels of an `RGBA8888` bitmap, without any padding
+fn clip_bitmap(rgba: &[u8], pitch: usize, width: u32, rows: u32) -> Vec<u8> {
+    let row_len = width as usize * 4;
+    rgba.chunks(pitch.max(1))
+        .take(rows as usize)
+        .flat_map(|row| &row[..row_len.min(row.len())])
+        .copied()
+        .collect()
+}
+
+/// Typeface variant a glyph was rasterized from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    #[default]
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
 #[derive(Debug, Clone)]
 pub struct FontChar {
     pub ch: char,
     pub bbox: Rect,
     pub _ax: u32,
     pub _ay: u32,
+    /// Horizontal advance with the fractional part FreeType reports in 26.6
+    pub advance: f32,
     pub bl: i32,
     pub bt: i32,
+    /// Texture key of the atlas holding this glyph
+    pub atlas: usize,
+    pub style: FontStyle,
+    /// Where the glyph is drawn relative to the pen position, see `FontDef::aligned_quad`
+    pub quad: Rect,
+    /// Face the glyph was rasterized from, 0 for the font itself and then the fallbacks in order
+    pub face: usize,
 }
 
 impl FontChar {
@@ -90,22 +210,49 @@ impl FontChar {
             bbox: Rect::new(0, 0, 0, 0),
             _ax: 0,
             _ay: 0,
+            advance: 0.0,
             bl: 0,
             bt: 0,
+            atlas: usize::MAX,
+            style: FontStyle::Regular,
+            quad: Rect::new(0, 0, 0, 0),
+            face: 0,
         }
     }
-    pub fn new(ch: char, bbox: Rect, _ax: u32, _ay: u32, bl: i32, bt: i32) -> Self {
+    pub fn new(ch: char, bbox: Rect, _ax: u32, _ay: u32, bl: i32, bt: i32, atlas: usize) -> Self {
         FontChar {
             ch,
             bbox,
             _ax,
             _ay,
+            advance: _ax as f32,
             bl,
             bt,
+            atlas,
+            style: FontStyle::Regular,
+            quad: Rect::new(0, 0, _ax, bbox.height()),
+            face: 0,
         }
     }
 }
 
+/// A new atlas texture that has to be created before its glyphs can be uploaded
+#[derive(Debug, Clone)]
+pub struct GlyphPage {
+    pub atlas: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Pixels of a lazily rasterized glyph, waiting to be copied into its atlas texture
+#[derive(Debug, Clone)]
+pub struct GlyphUpload {
+    pub atlas: usize,
+    pub dest: Rect,
+    pub pixels: Vec<u8>,
+    pub pitch: usize,
+}
+
 #[derive(Default, Clone)]
 pub struct FontDef {
     pub glyph_height: u32,
@@ -118,6 +265,33 @@ pub struct FontDef {
     pub max_back: u32,
     pub max_forward: u32,
     pub font_pixel_size: u32,
+    // vertical metrics the face declares, as opposed to the ones measured from the glyphs
+    ascent: i32,
+    descent: i32,
+    line_height: u32,
+    /// Glyph substituted for characters the font cannot provide
+    pub fallback: Option<Rc<FontChar>>,
+    kerning: HashMap<(char, char), i32>,
+    style_faces: HashMap<FontStyle, Face>,
+    /// Faces tried in order for glyphs the font itself lacks
+    fallback_faces: Vec<Face>,
+    styled_lookup: HashMap<(FontStyle, usize), Rc<FontChar>>,
+    /// Number of prebuilt atlas textures, the glyph pages come after them
+    atlas_count: usize,
+    // lazy rasterization state
+    face: Option<Face>,
+    slot_width: u32,
+    slot_height: u32,
+    page_count: usize,
+    next_slot: u32,
+    pub pending_pages: Vec<GlyphPage>,
+    pub pending_uploads: Vec<GlyphUpload>,
+    /// Contents of the font file, handed to rustybuzz for shaping
+    #[cfg(feature = "shaping")]
+    shaping_data: Rc<Vec<u8>>,
+    /// Glyphs rasterized by glyph index for shaped text
+    #[cfg(feature = "shaping")]
+    glyph_lookup: HashMap<u32, Rc<FontChar>>,
 }
 
 impl FontDef {
@@ -132,54 +306,350 @@ impl FontDef {
         max_forward: u32,
         font_pixel_size: u32,
     ) -> FontDef {
-        let avg_width: u32 =
-            char_lookup.values().map(|x| x.bbox.width()).sum::<u32>() / char_lookup.len() as u32;
+        // spaces advance by the width of the space glyph, if the font has none use the average
+        let whitespace_width = match char_lookup.get(&(' ' as usize)) {
+            Some(space) if space._ax > 0 => space._ax,
+            _ => {
+                char_lookup.values().map(|x| x.bbox.width()).sum::<u32>()
+                    / char_lookup.len().max(1) as u32
+            }
+        };
+        let char_lookup: HashMap<usize, Rc<FontChar>> = char_lookup
+            .into_iter()
+            .map(|(ch, fch)| {
+                let quad = Self::aligned_quad(max_ascent, max_height, &fch);
+                (
+                    ch,
+                    Rc::new(FontChar {
+                        quad,
+                        ..(*fch).clone()
+                    }),
+                )
+            })
+            .collect();
+        let fallback = char_lookup
+            .get(&(char::REPLACEMENT_CHARACTER as usize))
+            .cloned();
         FontDef {
+            fallback,
             char_lookup,
             char_spacing,
             glyph_height: max_height,
             glyph_width: max_width,
-            whitespace_width: avg_width,
+            whitespace_width,
             max_ascent,
             max_descent,
             max_back,
             max_forward,
             font_pixel_size,
+            ..Default::default()
         }
     }
-    /// Get the corrected position of a character
-    /// TODO: cache this information
-    pub fn get_char_aligned_rect(&self, x: i32, y: i32, info: &FontChar) -> Rect {
-        let x: i32 = x.into();
-        let y: i32 = y.into();
 
+    #[inline]
+    pub fn set_kerning(&mut self, kerning: HashMap<(char, char), i32>) {
+        self.kerning = kerning;
+    }
+
+    /// Horizontal adjustment between two consecutive characters
+    #[inline]
+    pub fn kerning(&self, a: char, b: char) -> i32 {
+        self.kerning.get(&(a, b)).copied().unwrap_or(0)
+    }
+
+    /// Store the vertical metrics declared by the face, in pixels.
+    /// `descent` is the distance below the baseline, so usually positive.
+    pub fn set_vertical_metrics(&mut self, ascent: i32, descent: i32, line_height: u32) {
+        self.ascent = ascent;
+        self.descent = descent;
+        self.line_height = line_height;
+    }
+
+    /// Distance from the baseline to the top of the tallest glyphs the face declares
+    #[inline]
+    pub fn ascent(&self) -> i32 {
+        self.ascent
+    }
+
+    /// Distance from the baseline to the bottom of the lowest glyphs the face declares
+    #[inline]
+    pub fn descent(&self) -> i32 {
+        self.descent
+    }
+
+    /// Extra space the face puts between lines
+    #[inline]
+    pub fn line_gap(&self) -> i32 {
+        self.line_height as i32 - self.ascent - self.descent
+    }
+
+    /// Distance between the baselines of two lines, never less than the height of the glyphs
+    #[inline]
+    pub fn line_height(&self) -> u32 {
+        self.line_height.max(self.glyph_height)
+    }
+
+    /// Number of glyph pages allocated for lazily rasterized glyphs
+    #[inline]
+    pub fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    #[inline]
+    pub fn atlas_count(&self) -> usize {
+        self.atlas_count
+    }
+
+    #[inline]
+    pub fn set_atlas_count(&mut self, atlas_count: usize) {
+        self.atlas_count = atlas_count;
+    }
+
+    /// Texture key of the atlas or glyph page with the given index.
+    /// Keys count down from `usize::MAX`, so they stay clear of other textures.
+    #[inline]
+    pub fn atlas_key(idx: usize) -> usize {
+        usize::MAX - idx
+    }
+
+    /// Keep the face around so glyphs missing from the atlas can be rasterized on demand.
+    /// Every slot of a glyph page is `slot_width` x `slot_height` pixels big.
+    pub fn set_face(&mut self, face: Face, slot_width: u32, slot_height: u32) {
+        self.face = Some(face);
+        self.slot_width = slot_width;
+        self.slot_height = slot_height;
+    }
+
+    /// Font file contents to shape text with, the same the face was loaded from
+    #[cfg(feature = "shaping")]
+    pub fn set_shaping_data(&mut self, data: Rc<Vec<u8>>) {
+        self.shaping_data = data;
+        self.glyph_lookup.clear();
+    }
+
+    /// Register the face used for a non-regular style.
+    /// Its glyphs are rasterized on demand into the glyph pages.
+    pub fn add_style_face(&mut self, style: FontStyle, face: Face) {
+        self.style_faces.insert(style, face);
+        self.styled_lookup
+            .retain(|(glyph_style, _), _| *glyph_style != style);
+    }
+
+    /// Append a face to the fallback chain.
+    /// Its glyphs are rasterized on demand into the glyph pages.
+    pub fn add_fallback_face(&mut self, face: Face) {
+        self.fallback_faces.push(face);
+    }
+
+    #[inline]
+    pub fn fallback_face_count(&self) -> usize {
+        self.fallback_faces.len()
+    }
+
+    #[inline]
+    pub fn has_style(&self, style: FontStyle) -> bool {
+        style == FontStyle::Regular || self.style_faces.contains_key(&style)
+    }
+
+    /// Rasterize a glyph that is not in the atlas yet and queue it for upload.
+    /// If the face of the style lacks the glyph, the fallback faces are tried in order.
+    /// A new glyph page is allocated whenever the current one is full.
+    fn rasterize_char(&mut self, char: usize, style: FontStyle) -> Result<Rc<FontChar>, ()> {
+        use freetype::face::LoadFlag;
+
+        let face = match style {
+            FontStyle::Regular => self.face.as_ref(),
+            _ => self.style_faces.get(&style),
+        };
+        let ch = char::from_u32(char as u32).ok_or(())?;
+        let (face_idx, face) = face
+            .into_iter()
+            .chain(&self.fallback_faces)
+            .enumerate()
+            .find(|(_, face)| face.get_char_index(char) != 0)
+            .ok_or(())?;
+        face.load_char(char, LoadFlag::RENDER)
+            .map_err(|err| error!("Could not load char {ch:?}: {err}"))?;
+
+        let glyph = face.glyph();
+        let bitmap = glyph.bitmap();
+        let rgba = coverage_to_rgba(bitmap.buffer());
+        let pitch = bitmap.pitch() as usize * 4;
+        let bitmap_width = bitmap.width() as u32;
+        let bitmap_rows = bitmap.rows() as u32;
+        let ax = glyph.advance().x as u32 >> 6;
+        let advance = glyph.advance().x as f32 / 64.0;
+        let ay = glyph.advance().y as u32 >> 6;
+        let bl = glyph.bitmap_left();
+        let bt = glyph.bitmap_top();
+        let (atlas, bbox) = self.place_bitmap(rgba, pitch, bitmap_width, bitmap_rows);
+
+        let mut entry = FontChar {
+            style,
+            advance,
+            face: face_idx,
+            ..FontChar::new(ch, bbox, ax, ay, bl, bt, atlas)
+        };
+        entry.quad = Self::aligned_quad(self.max_ascent, self.glyph_height, &entry);
+        let entry = Rc::new(entry);
+        match style {
+            FontStyle::Regular => self.char_lookup.insert(char, entry.clone()),
+            _ => self.styled_lookup.insert((style, char), entry.clone()),
+        };
+        Ok(entry)
+    }
+
+    /// Rasterize the glyph with the given index of the regular face, for shaped text.
+    /// `ch` is the first character of the cluster the glyph stands for.
+    #[cfg(feature = "shaping")]
+    fn rasterize_glyph(&mut self, glyph_id: u32, ch: char) -> Result<Rc<FontChar>, ()> {
+        use freetype::face::LoadFlag;
+
+        let face = self.face.as_ref().ok_or(())?;
+        face.load_glyph(glyph_id, LoadFlag::RENDER)
+            .map_err(|err| error!("Could not load glyph {glyph_id}: {err}"))?;
+
+        let glyph = face.glyph();
+        let bitmap = glyph.bitmap();
+        let rgba = coverage_to_rgba(bitmap.buffer());
+        let pitch = bitmap.pitch() as usize * 4;
+        let bitmap_width = bitmap.width() as u32;
+        let bitmap_rows = bitmap.rows() as u32;
+        let ax = glyph.advance().x as u32 >> 6;
+        let advance = glyph.advance().x as f32 / 64.0;
+        let ay = glyph.advance().y as u32 >> 6;
+        let bl = glyph.bitmap_left();
+        let bt = glyph.bitmap_top();
+        let (atlas, bbox) = self.place_bitmap(rgba, pitch, bitmap_width, bitmap_rows);
+
+        let mut entry = FontChar {
+            advance,
+            ..FontChar::new(ch, bbox, ax, ay, bl, bt, atlas)
+        };
+        entry.quad = Self::aligned_quad(self.max_ascent, self.glyph_height, &entry);
+        let entry = Rc::new(entry);
+        self.glyph_lookup.insert(glyph_id, entry.clone());
+        Ok(entry)
+    }
+
+    /// Reserve a slot in the current glyph page, allocating a new page if it is full,
+    /// and queue the bitmap for upload. Returns the atlas key and the part of the slot it covers.
+    fn place_bitmap(
+        &mut self,
+        rgba: Vec<u8>,
+        pitch: usize,
+        bitmap_width: u32,
+        bitmap_rows: u32,
+    ) -> (usize, Rect) {
+        if self.page_count == 0 || self.next_slot == GLYPH_PAGE_COLS * GLYPH_PAGE_ROWS {
+            self.page_count += 1;
+            self.next_slot = 0;
+            self.pending_pages.push(GlyphPage {
+                atlas: Self::atlas_key(self.atlas_count + self.page_count - 1),
+                width: GLYPH_PAGE_COLS * self.slot_width,
+                height: GLYPH_PAGE_ROWS * self.slot_height,
+            });
+        }
+        let atlas = Self::atlas_key(self.atlas_count + self.page_count - 1);
+        let x = (self.next_slot % GLYPH_PAGE_COLS * self.slot_width) as i32;
+        let y = (self.next_slot / GLYPH_PAGE_COLS * self.slot_height) as i32;
+        self.next_slot += 1;
+
+        // a glyph larger than its slot is cut off, it must not spill into the neighbouring slots
+        let width = bitmap_width.min(self.slot_width);
+        let rows = bitmap_rows.min(self.slot_height);
+        if width > 0 && rows > 0 {
+            self.pending_uploads.push(GlyphUpload {
+                atlas,
+                dest: Rect::new(x, y, width, rows),
+                pixels: clip_bitmap(&rgba, pitch, width, rows),
+                pitch: width as usize * 4,
+            });
+        }
+        (atlas, Rect::new(x, y, width, rows))
+    }
+    /// Offset and size of a glyph drawn at the pen position (0, 0), aligned to the baseline.
+    /// This only depends on the glyph and the font metrics, so it is computed once per glyph.
+    pub fn aligned_quad(max_ascent: u32, glyph_height: u32, info: &FontChar) -> Rect {
         //let align_lowest = self.glyph_height as i32 - info.height as i32;
 
-        let baseline_dist = self.max_ascent as i32 - info.bt;
-        let center_dist: i32 = self.max_forward as i32 + info.bl;
+        let baseline_dist = max_ascent as i32 - info.bt;
 
         if info.ch.is_whitespace() {
-            Rect::new(x, y, info._ax, self.glyph_height)
+            Rect::new(0, 0, info._ax, glyph_height)
         } else {
-            Rect::new(
-                x, /*+ center_dist*/
-                y + baseline_dist,
-                info.bbox.width(),
-                info.bbox.height(),
-            )
+            Rect::new(0, baseline_dist, info.bbox.width(), info.bbox.height())
+        }
+    }
+
+    /// Get the corrected position of a character
+    #[inline]
+    pub fn get_char_aligned_rect(&self, x: i32, y: i32, info: &FontChar) -> Rect {
+        let quad = info.quad;
+        Rect::new(x + quad.x(), y + quad.y(), quad.width(), quad.height())
+    }
+
+    /// Horizontal advance of a single character, missing glyphs measure as the fallback glyph
+    pub fn measure_char(&self, ch: char) -> u32 {
+        if ch == ' ' {
+            return self.whitespace_width;
+        }
+        self.char_lookup
+            .get(&(ch as usize))
+            .or(self.fallback.as_ref())
+            .map_or(0, |fch| fch._ax)
+    }
+
+    /// Advance of a character in pixels, including its fractional part
+    pub fn measure_char_exact(&self, ch: char) -> f32 {
+        if ch == ' ' {
+            return self.whitespace_width as f32;
+        }
+        self.char_lookup
+            .get(&(ch as usize))
+            .or(self.fallback.as_ref())
+            .map_or(0.0, |fch| fch.advance)
+    }
+
+    /// Width of a string in pixels, including the spacing between characters.
+    /// Fractional advances are summed up and only rounded at the end.
+    pub fn measure_string(&self, s: &str) -> u32 {
+        let mut width = 0.0f32;
+        let mut prev: Option<char> = None;
+        for ch in s.chars() {
+            if let Some(prev) = prev {
+                width += (self.char_spacing as i32 + self.kerning(prev, ch)) as f32;
+            }
+            width += self.measure_char_exact(ch);
+            prev = Some(ch);
         }
+        width.max(0.0).round() as u32
     }
 
-    /// Get the position of the character in the texture atlas
-    pub fn get_char(&self, char: usize) -> Result<Rc<FontChar>, ()> {
+    /// Get the position of the character in the texture atlas,
+    /// rasterizing it first if it has not been loaded yet
+    pub fn get_char(&mut self, char: usize) -> Result<Rc<FontChar>, ()> {
         if let Some(info) = self.char_lookup.get(&char) {
             Ok(info.clone())
         } else {
-            Err(())
+            self.rasterize_char(char, FontStyle::Regular)
+        }
+    }
+
+    /// Like `get_char`, but from the face of the given style.
+    /// Styles without a loaded face fall back to the regular glyphs.
+    pub fn get_char_styled(&mut self, char: usize, style: FontStyle) -> Result<Rc<FontChar>, ()> {
+        if style == FontStyle::Regular || !self.has_style(style) {
+            return self.get_char(char);
+        }
+        if let Some(info) = self.styled_lookup.get(&(style, char)) {
+            Ok(info.clone())
+        } else {
+            self.rasterize_char(char, style)
         }
     }
 
-    pub fn get_string<T: Into<String>>(&self, str: T) -> Result<Vec<Rc<FontChar>>, ()> {
+    pub fn get_string<T: Into<String>>(&mut self, str: T) -> Result<Vec<Rc<FontChar>>, ()> {
         let str: String = str.into();
         let mut vec = Vec::<Rc<FontChar>>::with_capacity(str.len());
         for ch in str.chars() {
@@ -187,4 +657,262 @@ impl FontDef {
         }
         Ok(vec)
     }
+
+    /// Shape `str` with rustybuzz and return where it differs from one glyph per character,
+    /// as (character index, glyph) pairs. A glyph standing for a whole cluster comes with the
+    /// first character of the cluster, the other characters of the cluster are covered by it
+    /// and come with None. Without the `shaping` feature nothing is ever substituted.
+    #[cfg(feature = "shaping")]
+    pub fn shape_string(&mut self, str: &str) -> Vec<(usize, Option<Rc<FontChar>>)> {
+        if self.shaping_data.is_empty() {
+            return Vec::new();
+        }
+        let Some(shaped) = crate::shaping::shape(&self.shaping_data, self.font_pixel_size, str)
+        else {
+            return Vec::new();
+        };
+        // byte offset of every character, clusters are given as byte offsets
+        let starts: Vec<(usize, char)> = str.char_indices().collect();
+        let mut substitutes = Vec::new();
+        for (idx, glyph) in shaped.iter().enumerate() {
+            let end = shaped
+                .get(idx + 1)
+                .map_or(str.len(), |next| next.cluster)
+                .max(glyph.cluster);
+            let first = starts.partition_point(|(start, _)| *start < glyph.cluster);
+            let last = starts.partition_point(|(start, _)| *start < end);
+            let Some(&(_, ch)) = starts[first..last].first() else {
+                // a further glyph of a cluster that already has one
+                continue;
+            };
+            // whitespace, missing glyphs and glyphs the shaping left alone keep the plain glyph
+            let plain = self
+                .face
+                .as_ref()
+                .map_or(0, |face| face.get_char_index(ch as usize));
+            if !(ch.is_whitespace() || glyph.glyph_id == 0 || glyph.glyph_id == plain) {
+                let fch = match self.glyph_lookup.get(&glyph.glyph_id) {
+                    Some(cached) if cached.ch == ch => cached.clone(),
+                    Some(cached) => Rc::new(FontChar {
+                        ch,
+                        ..(**cached).clone()
+                    }),
+                    None => self
+                        .rasterize_glyph(glyph.glyph_id, ch)
+                        .unwrap_or_else(|_| Rc::new(FontChar::default())),
+                };
+                substitutes.push((first, Some(fch)));
+            }
+            substitutes.extend((first + 1..last).map(|covered| (covered, None)));
+        }
+        substitutes
+    }
+
+    #[cfg(not(feature = "shaping"))]
+    pub fn shape_string(&mut self, _str: &str) -> Vec<(usize, Option<Rc<FontChar>>)> {
+        Vec::new()
+    }
+
+    /// Like `get_string`, but substitutes the fallback glyph for missing characters
+    pub fn get_string_lossy<T: Into<String>>(&mut self, str: T) -> Vec<Rc<FontChar>> {
+        self.get_string_styled(str, FontStyle::Regular)
+    }
+
+    /// Lossy conversion of a whole run of text in a single style
+    pub fn get_string_styled<T: Into<String>>(
+        &mut self,
+        str: T,
+        style: FontStyle,
+    ) -> Vec<Rc<FontChar>> {
+        let str: String = str.into();
+        let mut vec = Vec::<Rc<FontChar>>::with_capacity(str.len());
+        for ch in str.chars() {
+            let fch = self
+                .get_char_styled(ch as usize, style)
+                .unwrap_or_else(|_| {
+                    self.fallback
+                        .clone()
+                        .unwrap_or_else(|| Rc::new(FontChar::default()))
+                });
+            vec.push(fch);
+        }
+        vec
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A font without a face whose printable ASCII glyphs all advance by `advance` pixels
+    pub(crate) fn monospace_font(advance: u32) -> FontDef {
+        let chars = (' '..='~').chain(['\n', '\t']);
+        let map = chars
+            .map(|ch| {
+                let fch = FontChar::new(ch, Rect::new(0, 0, advance, 10), advance, 0, 0, 8, 0);
+                (ch as usize, Rc::new(fch))
+            })
+            .collect();
+        FontDef::new(map, 10, advance, 0, 8, 2, 0, advance, 10)
+    }
+
+    #[test]
+    fn coverage_becomes_the_alpha_of_white_pixels() {
+        let rgba = coverage_to_rgba(&[0, 128, 255]);
+        let pixels: Vec<u32> = rgba
+            .chunks(4)
+            .map(|pixel| u32::from_ne_bytes(pixel.try_into().unwrap()))
+            .collect();
+        assert_eq!(pixels, [0xFFFFFF00, 0xFFFFFF80, 0xFFFFFFFF]);
+
+        // anti-aliased edges of a real glyph end up partially transparent
+        let lib = freetype::Library::init().unwrap();
+        let face = lib
+            .new_memory_face(include_bytes!("../fonts/DejaVuSansMono.ttf").to_vec(), 0)
+            .unwrap();
+        face.set_pixel_sizes(0, 24).unwrap();
+        face.load_char('O' as usize, freetype::face::LoadFlag::RENDER)
+            .unwrap();
+        let rgba = coverage_to_rgba(face.glyph().bitmap().buffer());
+        let alphas: Vec<u8> = rgba
+            .chunks(4)
+            .map(|pixel| (u32::from_ne_bytes(pixel.try_into().unwrap()) & 0xFF) as u8)
+            .collect();
+        assert!(alphas.iter().any(|&alpha| alpha > 0 && alpha < 255));
+        assert!(alphas.contains(&255));
+    }
+
+    #[test]
+    fn lossy_string_keeps_one_glyph_per_char() {
+        let mut font = monospace_font(8);
+        let text = "ok ✓ ünïcode 🦀";
+        let glyphs = font.get_string_lossy(text);
+        assert_eq!(glyphs.len(), text.chars().count());
+        assert!(font.get_string(text).is_err());
+    }
+
+    #[test]
+    fn measure_string_rounds_the_sum_of_fractional_advances() {
+        let fch = FontChar {
+            advance: 7.3,
+            ..FontChar::new('i', Rect::new(0, 0, 7, 10), 7, 0, 0, 8, 0)
+        };
+        let font = FontDef::new(
+            [('i' as usize, Rc::new(fch))].into_iter().collect(),
+            10,
+            7,
+            0,
+            8,
+            2,
+            0,
+            7,
+            10,
+        );
+        // rounding every advance on its own would give 40 * 7
+        assert_eq!(font.measure_string(&"i".repeat(40)), 292);
+        assert_eq!(font.measure_string("ii"), 15);
+    }
+
+    #[test]
+    fn measure_string_adds_spacing_between_characters() {
+        let map = [('a', 7), ('b', 9)]
+            .into_iter()
+            .map(|(ch, ax)| {
+                let fch = FontChar::new(ch, Rect::new(0, 0, ax, 10), ax, 0, 0, 8, 0);
+                (ch as usize, Rc::new(fch))
+            })
+            .collect();
+        let font = FontDef::new(map, 10, 9, 2, 8, 2, 0, 9, 10);
+        assert_eq!(font.measure_string("a"), 7);
+        assert_eq!(font.measure_string("ab"), 7 + 9 + 2);
+        assert_eq!(font.measure_string(""), 0);
+    }
+
+    /// Loads resources as copies of their name, counting how often it had to
+    #[derive(Default)]
+    struct CountingLoader {
+        loads: Cell<usize>,
+    }
+
+    impl<'l> ResourceLoader<'l, String> for CountingLoader {
+        type Args = str;
+        fn load(&'l self, data: &str) -> Result<String, String> {
+            self.loads.set(self.loads.get() + 1);
+            Ok(data.to_owned())
+        }
+        fn create(&'l self, w: u32, h: u32) -> Result<String, String> {
+            Ok(format!("{w}x{h}"))
+        }
+    }
+
+    #[test]
+    fn remove_drops_an_entry_but_not_its_users() {
+        let loader = CountingLoader::default();
+        let mut manager: ResourceManager<'_, usize, String, _> = ResourceManager::new(&loader);
+        let held = manager.load(1, "one").unwrap();
+        manager.load(2, "two").unwrap();
+        assert_eq!(manager.len(), 2);
+        assert!(manager.contains_key(&1));
+        let removed = manager.remove(&1).unwrap();
+        assert!(Rc::ptr_eq(&held, &removed));
+        assert_eq!(*held.borrow(), "one");
+        assert!(!manager.contains_key(&1));
+        assert!(manager.remove(&1).is_none());
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[test]
+    fn capacity_evicts_the_least_recently_used() {
+        let loader = CountingLoader::default();
+        let mut manager: ResourceManager<'_, usize, String, _> =
+            ResourceManager::with_capacity(&loader, 2);
+        manager.load(1, "one").unwrap();
+        manager.load(2, "two").unwrap();
+        // touching 1 leaves 2 as the oldest
+        manager.get(&1).unwrap();
+        manager.load(3, "three").unwrap();
+        assert_eq!(manager.len(), 2);
+        assert!(manager.contains_key(&1));
+        assert!(!manager.contains_key(&2));
+        assert!(manager.contains_key(&3));
+
+        // an evicted resource is loaded again on demand
+        manager.load(2, "two").unwrap();
+        assert_eq!(manager.len(), 2);
+        assert!(!manager.contains_key(&1));
+        assert_eq!(loader.loads.get(), 4);
+    }
+
+    #[test]
+    fn create_and_load_keep_existing_resources() {
+        let loader = CountingLoader::default();
+        let mut manager: ResourceManager<'_, usize, String, _> = ResourceManager::new(&loader);
+        manager.create(1, 16, 8).unwrap();
+        assert!(manager.create(1, 32, 32).is_err());
+        assert!(manager.load(1, "one").is_err());
+        assert_eq!(*manager.get(&1).unwrap().borrow(), "16x8");
+        assert_eq!(loader.loads.get(), 0);
+    }
+
+    #[test]
+    fn oversized_glyphs_are_clipped_to_their_slot() {
+        let mut font = monospace_font(8);
+        (font.slot_width, font.slot_height) = (4, 3);
+        // a 6x5 bitmap padded to a pitch of 8 pixels, each pixel holding its column and row
+        let rgba: Vec<u8> = (0..5u8)
+            .flat_map(|row| (0..8u8).flat_map(move |col| [col, row, 0, 0]))
+            .collect();
+        let (atlas, bbox) = font.place_bitmap(rgba, 8 * 4, 6, 5);
+        let (next_atlas, next_bbox) = font.place_bitmap(vec![0; 4], 4, 1, 1);
+        assert_eq!(atlas, next_atlas);
+        assert_eq!(bbox, Rect::new(0, 0, 4, 3));
+        assert_eq!(next_bbox.left(), bbox.right());
+
+        let upload = &font.pending_uploads[0];
+        assert_eq!(upload.dest, bbox);
+        assert_eq!(upload.pitch, 4 * 4);
+        assert_eq!(upload.pixels.len(), 4 * 3 * 4);
+        let last = &upload.pixels[upload.pixels.len() - 4..];
+        assert_eq!(last, [3, 2, 0, 0]);
+    }
 }
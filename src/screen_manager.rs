@@ -1,40 +1,159 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BinaryHeap, HashMap};
+use std::iter::{Chain, FlatMap};
+use std::ops::{Deref, DerefMut, Index, Range};
 use std::rc::Rc;
+use std::slice;
+use std::time::{Duration, Instant};
 
-use sdl2::rect::Rect;
+use log::error;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::BlendMode;
 
-use crate::{FontChar, Renderer};
+use crate::edit_buffer::EditBuffer;
+use crate::gap_buffer::{self, GapBuffer};
+use crate::geometry::batch_glyphs;
+use crate::highlight::Highlighter;
+use crate::{FontChar, FontDef, Renderer};
 pub trait Renderable {
     fn render(&self, target: &mut Renderer<'_>, x: u32, y: u32) -> Result<Rect, String>;
 }
 
+/// Distance between the tops of two rows, `line_spacing` times the glyph height
+#[inline]
+pub fn row_advance(glyph_height: u32, line_spacing: f32) -> u32 {
+    (glyph_height as f32 * line_spacing).ceil() as u32
+}
+
+/// Resolve color spans where later ones win into disjoint runs sorted by their start
+fn flatten_spans(spans: &[(Range<u32>, Color)]) -> Vec<(Range<u32>, Color)> {
+    let mut bounds: Vec<u32> = spans
+        .iter()
+        .flat_map(|(range, _)| [range.start, range.end])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut by_start: Vec<usize> = (0..spans.len())
+        .filter(|&idx| !spans[idx].0.is_empty())
+        .collect();
+    by_start.sort_by_key(|&idx| spans[idx].0.start);
+
+    // spans covering the current piece, the latest one on top
+    let mut covering = BinaryHeap::new();
+    let mut next = 0;
+    let mut runs: Vec<(Range<u32>, Color)> = Vec::new();
+    for piece in bounds.windows(2) {
+        let (start, end) = (piece[0], piece[1]);
+        while next < by_start.len() && spans[by_start[next]].0.start <= start {
+            covering.push(by_start[next]);
+            next += 1;
+        }
+        // spans that ended are only dropped once they come out on top
+        while covering
+            .peek()
+            .is_some_and(|&idx| spans[idx].0.end <= start)
+        {
+            covering.pop();
+        }
+        let Some(&idx) = covering.peek() else {
+            continue;
+        };
+        let color = spans[idx].1;
+        match runs.last_mut() {
+            Some((run, run_color)) if run.end == start && *run_color == color => run.end = end,
+            _ => runs.push((start..end, color)),
+        }
+    }
+    runs
+}
+
 #[derive(Default, Clone)]
 pub struct ScreenLine {
-    content: Vec<Rc<FontChar>>,
+    content: GapBuffer<Rc<FontChar>>,
     width: u32,
     row: usize,
+    line_spacing: f32,
 }
 
 impl ScreenLine {
     pub fn new(row: usize) -> Self {
         ScreenLine {
             row,
+            line_spacing: 1.0,
             ..Default::default()
         }
     }
+    #[inline]
+    pub fn set_line_spacing(&mut self, line_spacing: f32) {
+        self.line_spacing = line_spacing;
+    }
     pub fn get_text(&self) -> String {
         let str = self.content.iter().map(|fch| fch.ch).collect::<String>();
         str
     }
     #[inline]
-    pub fn content(&self) -> &Vec<Rc<FontChar>> {
+    pub fn content(&self) -> &GapBuffer<Rc<FontChar>> {
         &self.content
     }
-    pub fn push_char(&mut self, fch: Rc<FontChar>) {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+    #[inline]
+    pub fn get(&self, col: usize) -> Option<&Rc<FontChar>> {
+        self.content.get(col)
+    }
+    pub fn insert(&mut self, col: usize, fch: Rc<FontChar>) {
         self.width += fch.bbox.width();
-        self.content.push(fch);
+        self.content.insert(col, fch);
+    }
+    pub fn remove(&mut self, col: usize) -> Rc<FontChar> {
+        let fch = self.content.remove(col);
+        self.width = self.width.saturating_sub(fch.bbox.width());
+        fch
+    }
+    /// Remove the characters in the columns `range`, returning them in order
+    pub fn remove_range(&mut self, range: Range<usize>) -> Vec<Rc<FontChar>> {
+        let removed = self.content.remove_range(range);
+        let removed_width = removed.iter().map(|fch| fch.bbox.width()).sum();
+        self.width = self.width.saturating_sub(removed_width);
+        removed
+    }
+    pub fn push_char(&mut self, fch: Rc<FontChar>) {
+        self.insert(self.len(), fch);
     }
     pub fn pop_char(&mut self) -> Option<Rc<FontChar>> {
-        self.content.pop()
+        if self.content.is_empty() {
+            return None;
+        }
+        Some(self.remove(self.len() - 1))
+    }
+    /// Cut the line at `col`, returning everything from there on as the line below
+    pub fn split_off(&mut self, col: usize) -> ScreenLine {
+        let rest = self.content.remove_range(col..self.len());
+        let rest_width = rest.iter().map(|fch| fch.bbox.width()).sum();
+        self.width = self.width.saturating_sub(rest_width);
+        ScreenLine {
+            content: rest.into(),
+            width: rest_width,
+            row: self.row + 1,
+            line_spacing: self.line_spacing,
+        }
+    }
+    /// Move the contents of `other` to the end of this line
+    pub fn append(&mut self, other: ScreenLine) {
+        self.content
+            .insert_many(self.len(), other.content.iter().cloned());
+        self.width += other.width;
+    }
+    #[inline]
+    pub fn range(&self, range: Range<usize>) -> gap_buffer::Iter<'_, Rc<FontChar>> {
+        self.content.range(range)
+    }
+    #[inline]
+    pub fn iter(&self) -> gap_buffer::Iter<'_, Rc<FontChar>> {
+        self.content.iter()
     }
     pub fn wrapped_bbox(&self, max_width: u32, row_height: u32) -> Rect {
         let height = (self.width as f32 / max_width as f32).ceil() as u32 * row_height;
@@ -47,7 +166,8 @@ impl Renderable for ScreenLine {
     fn render(&self, target: &mut Renderer<'_>, x: u32, y: u32) -> Result<Rect, String> {
         let mut w = 0;
         let mut x_offset = 0;
-        let mut y_offset = y + self.row as u32 * target.loaded_font.glyph_height;
+        let row_height = row_advance(target.loaded_font.glyph_height, self.line_spacing);
+        let mut y_offset = y + self.row as u32 * row_height;
         for fch in &self.content {
             let ch_w = if fch.bbox.width() <= 1 {
                 target.loaded_font.whitespace_width
@@ -56,7 +176,7 @@ impl Renderable for ScreenLine {
             };
 
             if x_offset + ch_w > target.width {
-                y_offset += target.loaded_font.glyph_height;
+                y_offset += row_height;
                 x_offset = 0;
             }
 
@@ -64,7 +184,7 @@ impl Renderable for ScreenLine {
             if !fch.ch.is_whitespace() {
                 fch.render(target, x + x_offset, y_offset)
                     .map_err(|err| {
-                        eprintln!("Could not render character: {err}");
+                        error!("Could not render character: {err}");
                     })
                     .unwrap();
             }
@@ -84,28 +204,452 @@ impl Renderable for ScreenLine {
     }
 }
 
+type LineIter<'a> = FlatMap<
+    slice::Iter<'a, ScreenLine>,
+    gap_buffer::Iter<'a, Rc<FontChar>>,
+    fn(&'a ScreenLine) -> gap_buffer::Iter<'a, Rc<FontChar>>,
+>;
+type LinesIter<'a> = Chain<
+    Chain<gap_buffer::Iter<'a, Rc<FontChar>>, LineIter<'a>>,
+    gap_buffer::Iter<'a, Rc<FontChar>>,
+>;
+
+/// Iterator over a range of the characters of `Lines`
+#[derive(Clone)]
+pub enum Iter<'a> {
+    /// The range lies within a single line
+    Line(gap_buffer::Iter<'a, Rc<FontChar>>),
+    /// The range spans several lines: the end of the first, whole lines, the start of the last
+    Lines(LinesIter<'a>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Rc<FontChar>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Line(iter) => iter.next(),
+            Iter::Lines(iter) => iter.next(),
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Line(iter) => iter.size_hint(),
+            Iter::Lines(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Line(iter) => iter.next_back(),
+            Iter::Lines(iter) => iter.next_back(),
+        }
+    }
+}
+
+/// The text of a `TextScreen`, one `ScreenLine` per logical line.
+/// Every line but the last ends in `'\n'`, so there is always at least one line.
+/// Characters are addressed by their absolute position in the text.
+#[derive(Clone)]
+pub struct Lines {
+    lines: Vec<ScreenLine>,
+    len: usize,
+    /// Line found by the last lookup and its absolute start, so sequential access is cheap
+    hint: Cell<(usize, usize)>,
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Lines {
+            lines: vec![ScreenLine::new(0)],
+            len: 0,
+            hint: Cell::new((0, 0)),
+        }
+    }
+}
+
+impl Lines {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    #[inline]
+    pub fn line(&self, idx: usize) -> Option<&ScreenLine> {
+        self.lines.get(idx)
+    }
+
+    /// Line and column of the absolute position `pos`.
+    /// The end of a line break belongs to the line after it.
+    pub(crate) fn locate(&self, pos: usize) -> (usize, usize) {
+        let (mut line, mut start) = self.hint.get();
+        if pos < start || line >= self.lines.len() {
+            (line, start) = (0, 0);
+        }
+        while line + 1 < self.lines.len() && pos >= start + self.lines[line].len() {
+            start += self.lines[line].len();
+            line += 1;
+        }
+        self.hint.set((line, start));
+        (line, pos - start)
+    }
+
+    /// Forget lookups past `line`, their positions may have shifted
+    #[inline]
+    fn invalidate_hint(&self, line: usize) {
+        if self.hint.get().0 > line {
+            self.hint.set((0, 0));
+        }
+    }
+
+    pub fn get(&self, pos: usize) -> Option<&Rc<FontChar>> {
+        if pos >= self.len {
+            return None;
+        }
+        let (line, col) = self.locate(pos);
+        self.lines[line].get(col)
+    }
+
+    /// Give every line from `line` on its index as row again, after lines were added or removed
+    fn renumber_from(&mut self, line: usize) {
+        for (row, screen_line) in self.lines.iter_mut().enumerate().skip(line) {
+            screen_line.row = row;
+        }
+    }
+
+    pub fn insert(&mut self, pos: usize, fch: Rc<FontChar>) {
+        assert!(pos <= self.len, "insertion index {pos} out of bounds");
+        let (line, col) = self.locate(pos);
+        let newline = fch.ch == '\n';
+        self.lines[line].insert(col, fch);
+        if newline {
+            let rest = self.lines[line].split_off(col + 1);
+            self.lines.insert(line + 1, rest);
+            self.renumber_from(line + 1);
+        }
+        self.len += 1;
+        self.invalidate_hint(line);
+    }
+
+    pub fn insert_many<I: IntoIterator<Item = Rc<FontChar>>>(&mut self, pos: usize, elems: I) {
+        assert!(pos <= self.len, "insertion index {pos} out of bounds");
+        let (line, col) = self.locate(pos);
+        let tail = self.lines[line].split_off(col);
+        let line_spacing = tail.line_spacing;
+        // lines started by the inserted line breaks, renumbered once they are in place
+        let mut added: Vec<ScreenLine> = Vec::new();
+        for fch in elems {
+            let newline = fch.ch == '\n';
+            added
+                .last_mut()
+                .unwrap_or(&mut self.lines[line])
+                .push_char(fch);
+            self.len += 1;
+            if newline {
+                let mut next = ScreenLine::new(0);
+                next.set_line_spacing(line_spacing);
+                added.push(next);
+            }
+        }
+        added
+            .last_mut()
+            .unwrap_or(&mut self.lines[line])
+            .append(tail);
+        if !added.is_empty() {
+            self.lines.splice(line + 1..line + 1, added);
+            self.renumber_from(line + 1);
+        }
+        self.invalidate_hint(line);
+    }
+
+    pub fn remove(&mut self, pos: usize) -> Rc<FontChar> {
+        assert!(pos < self.len, "removal index {pos} out of bounds");
+        let (line, col) = self.locate(pos);
+        let fch = self.lines[line].remove(col);
+        // joining two lines
+        if fch.ch == '\n' && line + 1 < self.lines.len() {
+            let next = self.lines.remove(line + 1);
+            self.lines[line].append(next);
+            self.renumber_from(line + 1);
+        }
+        self.len -= 1;
+        self.invalidate_hint(line);
+        fch
+    }
+
+    /// Remove all characters in `range`, returning them in order
+    pub fn remove_range(&mut self, range: Range<usize>) -> Vec<Rc<FontChar>> {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "removal range out of bounds"
+        );
+        let (first, first_col) = self.locate(range.start);
+        let (last, last_col) = self.locate(range.end);
+        let removed = if first == last {
+            self.lines[first].remove_range(first_col..last_col)
+        } else {
+            let mut removed: Vec<Rc<FontChar>> = self.lines[first]
+                .split_off(first_col)
+                .iter()
+                .cloned()
+                .collect();
+            let mut joined: Vec<ScreenLine> = self.lines.drain(first + 1..=last).collect();
+            // the rest of the last line moves up to the first one
+            let tail = joined.last_mut().unwrap().split_off(last_col);
+            removed.extend(joined.iter().flat_map(ScreenLine::iter).cloned());
+            self.lines[first].append(tail);
+            self.renumber_from(first + 1);
+            removed
+        };
+        self.len -= removed.len();
+        self.invalidate_hint(first);
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        *self = Lines::default();
+    }
+
+    /// Iterate over the characters in `range`
+    pub fn range(&self, range: Range<usize>) -> Iter<'_> {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "range out of bounds"
+        );
+        let (first, first_col) = self.locate(range.start);
+        let (last, last_col) = self.locate(range.end);
+        if first == last {
+            return Iter::Line(self.lines[first].range(first_col..last_col));
+        }
+        let whole_lines: fn(&ScreenLine) -> gap_buffer::Iter<'_, Rc<FontChar>> = ScreenLine::iter;
+        let first_line = &self.lines[first];
+        Iter::Lines(
+            first_line
+                .range(first_col..first_line.len())
+                .chain(self.lines[first + 1..last].iter().flat_map(whole_lines))
+                .chain(self.lines[last].range(0..last_col)),
+        )
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(0..self.len)
+    }
+}
+
+impl From<Vec<Rc<FontChar>>> for Lines {
+    fn from(text: Vec<Rc<FontChar>>) -> Self {
+        let mut lines = Lines {
+            len: text.len(),
+            ..Default::default()
+        };
+        for fch in text {
+            let newline = fch.ch == '\n';
+            let last = lines.lines.len() - 1;
+            lines.lines[last].push_char(fch);
+            if newline {
+                lines.lines.push(ScreenLine::new(last + 1));
+            }
+        }
+        lines
+    }
+}
+
+impl Index<usize> for Lines {
+    type Output = Rc<FontChar>;
+
+    fn index(&self, pos: usize) -> &Rc<FontChar> {
+        self.get(pos)
+            .unwrap_or_else(|| panic!("index {pos} out of bounds"))
+    }
+}
+
+impl<'a> IntoIterator for &'a Lines {
+    type Item = &'a Rc<FontChar>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// How lines longer than the screen width are broken up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    /// Break at the last character that fits
+    #[default]
+    Char,
+    /// Move words that do not fit to the next line
+    Word,
+    /// Do not wrap, clip long lines at the screen edge
+    None,
+}
+
+/// How the caret is drawn
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaretShape {
+    /// A thin vertical line in front of the character
+    #[default]
+    Bar,
+    /// A filled cell covering the character
+    Block,
+    /// A thin line on the baseline below the character
+    Underline,
+}
+
+/// Width of the scrollbar on the right edge
+const SCROLLBAR_WIDTH: u32 = 6;
+/// Smallest thumb height, so it stays visible for very long texts
+const SCROLLBAR_MIN_THUMB: u32 = 16;
+/// Time the text has to stay unchanged before it is highlighted again
+const HIGHLIGHT_DELAY: Duration = Duration::from_millis(150);
+/// Colors of the debug overlay: glyph boxes, baselines and glyph origins
+const DEBUG_BOX_COLOR: Color = Color::RGBA(255, 60, 60, 160);
+const DEBUG_BASELINE_COLOR: Color = Color::RGBA(60, 200, 60, 160);
+const DEBUG_ORIGIN_COLOR: Color = Color::RGBA(80, 140, 255, 255);
+
+/// Caret positions of a layout, with the x position and buffer revision they were computed for
+type Layout = RefCell<Option<(u32, u64, Rc<Vec<(u32, u32)>>)>>;
+
 #[derive(Default, Clone)]
 pub struct TextScreen {
-    //lines: Vec<ScreenLine>,
-    content: Vec<Rc<FontChar>>,
+    buffer: EditBuffer,
     width: usize,
     height: usize,
     row_height: usize,
-    cursor_abs: u32,
-    cursor_col: u32,
-    cursor_row: u32,
-    highlight_mark: u32,
+    glyph_height: usize,
+    line_spacing: f32,
     _cursor_enabled: bool,
+    scroll_row: usize,
+    /// Horizontal scroll offset in pixels, only used without wrapping
+    scroll_col_px: u32,
+    total_rows: usize,
+    wrap_mode: WrapMode,
+    render_whitespace: bool,
+    caret_shape: CaretShape,
+    highlight_current_line: bool,
+    find_changed: bool,
+    blink_interval: Duration,
+    blink_timer: Option<Instant>,
+    cursor_visible: bool,
+    /// Revision of the buffer when the blink was last restarted
+    blink_revision: u64,
+    line_cache: Option<usize>,
+    cached_lines: usize,
+    rendered_cursor: Option<Rect>,
+    rendered_current_line: Option<Rect>,
+    rendered_brackets: Vec<Rect>,
+    rendered_scroll_row: usize,
+    rendered_selection: bool,
+    /// Input method text that is still being composed, shown at the caret but not inserted
+    composition: Option<String>,
+    rendered_composition: bool,
+    /// Start wrapped continuation rows at the indentation of their line
+    wrap_indent: bool,
+    /// Columns continuation rows are indented by beyond the line's own indentation
+    hanging_indent: u32,
+    /// Set when the font, width or wrapping changed since `layout_cache` was computed
+    layout_dirty: Cell<bool>,
+    /// Last layout with the x position and buffer revision it was computed for
+    layout_cache: Layout,
+    /// Text colors as disjoint character ranges sorted by their start
+    color_spans: Vec<(Range<u32>, Color)>,
+    /// Produces `color_spans` from the text, if syntax highlighting is on
+    highlighter: Option<Rc<dyn Highlighter>>,
+    /// Buffer revision the color spans were computed for
+    highlighted_revision: Option<u64>,
+    /// Last buffer revision seen and when it was first seen, to wait for typing to pause
+    pending_highlight: Option<(u64, Instant)>,
+}
+
+/// The edit API of the buffer is available on the screen showing it
+impl Deref for TextScreen {
+    type Target = EditBuffer;
+
+    fn deref(&self) -> &EditBuffer {
+        &self.buffer
+    }
+}
+
+impl DerefMut for TextScreen {
+    fn deref_mut(&mut self) -> &mut EditBuffer {
+        &mut self.buffer
+    }
 }
 
 impl TextScreen {
-    fn put_cursor(&self, target: &mut Renderer<'_>, x: i32, y: i32) {
-        let cursor_width = target.loaded_font.glyph_width / 16;
-        if self._cursor_enabled {
-            target
-                .canvas
-                .set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
-            let dst = Rect::new(x, y, cursor_width, target.loaded_font.glyph_height);
+    /// Draw the composition underlined over the text at the caret
+    fn render_composition(
+        &self,
+        target: &mut Renderer<'_>,
+        layout: &[(u32, u32)],
+        x: u32,
+        y: u32,
+        text: String,
+    ) {
+        let (cursor_x, cursor_row) = layout[self.buffer.cursor_abs as usize];
+        let first_row = self.scroll_row as u32;
+        if !(first_row..first_row + self.visible_rows() as u32).contains(&cursor_row) {
+            return;
+        }
+        let Some(left) = self.view_x(x, cursor_x) else {
+            return;
+        };
+        let top = y + (cursor_row - first_row) * self.row_height as u32 + self.leading();
+        let glyphs = target.loaded_font.get_string_lossy(text);
+        let width: u32 = glyphs.iter().map(|fch| fch._ax.max(1)).sum();
+        let font = &target.loaded_font;
+        let (glyph_height, thickness) = (font.glyph_height, (font.glyph_width / 16).max(1));
+        let underline_top = top + font.max_ascent + thickness;
+
+        target.canvas.set_draw_color(target.theme.background);
+        target
+            .canvas
+            .fill_rect(Rect::new(left as i32, top as i32, width, glyph_height))
+            .unwrap();
+        let mut pen_x = left;
+        for fch in &glyphs {
+            fch.render(target, pen_x, top)
+                .map_err(|err| {
+                    error!(
+                        "Failed to render composed character {ch}: {err}",
+                        ch = fch.ch
+                    );
+                })
+                .unwrap();
+            pen_x += fch._ax.max(1);
+        }
+        target.canvas.set_draw_color(target.theme.foreground);
+        target
+            .canvas
+            .fill_rect(Rect::new(
+                left as i32,
+                underline_top as i32,
+                width,
+                thickness,
+            ))
+            .unwrap();
+    }
+
+    fn put_cursor(&self, target: &mut Renderer<'_>, dst: Rect) {
+        if self._cursor_enabled && self.cursor_visible {
+            target.canvas.set_draw_color(target.theme.cursor);
             target.canvas.fill_rect(dst).unwrap();
         }
     }
@@ -115,7 +659,11 @@ impl TextScreen {
             width,
             height,
             row_height,
-            highlight_mark: u32::MAX,
+            glyph_height: row_height,
+            line_spacing: 1.0,
+            buffer: EditBuffer::new(),
+            blink_interval: Duration::from_millis(500),
+            cursor_visible: true,
             ..Default::default()
         }
     }
@@ -126,7 +674,11 @@ impl TextScreen {
     }
     #[inline]
     pub fn set_width(&mut self, new_width: usize) {
+        if new_width == self.width {
+            return;
+        }
         self.width = new_width;
+        self.invalidate_lines();
     }
     #[inline]
     pub fn height(&self) -> usize {
@@ -136,6 +688,33 @@ impl TextScreen {
     pub fn set_height(&mut self, new_height: usize) {
         self.height = new_height;
     }
+    /// Distance between the tops of two rows, including the line spacing
+    #[inline]
+    pub fn row_height(&self) -> usize {
+        self.row_height
+    }
+    /// Set the height of a single row of glyphs, the row advance is derived from it
+    #[inline]
+    pub fn set_row_height(&mut self, new_row_height: usize) {
+        self.glyph_height = new_row_height;
+        self.row_height = row_advance(new_row_height as u32, self.line_spacing) as usize;
+        self.invalidate_lines();
+    }
+    #[inline]
+    pub fn line_spacing(&self) -> f32 {
+        self.line_spacing
+    }
+    /// Space rows `line_spacing` glyph heights apart, 1.5 puts half a line between them
+    #[inline]
+    pub fn set_line_spacing(&mut self, line_spacing: f32) {
+        self.line_spacing = line_spacing;
+        self.set_row_height(self.glyph_height);
+    }
+    /// Space above the glyphs of a row, half of the extra line spacing
+    #[inline]
+    fn leading(&self) -> u32 {
+        (self.row_height.saturating_sub(self.glyph_height) / 2) as u32
+    }
     #[inline]
     pub fn cursor_enable(&mut self) {
         self._cursor_enabled = true;
@@ -150,108 +729,241 @@ impl TextScreen {
     }
 
     #[inline]
-    pub fn set_cursor_row(&mut self, row: u32) {
-        self.cursor_row = row;
+    pub fn set_blink_interval(&mut self, interval: Duration) {
+        self.blink_interval = interval;
+    }
+
+    /// Toggle the caret once the blink interval has elapsed.
+    /// Returns true if the visibility changed and the screen needs a redraw.
+    pub fn update_blink(&mut self, now: Instant) -> bool {
+        // editing shows the caret solid again
+        if self.buffer.revision() != self.blink_revision {
+            self.blink_revision = self.buffer.revision();
+            self.reset_blink();
+            return true;
+        }
+        let last_toggle = *self.blink_timer.get_or_insert(now);
+        if now.duration_since(last_toggle) < self.blink_interval {
+            return false;
+        }
+        self.cursor_visible = !self.cursor_visible;
+        self.blink_timer = Some(now);
+        true
     }
+
+    /// Time left until the caret toggles next
+    pub fn blink_timeout(&self, now: Instant) -> Duration {
+        match self.blink_timer {
+            Some(last_toggle) => self
+                .blink_interval
+                .saturating_sub(now.duration_since(last_toggle)),
+            None => self.blink_interval,
+        }
+    }
+
+    /// Show the caret solid and restart the blink interval
     #[inline]
-    pub fn set_cursor_col(&mut self, col: u32) {
-        self.cursor_col = col;
+    pub fn reset_blink(&mut self) {
+        self.cursor_visible = true;
+        self.blink_timer = Some(Instant::now());
     }
 
     #[inline]
-    pub fn set_highlight_mark(&mut self, pos: u32) {
-        self.highlight_mark = pos;
+    pub fn wrap_mode(&self) -> WrapMode {
+        self.wrap_mode
     }
     #[inline]
-    pub fn set_cursor_abs(&mut self, pos: u32) {
-        self.cursor_abs = pos;
+    pub fn set_wrap_mode(&mut self, wrap_mode: WrapMode) {
+        self.wrap_mode = wrap_mode;
+        self.scroll_col_px = 0;
+        self.invalidate_lines();
+        self.scroll_to_cursor();
     }
-
     #[inline]
-    pub fn get_cursor_row(&mut self) -> u32 {
-        self.cursor_row
+    pub fn wrap_indent(&self) -> bool {
+        self.wrap_indent
     }
-
+    /// Start wrapped continuation rows at the indentation of their line,
+    /// plus `hanging_indent` columns. At most half the width is used for indentation.
     #[inline]
-    pub fn get_cursor_col(&mut self) -> u32 {
-        self.cursor_col
+    pub fn set_wrap_indent(&mut self, enabled: bool, hanging_indent: u32) {
+        self.wrap_indent = enabled;
+        self.hanging_indent = hanging_indent;
+        self.invalidate_lines();
     }
-
     #[inline]
-    pub fn get_highlight_mark(&mut self) -> u32 {
-        self.highlight_mark
+    pub fn set_tab_width(&mut self, tab_width: u32) {
+        self.buffer.set_tab_width(tab_width);
+        self.invalidate_lines();
+    }
+    #[inline]
+    pub fn caret_shape(&self) -> CaretShape {
+        self.caret_shape
+    }
+    #[inline]
+    pub fn set_caret_shape(&mut self, caret_shape: CaretShape) {
+        self.caret_shape = caret_shape;
+    }
+    #[inline]
+    pub fn render_whitespace(&self) -> bool {
+        self.render_whitespace
+    }
+    /// Draw markers for spaces, tabs and line breaks
+    #[inline]
+    pub fn set_render_whitespace(&mut self, enabled: bool) {
+        self.render_whitespace = enabled;
     }
-
     #[inline]
-    pub fn get_cursor_abs(&mut self) -> u32 {
-        self.cursor_abs
+    pub fn highlight_current_line(&self) -> bool {
+        self.highlight_current_line
+    }
+    /// Tint the background of the row holding the cursor
+    #[inline]
+    pub fn set_highlight_current_line(&mut self, enabled: bool) {
+        self.highlight_current_line = enabled;
     }
 
-    pub fn get_text(&self) -> String {
-        self.content.iter().map(|fch| fch.ch).collect::<String>()
+    /// Color characters by range instead of the theme foreground, for syntax highlighting.
+    /// Where spans overlap, the last one wins.
+    pub fn set_color_spans(&mut self, spans: Vec<(Range<u32>, Color)>) {
+        self.color_spans = flatten_spans(&spans);
+        self.invalidate_lines();
     }
-    // #endregion
-    pub fn cursor_forward(&mut self) {
-        if let Some(fch) = self.content.get((self.cursor_abs) as usize) {
-            if fch.ch == '\n' {
-                println!("New line!");
-                self.cursor_col = 0;
-                self.cursor_row += 1;
-            } else {
-                self.cursor_col += 1;
+
+    /// Highlight the text with `highlighter`, or stop highlighting with None
+    pub fn set_highlighter(&mut self, highlighter: Option<Rc<dyn Highlighter>>) {
+        self.highlighter = highlighter;
+        self.highlighted_revision = None;
+        self.pending_highlight = None;
+        if self.highlighter.is_none() {
+            self.set_color_spans(Vec::new());
+        }
+    }
+
+    /// Run the highlighter once the text has not changed for a moment.
+    /// Returns whether the colors changed and the screen has to be redrawn.
+    pub fn update_highlight(&mut self, now: Instant) -> bool {
+        let Some(highlighter) = self.highlighter.clone() else {
+            return false;
+        };
+        let revision = self.buffer.revision();
+        if self.highlighted_revision == Some(revision) {
+            return false;
+        }
+        match self.pending_highlight {
+            Some((pending, since)) if pending == revision => {
+                if now.duration_since(since) < HIGHLIGHT_DELAY {
+                    return false;
+                }
+            }
+            _ => {
+                self.pending_highlight = Some((revision, now));
+                // the first highlight of a buffer does not wait for anything
+                if self.highlighted_revision.is_some() {
+                    return false;
+                }
             }
-            self.cursor_abs += 1;
         }
+        let text = self.buffer.get_text();
+        // the highlighter works on byte offsets, the screen on characters
+        let mut char_starts: Vec<usize> = text.char_indices().map(|(byte, _)| byte).collect();
+        char_starts.push(text.len());
+        let to_char = |byte: usize| char_starts.partition_point(|&start| start < byte) as u32;
+        let spans = highlighter
+            .highlight(&text)
+            .into_iter()
+            .map(|(range, color)| (to_char(range.start)..to_char(range.end), color))
+            .collect();
+        self.set_color_spans(spans);
+        self.highlighted_revision = Some(revision);
+        self.pending_highlight = None;
+        true
+    }
+
+    /// Time left until the text is highlighted again, if an edit is waiting for it
+    pub fn highlight_timeout(&self, now: Instant) -> Option<Duration> {
+        let (_, since) = self.pending_highlight?;
+        Some(HIGHLIGHT_DELAY.saturating_sub(now.duration_since(since)))
+    }
+
+    /// Color of the character at `idx`, None if no span covers it
+    fn span_color(&self, idx: usize) -> Option<Color> {
+        let idx = idx as u32;
+        let run = self
+            .color_spans
+            .partition_point(|(range, _)| range.end <= idx);
+        self.color_spans
+            .get(run)
+            .filter(|(range, _)| range.start <= idx)
+            .map(|(_, color)| *color)
+    }
+
+    /// Cache every logical line as its own texture, keyed `base + line index`
+    pub fn enable_line_cache(&mut self, base: usize) {
+        self.line_cache = Some(base);
+        self.invalidate_lines();
     }
 
-    pub fn cursor_back(&mut self) {
-        if let Some(_) = self.content.get((self.cursor_abs - 1) as usize) {
-            if self.cursor_col == 0 {
-                self.cursor_row -= 1;
-                self.cursor_col = self
-                    .content
-                    .iter()
-                    .rev()
-                    .take_while(|x| x.ch != '\n')
-                    .count() as u32;
+    /// Free the textures of the cached lines, e.g. before the screen is dropped
+    pub fn release_line_cache(&mut self, target: &mut Renderer<'_>) {
+        if let Some(base) = self.line_cache {
+            for line in 0..self.cached_lines {
+                target.line_textures.remove(&(base + line));
             }
-            self.cursor_col -= 1;
-            self.cursor_abs -= 1;
         }
+        self.cached_lines = 0;
     }
 
+    /// Lay out and render every line anew, e.g. after the theme or width changed
     #[inline]
-    pub fn push_char(&mut self, fch: Rc<FontChar>) {
-        self.content.insert(self.cursor_abs as usize, fch.clone());
-        self.cursor_forward();
+    pub fn invalidate_lines(&mut self) {
+        self.layout_dirty.set(true);
+        self.buffer.invalidate_lines();
+    }
+    /// Highlight every occurrence of `query`, or stop searching with `None`
+    pub fn set_find(&mut self, query: Option<String>) {
+        self.buffer.set_find(query);
+        self.find_changed = true;
     }
     #[inline]
-    pub fn pop_char(&mut self) -> Option<Rc<FontChar>> {
-        self.cursor_back();
-        if self.content.len() != 0 {
-            return Some(self.content.remove(self.cursor_abs as usize));
-        }
-        None
+    pub fn set_find_case_insensitive(&mut self, enabled: bool) {
+        self.buffer.set_find_case_insensitive(enabled);
+        self.find_changed = true;
     }
+    // #endregion
+
     #[inline]
-    pub fn push_string<T: Into<Vec<Rc<FontChar>>>>(&mut self, fstr: T) {
-        let fstr: Vec<Rc<FontChar>> = fstr.into();
-        for fch in fstr {
-            self.push_char(fch);
-        }
+    pub fn composition(&self) -> Option<&str> {
+        self.composition.as_deref()
+    }
+
+    /// Show the text an input method is composing, an empty string ends the composition
+    pub fn set_composition(&mut self, text: String) {
+        self.composition = (!text.is_empty()).then_some(text);
+        self.reset_blink();
+    }
+
+    /// Drop the composition, e.g. once the input method committed it as text input
+    #[inline]
+    pub fn clear_composition(&mut self) {
+        self.composition = None;
+    }
+
+    /// Swap every glyph for its counterpart in a newly built font, keeping the cursor in place
+    pub fn reload_glyphs(&mut self, font: &mut FontDef) {
+        self.buffer.reload_glyphs(font);
+        self.set_row_height(font.glyph_height as usize);
     }
     #[inline]
     pub fn clear(&mut self) {
-        self.cursor_col = 0;
-        self.cursor_row = 0;
-        self.cursor_abs = 0;
-        self.content.clear();
+        self.buffer.clear();
+        self.scroll_row = 0;
+        self.scroll_col_px = 0;
+        self.invalidate_lines();
     }
 
     pub fn render_highlight(target: &mut Renderer<'_>, region: Rect) {
-        use sdl2::pixels::Color;
-        use sdl2::render::BlendMode;
-        let highlight_color = Color::RGB(50, 50, 50);
+        let highlight_color = target.theme.selection;
 
         target.canvas.set_blend_mode(BlendMode::Add);
         target.canvas.set_draw_color(highlight_color);
@@ -259,7 +971,7 @@ impl TextScreen {
             .canvas
             .fill_rect(region)
             .map_err(|err| {
-                eprintln!(
+                error!(
                     "Could not highlight at {xpos} x {ypos}: {err}",
                     xpos = region.x(),
                     ypos = region.y()
@@ -269,56 +981,1255 @@ impl TextScreen {
         target.canvas.set_blend_mode(BlendMode::None);
     }
 
+    /// Cells of the visible whitespace characters, none unless whitespace is shown
+    fn whitespace_markers(
+        &self,
+        layout: &[(u32, u32)],
+        x: u32,
+        y: u32,
+        font: &FontDef,
+    ) -> Vec<(char, Rect)> {
+        if !self.render_whitespace {
+            return Vec::new();
+        }
+        let first_row = self.scroll_row as u32;
+        let last_row = first_row + self.visible_rows() as u32;
+        self.buffer
+            .content
+            .iter()
+            .zip(layout)
+            .filter(|(fch, &(_, row))| {
+                fch.ch.is_whitespace() && (first_row..last_row).contains(&row)
+            })
+            .filter_map(|(fch, &(x_offset, row))| {
+                let left = self
+                    .view_x(0, x_offset)
+                    .filter(|&left| left < self.width as u32)?;
+                let top = (row - first_row) * self.row_height as u32 + self.leading();
+                Some((
+                    fch.ch,
+                    Rect::new(
+                        (x + left) as i32,
+                        (y + top) as i32,
+                        self.char_advance(fch, x_offset, font),
+                        font.glyph_height,
+                    ),
+                ))
+            })
+            .collect()
+    }
+
+    /// Draw a dot for a space, an arrow for a tab and a box for a line break into `cell`
+    fn render_whitespace_marker(target: &mut Renderer<'_>, ch: char, cell: Rect) {
+        let size = (cell.height() / 12).max(1);
+        let center = cell.center();
+        let marks = match ch {
+            ' ' => vec![Rect::from_center(center, size, size)],
+            '\t' => vec![
+                Rect::new(
+                    cell.x() + size as i32,
+                    center.y(),
+                    cell.width().saturating_sub(2 * size),
+                    size,
+                ),
+                Rect::new(
+                    cell.right() - 2 * size as i32,
+                    center.y() - size as i32,
+                    size,
+                    3 * size,
+                ),
+            ],
+            '\n' => vec![Rect::new(
+                cell.x() + size as i32,
+                center.y() - 2 * size as i32,
+                4 * size,
+                4 * size,
+            )],
+            _ => return,
+        };
+        target.canvas.set_draw_color(target.theme.selection);
+        target
+            .canvas
+            .fill_rects(&marks)
+            .map_err(|err| {
+                error!("Could not draw whitespace marker for {ch:?}: {err}");
+            })
+            .unwrap();
+    }
+
+    /// Width of a tab starting at `x_offset`, so that it ends on the next tab stop
+    fn tab_advance(&self, x_offset: u32, font: &FontDef) -> u32 {
+        let tab_stop = self.buffer.tab_width * font.whitespace_width;
+        if tab_stop == 0 {
+            return font.whitespace_width.max(1);
+        }
+        tab_stop - x_offset % tab_stop
+    }
+
+    /// Horizontal space taken up by a character drawn at `x_offset`
+    fn char_advance(&self, fch: &FontChar, x_offset: u32, font: &FontDef) -> u32 {
+        if fch.ch == '\t' {
+            self.tab_advance(x_offset, font)
+        } else {
+            fch._ax.max(1)
+        }
+    }
+
+    /// Like `char_advance`, but keeping the fractional part of the glyph advance
+    fn char_advance_exact(&self, fch: &FontChar, x_offset: u32, font: &FontDef) -> f32 {
+        if fch.ch == '\t' {
+            self.tab_advance(x_offset, font) as f32
+        } else {
+            fch.advance.max(1.0)
+        }
+    }
+
+    /// Compute where the caret would be drawn for every position in the buffer,
+    /// as (x offset, visual row) pairs. Position `i` is also where character `i` starts.
+    /// Rows are broken according to the wrap mode.
+    /// The pen position keeps fractional advances and is only rounded per position.
+    /// The result is cached until the text, font or width changes.
+    fn layout(&self, x: u32, font: &FontDef) -> Rc<Vec<(u32, u32)>> {
+        let revision = self.buffer.revision();
+        if !self.layout_dirty.get() {
+            if let Some((cached_x, cached_revision, layout)) = &*self.layout_cache.borrow() {
+                if *cached_x == x && *cached_revision == revision {
+                    return Rc::clone(layout);
+                }
+            }
+        }
+        let layout = Rc::new(self.compute_layout(x, font));
+        *self.layout_cache.borrow_mut() = Some((x, revision, Rc::clone(&layout)));
+        self.layout_dirty.set(false);
+        layout
+    }
+
+    fn compute_layout(&self, x: u32, font: &FontDef) -> Vec<(u32, u32)> {
+        let width = self.width as u32;
+        let mut positions = Vec::with_capacity(self.buffer.content.len() + 1);
+        let mut row = 0u32;
+        let mut pen_x = 0.0f32;
+        let mut prev_whitespace = true;
+        let mut prev_char: Option<char> = None;
+        // set while a word too long for a whole line is being broken up
+        let mut breaking_word = false;
+        // where continuation rows of the current line start, see `set_wrap_indent`
+        let hanging_indent = (self.hanging_indent * font.measure_char(' ')) as f32;
+        let max_indent = width.saturating_sub(x) as f32 / 2.0;
+        let mut in_indent = true;
+        let mut continuation = 0.0f32;
+        for (idx, fch) in self.buffer.content.iter().enumerate() {
+            if in_indent && !(fch.ch == ' ' || fch.ch == '\t') {
+                in_indent = false;
+                if self.wrap_indent {
+                    continuation = (pen_x + hanging_indent).min(max_indent);
+                }
+            }
+            // move a word to the next line if it would overflow this one
+            if self.wrap_mode == WrapMode::Word && prev_whitespace && !fch.ch.is_whitespace() {
+                let word_width = self.word_width(idx);
+                let x_offset = pen_x.round() as u32;
+                if x_offset > continuation.round() as u32 && x + x_offset + word_width > width {
+                    pen_x = continuation;
+                    row += 1;
+                    prev_char = None;
+                }
+                breaking_word = x + pen_x.round() as u32 + word_width > width;
+            }
+            if let Some(prev) = prev_char {
+                pen_x = (pen_x + font.kerning(prev, fch.ch) as f32).max(0.0);
+            }
+            let x_offset = pen_x.round() as u32;
+            positions.push((x_offset, row));
+            pen_x += self.char_advance_exact(fch, x_offset, font);
+
+            // Line wrap and newline logic
+            let overflow = x + pen_x.round() as u32 + fch._ax > width;
+            let wrap = match self.wrap_mode {
+                WrapMode::Char => overflow,
+                WrapMode::Word => overflow && breaking_word && !fch.ch.is_whitespace(),
+                WrapMode::None => false,
+            };
+            if fch.ch == '\n' {
+                in_indent = true;
+                continuation = 0.0;
+            }
+            if wrap || fch.ch == '\n' {
+                pen_x = continuation;
+                row += 1;
+            }
+            prev_whitespace = fch.ch.is_whitespace();
+            // no kerning across line breaks
+            prev_char = if wrap || fch.ch == '\n' {
+                None
+            } else {
+                Some(fch.ch)
+            };
+        }
+        positions.push((pen_x.round() as u32, row));
+        positions
+    }
+
+    /// Width of the word starting at `pos`, up to the next whitespace
+    fn word_width(&self, pos: usize) -> u32 {
+        self.buffer
+            .content
+            .range(pos..self.buffer.content.len())
+            .take_while(|fch| !fch.ch.is_whitespace())
+            .map(|fch| fch._ax.max(1))
+            .sum()
+    }
+
+    #[inline]
+    fn visible_rows(&self) -> usize {
+        (self.height / self.row_height.max(1)).max(1)
+    }
+
+    #[inline]
+    pub fn scroll_row(&self) -> usize {
+        self.scroll_row
+    }
+
+    #[inline]
+    pub fn scroll_col_px(&self) -> u32 {
+        self.scroll_col_px
+    }
+
+    /// Screen position of `x_offset` in a row, or `None` if it is scrolled off to the left
+    #[inline]
+    fn view_x(&self, x: u32, x_offset: u32) -> Option<u32> {
+        x_offset
+            .checked_sub(self.scroll_col_px)
+            .map(|x_offset| x + x_offset)
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll_row = self.scroll_row.saturating_sub(rows);
+        self.buffer.follow_cursor = false;
+    }
+
+    /// Scroll towards the end, at most until the last row reaches the bottom of the screen
+    pub fn scroll_down(&mut self, rows: usize) {
+        let max_scroll_row = self.total_rows.saturating_sub(self.visible_rows());
+        self.scroll_row = (self.scroll_row + rows).min(max_scroll_row.max(self.scroll_row));
+        self.buffer.follow_cursor = false;
+    }
+
+    /// Convert a pixel position relative to the screen origin into an absolute buffer position.
+    /// This uses the same layout as `render_all`.
+    pub fn pos_from_pixel(&self, x: i32, y: i32, font: &FontDef) -> u32 {
+        let layout = self.layout(0, font);
+        let row = (y.max(0) as usize / self.row_height.max(1) + self.scroll_row) as u32;
+        let mut pos = self.buffer.content.len() as u32;
+        for (idx, fch) in self.buffer.content.iter().enumerate() {
+            let (x_offset, ch_row) = layout[idx];
+            if ch_row < row {
+                continue;
+            }
+            if ch_row > row {
+                break;
+            }
+            let ch_w = self.char_advance(fch, x_offset, font);
+            let x = x + self.scroll_col_px as i32;
+            if fch.ch == '\n' || x < (x_offset + ch_w / 2) as i32 {
+                return idx as u32;
+            }
+            // clicked past the end of a wrapped row
+            pos = idx as u32 + 1;
+        }
+        pos
+    }
+
+    /// Copy the visible part of every logical line from its cached texture,
+    /// rendering only the lines edited since the last frame anew
+    fn render_cached_lines(
+        &mut self,
+        target: &mut Renderer<'_>,
+        x: u32,
+        y: u32,
+        layout: &[(u32, u32)],
+        first_row: u32,
+        last_row: u32,
+    ) -> Result<(), String> {
+        let Some(base) = self.line_cache else {
+            return Ok(());
+        };
+        if let Some(from) = self.buffer.dirty_from.take() {
+            for line in from..self.cached_lines {
+                target.line_textures.remove(&(base + line));
+            }
+            self.cached_lines = self.cached_lines.min(from);
+        }
+        for line in self.buffer.dirty_lines.drain() {
+            target.line_textures.remove(&(base + line));
+        }
+
+        let row_height = self.row_height as u32;
+        let len = self.buffer.content.len();
+        let mut start = 0;
+        let mut line = 0;
+        while start <= len {
+            let end = self
+                .content
+                .range(start..len)
+                .position(|fch| fch.ch == '\n')
+                .map_or(len, |idx| start + idx);
+            let (row_start, row_end) = (layout[start].1, layout[end].1);
+            if row_start >= last_row {
+                break;
+            }
+            if row_end >= first_row {
+                let key = base + line;
+                if !target.line_textures.contains_key(&key) {
+                    self.render_line(target, key, start..end, layout, row_start, row_end)?;
+                }
+                let texture = target.line_textures.get(&key).unwrap();
+                let top = row_start.max(first_row);
+                let bottom = (row_end + 1).min(last_row);
+                let src = Rect::new(
+                    0,
+                    ((top - row_start) * row_height) as i32,
+                    self.width as u32,
+                    (bottom - top) * row_height,
+                );
+                let dst = Rect::new(
+                    x as i32,
+                    (y + (top - first_row) * row_height) as i32,
+                    self.width as u32,
+                    (bottom - top) * row_height,
+                );
+                target.canvas.copy(&texture.borrow(), src, dst)?;
+            }
+            self.cached_lines = self.cached_lines.max(line + 1);
+            start = end + 1;
+            line += 1;
+        }
+        Ok(())
+    }
+
+    /// Draw the characters in `chars`, spanning rows `row_start..=row_end`, into a new texture
+    fn render_line(
+        &self,
+        target: &mut Renderer<'_>,
+        key: usize,
+        chars: Range<usize>,
+        layout: &[(u32, u32)],
+        row_start: u32,
+        row_end: u32,
+    ) -> Result<(), String> {
+        let row_height = self.row_height as u32;
+        let glyphs = self.line_glyphs(chars, layout, row_start, &mut target.loaded_font);
+        let texture = target.line_textures.create(
+            key,
+            (self.width as u32).max(1),
+            ((row_end - row_start + 1) * row_height).max(1),
+        )?;
+        let max_ascent = target.loaded_font.max_ascent as i32;
+        let Renderer {
+            canvas,
+            texture_manager,
+            theme,
+            debug_overlay,
+            ..
+        } = target;
+        // the vertex colors replace the color mod of the atlas
+        let batches = batch_glyphs(
+            glyphs
+                .iter()
+                .map(|(fch, dst, color)| (fch.as_ref(), *dst, color.unwrap_or(theme.foreground))),
+            |atlas| {
+                let query = texture_manager.get(&atlas)?.borrow().query();
+                Some((query.width, query.height))
+            },
+        );
+        let mut texture = texture.borrow_mut();
+        canvas
+            .with_texture_canvas(&mut texture, |line_canvas| {
+                line_canvas.set_draw_color(theme.background);
+                line_canvas.clear();
+                for batch in &batches {
+                    let Some(atlas) = texture_manager.get(&batch.atlas) else {
+                        continue;
+                    };
+                    let mut atlas = atlas.borrow_mut();
+                    atlas.set_blend_mode(BlendMode::Blend);
+                    batch.render(line_canvas, &atlas).unwrap_or_else(|err| {
+                        error!("Failed to render the glyphs of line {key}: {err}");
+                    });
+                }
+                if *debug_overlay {
+                    Self::render_debug_overlay(
+                        line_canvas,
+                        &glyphs,
+                        row_end - row_start + 1,
+                        row_height,
+                        self.leading() as i32,
+                        max_ascent,
+                    );
+                }
+            })
+            .map_err(|err| format!("Could not render line {key}: {err}"))?;
+        Ok(())
+    }
+
+    /// Draw the glyphs of rows `first_row..last_row` straight onto the canvas at (x, y),
+    /// for screens without a line cache
+    fn render_visible_glyphs(
+        &self,
+        target: &mut Renderer<'_>,
+        x: u32,
+        y: u32,
+        layout: &[(u32, u32)],
+        first_row: u32,
+        last_row: u32,
+    ) -> Result<(), String> {
+        let start = layout.partition_point(|&(_, row)| row < first_row);
+        let end = layout
+            .partition_point(|&(_, row)| row < last_row)
+            .min(self.content.len());
+        let glyphs = self.line_glyphs(start..end, layout, first_row, &mut target.loaded_font);
+        for (fch, dst, _) in glyphs {
+            // `render` aligns the glyph to the pen position again
+            let pen_x = x as i32 + dst.x() - fch.quad.x();
+            let pen_y = y as i32 + dst.y() - fch.quad.y();
+            fch.render(target, pen_x as u32, pen_y as u32)
+                .map_err(|err| format!("Failed to render character {:?}: {err}", fch.ch))?;
+        }
+        Ok(())
+    }
+
+    /// The visible glyphs of the characters in `chars` with where they go relative to
+    /// the top of row `row_start`, and their color if a span covers them.
+    /// The text is shaped first, so a ligature replaces the glyphs of the characters it joins.
+    /// Characters keep their place in the layout, rows before `row_start` are left out.
+    fn line_glyphs(
+        &self,
+        chars: Range<usize>,
+        layout: &[(u32, u32)],
+        row_start: u32,
+        font: &mut FontDef,
+    ) -> Vec<(Rc<FontChar>, Rect, Option<Color>)> {
+        let text: String = self
+            .content
+            .range(chars.clone())
+            .map(|fch| fch.ch)
+            .collect();
+        let substitutes: HashMap<usize, Option<Rc<FontChar>>> = font
+            .shape_string(&text)
+            .into_iter()
+            .map(|(idx, glyph)| (chars.start + idx, glyph))
+            .collect();
+        let row_height = self.row_height as u32;
+        self.content
+            .range(chars.clone())
+            .zip(chars)
+            .filter(|(fch, _)| !fch.ch.is_whitespace())
+            .filter_map(|(fch, idx)| {
+                let fch = match substitutes.get(&idx) {
+                    Some(glyph) => glyph.as_ref()?,
+                    None => fch,
+                };
+                let (x_offset, row) = layout[idx];
+                let top = row.checked_sub(row_start)? * row_height + self.leading();
+                let left = self
+                    .view_x(0, x_offset)
+                    .filter(|&left| left < self.width as u32)?;
+                let dst = font.get_char_aligned_rect(left as i32, top as i32, fch);
+                Some((Rc::clone(fch), dst, self.span_color(idx)))
+            })
+            .collect()
+    }
+
+    /// Outline every glyph, draw the baseline of every row and mark where each glyph's pen position is
+    fn render_debug_overlay<T: sdl2::render::RenderTarget>(
+        canvas: &mut sdl2::render::Canvas<T>,
+        glyphs: &[(Rc<FontChar>, Rect, Option<Color>)],
+        rows: u32,
+        row_height: u32,
+        leading: i32,
+        max_ascent: i32,
+    ) {
+        canvas.set_blend_mode(BlendMode::Blend);
+        let width = canvas.output_size().map_or(0, |(width, _)| width as i32);
+        canvas.set_draw_color(DEBUG_BASELINE_COLOR);
+        for row in 0..rows {
+            let y = (row * row_height) as i32 + leading + max_ascent;
+            canvas
+                .draw_line(Point::new(0, y), Point::new(width, y))
+                .unwrap_or_else(|err| error!("Could not draw the baseline: {err}"));
+        }
+        canvas.set_draw_color(DEBUG_BOX_COLOR);
+        let boxes: Vec<Rect> = glyphs.iter().map(|(_, dst, _)| *dst).collect();
+        canvas
+            .draw_rects(&boxes)
+            .unwrap_or_else(|err| error!("Could not draw the glyph boxes: {err}"));
+        canvas.set_draw_color(DEBUG_ORIGIN_COLOR);
+        let origins: Vec<Rect> = glyphs
+            .iter()
+            .map(|(fch, dst, _)| {
+                // the pen position sits on the baseline, left of the glyph quad
+                let x = dst.x() - fch.quad.x();
+                let y = dst.y() - fch.quad.y() + max_ascent;
+                Rect::new(x - 1, y - 1, 3, 3)
+            })
+            .collect();
+        canvas
+            .fill_rects(&origins)
+            .unwrap_or_else(|err| error!("Could not draw the glyph origins: {err}"));
+    }
+
+    /// Scroll so the caret is visible, if it is supposed to be followed
+    fn scroll_into_view(&mut self, layout: &[(u32, u32)], font: &FontDef) {
+        if !self.buffer.follow_cursor {
+            return;
+        }
+        let visible_rows = self.visible_rows();
+        let (cursor_x, cursor_row) = layout[self.buffer.cursor_abs as usize];
+        let cursor_row = cursor_row as usize;
+        if cursor_row < self.scroll_row {
+            self.scroll_row = cursor_row;
+        } else if cursor_row >= self.scroll_row + visible_rows {
+            self.scroll_row = cursor_row + 1 - visible_rows;
+        }
+        if self.wrap_mode == WrapMode::None {
+            let scroll_col_px = self.clamp_scroll_col(cursor_x, font.whitespace_width);
+            if scroll_col_px != self.scroll_col_px {
+                self.scroll_col_px = scroll_col_px;
+                self.invalidate_lines();
+            }
+        }
+        self.buffer.follow_cursor = false;
+    }
+
+    /// Horizontal scroll offset that keeps a caret at `cursor_x`, and the cell after it, in view
+    fn clamp_scroll_col(&self, cursor_x: u32, cell_width: u32) -> u32 {
+        let width = self.width as u32;
+        if cursor_x < self.scroll_col_px {
+            cursor_x
+        } else if cursor_x + cell_width > self.scroll_col_px + width {
+            (cursor_x + cell_width).saturating_sub(width)
+        } else {
+            self.scroll_col_px
+        }
+    }
+
+    /// Where the caret is drawn, if it is inside the viewport
+    fn cursor_rect(&self, layout: &[(u32, u32)], x: u32, y: u32, font: &FontDef) -> Option<Rect> {
+        let (cursor_x, cursor_row) = layout[self.buffer.cursor_abs as usize];
+        let first_row = self.scroll_row as u32;
+        if !(first_row..first_row + self.visible_rows() as u32).contains(&cursor_row) {
+            return None;
+        }
+        let left = self.view_x(x, cursor_x)? as i32;
+        let top = (y + (cursor_row - first_row) * self.row_height as u32 + self.leading()) as i32;
+        let thickness = font.glyph_width / 16;
+        // width of the character under the caret
+        let cell_width = match self.buffer.content.get(self.buffer.cursor_abs as usize) {
+            Some(fch) if fch.ch != '\n' => self.char_advance(fch, cursor_x, font),
+            _ => font.whitespace_width,
+        };
+        let rect = match self.caret_shape {
+            CaretShape::Bar => {
+                let bearing = match self.buffer.cursor_abs {
+                    0 => 0,
+                    pos => self.buffer.content[pos as usize - 1].bl,
+                };
+                Rect::new(left + bearing, top, thickness, font.glyph_height)
+            }
+            CaretShape::Block => Rect::new(left, top, cell_width, font.glyph_height),
+            CaretShape::Underline => {
+                Rect::new(left, top + font.max_ascent as i32, cell_width, thickness)
+            }
+        };
+        Some(rect)
+    }
+
+    /// Cell taken up by the character at `pos`, if it is inside the viewport
+    fn char_rect(
+        &self,
+        layout: &[(u32, u32)],
+        pos: u32,
+        x: u32,
+        y: u32,
+        font: &FontDef,
+    ) -> Option<Rect> {
+        let fch = self.buffer.content.get(pos as usize)?;
+        let (x_offset, row) = layout[pos as usize];
+        let first_row = self.scroll_row as u32;
+        if !(first_row..first_row + self.visible_rows() as u32).contains(&row) {
+            return None;
+        }
+        Some(Rect::new(
+            self.view_x(x, x_offset)? as i32,
+            (y + (row - first_row) * self.row_height as u32 + self.leading()) as i32,
+            self.char_advance(fch, x_offset, font),
+            font.glyph_height,
+        ))
+    }
+
+    /// Boxes around the bracket next to the caret and its partner
+    fn bracket_rects(&self, layout: &[(u32, u32)], x: u32, y: u32, font: &FontDef) -> Vec<Rect> {
+        let (Some(pos), Some(partner)) = (self.adjacent_bracket(), self.matching_bracket()) else {
+            return Vec::new();
+        };
+        [pos, partner]
+            .into_iter()
+            .filter_map(|pos| self.char_rect(layout, pos, x, y, font))
+            .collect()
+    }
+
+    /// The full-width visual row holding the caret, if it is inside the viewport
+    fn current_line_rect(&self, layout: &[(u32, u32)], x: u32, y: u32) -> Option<Rect> {
+        let cursor_row = layout[self.buffer.cursor_abs as usize].1;
+        let first_row = self.scroll_row as u32;
+        if !(first_row..first_row + self.visible_rows() as u32).contains(&cursor_row) {
+            return None;
+        }
+        Some(Rect::new(
+            x as i32,
+            (y + (cursor_row - first_row) * self.row_height as u32) as i32,
+            self.width as u32,
+            self.row_height as u32,
+        ))
+    }
+
+    /// Track and thumb of the scrollbar on the right edge of the screen at (x, y).
+    /// There is none while the whole text fits into the viewport.
+    fn scrollbar_rects(&self, x: u32, y: u32) -> Option<(Rect, Rect)> {
+        let visible_rows = self.visible_rows();
+        if self.total_rows <= visible_rows {
+            return None;
+        }
+        let track_height = self.height as u32;
+        let track = Rect::new(
+            (x + (self.width as u32).saturating_sub(SCROLLBAR_WIDTH)) as i32,
+            y as i32,
+            SCROLLBAR_WIDTH,
+            track_height,
+        );
+        let thumb_height = ((track_height as usize * visible_rows / self.total_rows) as u32)
+            .clamp(SCROLLBAR_MIN_THUMB.min(track_height), track_height);
+        let max_scroll_row = self.total_rows - visible_rows;
+        let thumb_top = (track_height - thumb_height) as usize
+            * self.scroll_row.min(max_scroll_row)
+            / max_scroll_row;
+        let thumb = Rect::new(
+            track.x(),
+            track.y() + thumb_top as i32,
+            SCROLLBAR_WIDTH,
+            thumb_height,
+        );
+        Some((track, thumb))
+    }
+
+    /// Draw the scrollbar showing which part of the text is visible
+    fn render_scrollbar(&self, target: &mut Renderer<'_>, x: u32, y: u32) {
+        let Some((track, thumb)) = self.scrollbar_rects(x, y) else {
+            return;
+        };
+        target.canvas.set_draw_color(target.theme.scrollbar_track);
+        target.canvas.fill_rect(track).unwrap();
+        target.canvas.set_draw_color(target.theme.scrollbar_thumb);
+        target.canvas.fill_rect(thumb).unwrap();
+    }
+
+    /// Screen area that has to be repainted before the next `render_all` at (x, y).
+    /// If only the caret moved or blinked, this is just its old and new position.
+    pub fn damage(&mut self, x: u32, y: u32, font: &FontDef) -> Rect {
+        let area = Rect::new(x as i32, y as i32, self.width as u32, self.height as u32);
+        let layout = self.layout(x, font);
+        self.scroll_into_view(&layout, font);
+        let edited = !self.buffer.dirty_lines.is_empty() || self.buffer.dirty_from.is_some();
+        if edited
+            || self.find_changed
+            || self.scroll_row != self.rendered_scroll_row
+            || self.rendered_selection
+            || self.selection_range().is_some()
+            || self.rendered_composition
+            || self.composition.is_some()
+        {
+            return area;
+        }
+        let mut rects = vec![self.rendered_cursor, self.cursor_rect(&layout, x, y, font)];
+        rects.extend(self.rendered_brackets.iter().copied().map(Some));
+        rects.extend(
+            self.bracket_rects(&layout, x, y, font)
+                .into_iter()
+                .map(Some),
+        );
+        if self.highlight_current_line {
+            rects.push(self.rendered_current_line);
+            rects.push(self.current_line_rect(&layout, x, y));
+        }
+        rects
+            .into_iter()
+            .flatten()
+            .reduce(|a, b| a.union(b))
+            .unwrap_or(area)
+    }
+
+    /// Draw the visible part of the buffer at (x, y).
+    /// Returns the bounds of the whole laid out text: as wide as its widest row
+    /// and as high as all of its rows, regardless of scrolling.
     pub fn render_all(
         &mut self,
         target: &mut Renderer<'_>,
         x: u32,
         y: u32,
     ) -> Result<Rect, String> {
+        let layout = self.layout(x, &target.loaded_font);
+        self.scroll_into_view(&layout, &target.loaded_font);
+        let first_row = self.scroll_row as u32;
+        let last_row = first_row + self.visible_rows() as u32;
+        let cached = self.line_cache.is_some();
+        if cached {
+            self.render_cached_lines(target, x, y, &layout, first_row, last_row)?;
+        } else {
+            self.buffer.dirty_lines.clear();
+            self.buffer.dirty_from = None;
+            self.render_visible_glyphs(target, x, y, &layout, first_row, last_row)?;
+        }
+
         let mut cur_abs = 0u32;
-        let mut y_offset = 0u32;
-        let mut x_offset = 0u32;
-        for fch in &self.content {
-            // decide if we must render or not, we do not want whitespaces to be rendered.
-            let dst = if fch.ch.is_whitespace() {
-                target.loaded_font.get_char_aligned_rect(
-                    (x + x_offset) as i32,
+        let selection = self.selection_range();
+        for fch in &self.buffer.content {
+            let (x_offset, row) = layout[cur_abs as usize];
+            cur_abs += 1;
+            // skip lines outside of the viewport
+            if row < first_row {
+                continue;
+            }
+            if row >= last_row {
+                break;
+            }
+            let y_offset = (row - first_row) * self.row_height as u32 + self.leading();
+            // clip lines running past the screen edges
+            let Some(left) = self.view_x(0, x_offset) else {
+                continue;
+            };
+            if left >= self.width as u32 {
+                continue;
+            }
+
+            // the glyphs are already on screen, only the highlight and cursor are left
+            let dst = if cached || !fch.ch.is_whitespace() {
+                Rect::new(
+                    (x + left) as i32,
+                    (y + y_offset) as i32,
+                    self.char_advance(fch, x_offset, &target.loaded_font),
+                    target.loaded_font.glyph_height,
+                )
+            } else if fch.ch == '\t' {
+                Rect::new(
+                    (x + left) as i32,
                     (y + y_offset) as i32,
-                    &fch,
+                    self.tab_advance(x_offset, &target.loaded_font),
+                    target.loaded_font.glyph_height,
                 )
             } else {
-                fch.render(target, x + x_offset, y + y_offset)
-                    .map_err(|err| {
-                        eprintln!("Failed to render character {ch}: {err}", ch = fch.ch);
-                    })
-                    .unwrap()
+                target.loaded_font.get_char_aligned_rect(
+                    (x + left) as i32,
+                    (y + y_offset) as i32,
+                    fch,
+                )
             };
 
-            cur_abs += 1;
-            x_offset += dst.width();
-
             // Extend the highlight region on this line
-            if self.cursor_enabled() && self.highlight_mark < cur_abs && cur_abs <= self.cursor_abs
-            {
-                Self::render_highlight(target, dst);
+            if let Some((start, end)) = selection {
+                if self.cursor_enabled() && start < cur_abs && cur_abs <= end {
+                    Self::render_highlight(target, dst);
+                }
             }
+        }
 
-            // Line wrap and newline logic
-            if x + x_offset + fch._ax > self.width as u32 || fch.ch == '\n' {
-                x_offset = 0;
-                y_offset += self.row_height as u32;
+        for (ch, cell) in self.whitespace_markers(&layout, x, y, &target.loaded_font) {
+            Self::render_whitespace_marker(target, ch, cell);
+        }
+
+        for found in self.find_matches() {
+            for pos in found {
+                if let Some(cell) = self.char_rect(&layout, pos, x, y, &target.loaded_font) {
+                    Self::render_highlight(target, cell);
+                }
             }
+        }
+        self.find_changed = false;
 
-            // Render the cursor if we are at the right place
-            if self.cursor_enabled() && self.cursor_abs == cur_abs {
-                dbg!(x_offset, y_offset);
-                self.put_cursor(
-                    target,
-                    (x + x_offset) as i32 + fch.bl,
-                    (y + y_offset) as i32,
-                );
+        // added on top, as the glyphs are copied opaquely
+        self.rendered_current_line = None;
+        if self.highlight_current_line {
+            self.rendered_current_line = self.current_line_rect(&layout, x, y);
+            if let Some(line) = self.rendered_current_line {
+                target.canvas.set_blend_mode(BlendMode::Add);
+                target.canvas.set_draw_color(target.theme.current_line);
+                target.canvas.fill_rect(line).unwrap();
+                target.canvas.set_blend_mode(BlendMode::None);
             }
         }
-        Ok(Rect::new(x as i32, y as i32, x + x_offset, y_offset))
+
+        self.rendered_brackets.clear();
+        if self.cursor_enabled() {
+            self.rendered_brackets = self.bracket_rects(&layout, x, y, &target.loaded_font);
+            target.canvas.set_draw_color(target.theme.selection);
+            target.canvas.draw_rects(&self.rendered_brackets).unwrap();
+        }
+
+        self.rendered_composition = false;
+        if let Some(text) = self.composition.clone() {
+            self.render_composition(target, &layout, x, y, text);
+            self.rendered_composition = true;
+        }
+
+        self.rendered_cursor = self.cursor_rect(&layout, x, y, &target.loaded_font);
+        if let Some(dst) = self.rendered_cursor {
+            self.put_cursor(target, dst);
+        }
+        self.rendered_scroll_row = self.scroll_row;
+        self.total_rows = layout[layout.len() - 1].1 as usize + 1;
+        self.render_scrollbar(target, x, y);
+        self.rendered_selection = selection.is_some();
+        Ok(self.text_bounds(&layout, x, y, &target.loaded_font))
+    }
+
+    /// Area the text of `layout` takes up when drawn at (x, y):
+    /// as wide as the widest row and as high as all rows together
+    fn text_bounds(&self, layout: &[(u32, u32)], x: u32, y: u32, font: &FontDef) -> Rect {
+        let text_width = self
+            .content
+            .iter()
+            .zip(layout.iter())
+            .map(|(fch, &(x_offset, _))| x_offset + self.char_advance(fch, x_offset, font))
+            .chain(layout.last().map(|&(x_offset, _)| x_offset))
+            .max()
+            .unwrap_or(0);
+        let rows = layout.last().map_or(1, |&(_, row)| row + 1);
+        Rect::new(
+            x as i32,
+            y as i32,
+            text_width,
+            rows * self.row_height as u32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res_man::tests::monospace_font;
+
+    /// A screen showing `text` in a font whose glyphs are 8 pixels wide and 10 high
+    fn screen(text: &str, width: usize) -> (TextScreen, FontDef) {
+        let mut font = monospace_font(8);
+        let mut screen = TextScreen::new(width, 100, 10);
+        screen.push_string(font.get_string_lossy(text));
+        (screen, font)
+    }
+
+    #[test]
+    fn caret_toggles_once_per_blink_interval() {
+        let mut screen = TextScreen::new(800, 100, 10);
+        screen.set_blink_interval(Duration::from_millis(500));
+        screen.reset_blink();
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        assert!(!screen.update_blink(at(100)));
+        assert!(screen.cursor_visible);
+        assert!(screen.update_blink(at(600)));
+        assert!(!screen.cursor_visible);
+        assert_eq!(screen.blink_timeout(at(700)), Duration::from_millis(400));
+        assert!(!screen.update_blink(at(700)));
+        assert!(screen.update_blink(at(1100)));
+        assert!(screen.cursor_visible);
+    }
+
+    #[test]
+    fn line_spacing_rounds_the_row_advance_up() {
+        assert_eq!(row_advance(10, 1.0), 10);
+        assert_eq!(row_advance(10, 1.25), 13);
+        assert_eq!(row_advance(15, 1.5), 23);
+        let (mut screen, font) = screen("a\nb", 800);
+        screen.set_line_spacing(1.25);
+        assert_eq!(screen.row_height(), 13);
+        // the extra space is split above and below the glyphs
+        assert_eq!(screen.leading(), 1);
+        assert_eq!(screen.pos_from_pixel(0, 14, &font), 2);
+    }
+
+    #[test]
+    fn whitespace_markers_only_when_enabled() {
+        let (mut screen, font) = screen("a b\tc", 800);
+        let layout = screen.layout(0, &font);
+        assert!(screen.whitespace_markers(&layout, 0, 0, &font).is_empty());
+        screen.set_render_whitespace(true);
+        let markers: Vec<(char, i32)> = screen
+            .whitespace_markers(&layout, 0, 0, &font)
+            .into_iter()
+            .map(|(ch, cell)| (ch, cell.x()))
+            .collect();
+        assert_eq!(markers, [(' ', 8), ('\t', 24)]);
+    }
+
+    #[test]
+    fn scrolling_stops_at_both_ends() {
+        // 10 rows fit on the screen
+        let mut screen = TextScreen::new(800, 100, 10);
+        screen.total_rows = 25;
+        screen.scroll_up(3);
+        assert_eq!(screen.scroll_row(), 0);
+        screen.scroll_down(10);
+        assert_eq!(screen.scroll_row(), 10);
+        // the last row stops at the bottom of the screen
+        screen.scroll_down(10);
+        assert_eq!(screen.scroll_row(), 15);
+        screen.scroll_up(4);
+        assert_eq!(screen.scroll_row(), 11);
+        screen.scroll_up(40);
+        assert_eq!(screen.scroll_row(), 0);
+    }
+
+    #[test]
+    fn scrollbar_thumb_shows_the_visible_part() {
+        // 10 of 40 rows fit on the screen
+        let mut screen = TextScreen::new(800, 100, 10);
+        screen.total_rows = 40;
+        let (track, thumb) = screen.scrollbar_rects(5, 20).unwrap();
+        assert_eq!(track, Rect::new(5 + 800 - 6, 20, 6, 100));
+        assert_eq!(thumb, Rect::new(track.x(), 20, 6, 25));
+
+        screen.scroll_down(15);
+        let (_, thumb) = screen.scrollbar_rects(5, 20).unwrap();
+        assert_eq!(thumb.y(), 20 + 75 * 15 / 30);
+        // at the end the thumb touches the bottom of the track
+        screen.scroll_down(40);
+        let (track, thumb) = screen.scrollbar_rects(5, 20).unwrap();
+        assert_eq!(thumb.bottom(), track.bottom());
+
+        // a long text still gets a thumb that can be grabbed
+        screen.total_rows = 1000;
+        let (_, thumb) = screen.scrollbar_rects(5, 20).unwrap();
+        assert_eq!(thumb.height(), SCROLLBAR_MIN_THUMB);
+    }
+
+    #[test]
+    fn no_scrollbar_when_the_text_fits() {
+        let mut screen = TextScreen::new(800, 100, 10);
+        screen.total_rows = 10;
+        assert_eq!(screen.scrollbar_rects(0, 0), None);
+        screen.total_rows = 11;
+        assert!(screen.scrollbar_rects(0, 0).is_some());
+    }
+
+    #[test]
+    fn every_caret_shape_covers_its_own_area() {
+        let mut font = monospace_font(32);
+        let mut screen = TextScreen::new(800, 100, 10);
+        screen.push_string(font.get_string_lossy("ab"));
+        screen.move_cursor_to(1);
+        let layout = screen.layout(0, &font);
+        let mut rect_for = |shape| {
+            screen.set_caret_shape(shape);
+            screen.cursor_rect(&layout, 0, 0, &font)
+        };
+        assert_eq!(rect_for(CaretShape::Bar), Some(Rect::new(32, 0, 2, 10)));
+        assert_eq!(rect_for(CaretShape::Block), Some(Rect::new(32, 0, 32, 10)));
+        assert_eq!(
+            rect_for(CaretShape::Underline),
+            Some(Rect::new(32, 8, 32, 2))
+        );
+    }
+
+    #[test]
+    fn horizontal_scroll_keeps_the_caret_and_next_cell_in_view() {
+        let mut screen = TextScreen::new(80, 100, 10);
+        // near the end of a long line, the cell after the caret has to fit as well
+        assert_eq!(screen.clamp_scroll_col(76, 8), 4);
+        assert_eq!(screen.clamp_scroll_col(72, 8), 0);
+        screen.scroll_col_px = 40;
+        assert_eq!(screen.clamp_scroll_col(100, 8), 40);
+        assert_eq!(screen.clamp_scroll_col(120, 8), 48);
+        // back towards the start of the line
+        assert_eq!(screen.clamp_scroll_col(16, 8), 16);
+    }
+
+    #[test]
+    fn pos_from_pixel_picks_the_nearest_caret_position() {
+        let (screen, font) = screen("abc\ndef", 800);
+        assert_eq!(screen.pos_from_pixel(0, 0, &font), 0);
+        assert_eq!(screen.pos_from_pixel(9, 0, &font), 1);
+        assert_eq!(screen.pos_from_pixel(13, 5, &font), 2);
+        // past the end of a line lands on its line break
+        assert_eq!(screen.pos_from_pixel(100, 0, &font), 3);
+        assert_eq!(screen.pos_from_pixel(1, 15, &font), 4);
+        assert_eq!(screen.pos_from_pixel(100, 15, &font), 7);
+        // below the last row is the end of the text
+        assert_eq!(screen.pos_from_pixel(0, 95, &font), 7);
+    }
+
+    #[test]
+    fn dragging_upwards_selects_from_the_drop_to_the_mark() {
+        let (mut screen, font) = screen("abc\ndef", 800);
+        // press on the second row, then drag up to the first one
+        let pressed = screen.pos_from_pixel(9, 15, &font);
+        screen.move_cursor_to(pressed);
+        screen.set_highlight_mark(pressed);
+        let dropped = screen.pos_from_pixel(9, 0, &font);
+        screen.move_cursor_to(dropped);
+        assert_eq!(screen.selection_range(), Some((1, 5)));
+        assert_eq!(screen.selected_text().as_deref(), Some("bc\nd"));
+    }
+
+    #[test]
+    fn tab_advances_to_the_next_tab_stop() {
+        let (mut screen, font) = screen("ab\tc", 800);
+        screen.set_tab_width(4);
+        let layout = screen.layout(0, &font);
+        // the tab starts at column 2 and the character after it at column 4
+        assert_eq!(layout[2], (16, 0));
+        assert_eq!(layout[3], (32, 0));
+    }
+
+    #[test]
+    fn same_width_keeps_the_cached_layout() {
+        let (mut screen, font) = screen("hello world", 100);
+        let layout = screen.layout(0, &font);
+        screen.set_width(100);
+        assert!(!screen.layout_dirty.get());
+        assert!(Rc::ptr_eq(&layout, &screen.layout(0, &font)));
+
+        // a new width reflows the text
+        screen.set_width(48);
+        assert!(screen.layout_dirty.get());
+        let narrow = screen.layout(0, &font);
+        assert!(!Rc::ptr_eq(&layout, &narrow));
+        assert_eq!(layout.last(), Some(&(88, 0)));
+        assert!(narrow.last().unwrap().1 > 0);
+    }
+
+    #[test]
+    fn text_bounds_cover_the_widest_row_and_all_rows() {
+        let (screen, font) = screen("ab\nwxyz", 100);
+        let layout = screen.layout(5, &font);
+        assert_eq!(
+            screen.text_bounds(&layout, 5, 7, &font),
+            Rect::new(5, 7, 32, 20)
+        );
+    }
+
+    #[test]
+    fn glyphs_in_a_span_take_its_color() {
+        let red = Color::RGB(255, 0, 0);
+        let (mut screen, mut font) = screen("ab red", 100);
+        screen.set_color_spans(vec![(3..6, red)]);
+        let layout = screen.layout(0, &font);
+        let glyphs = screen.line_glyphs(0..6, &layout, 0, &mut font);
+        let colors: Vec<(char, Option<Color>)> = glyphs
+            .iter()
+            .map(|(fch, _, color)| (fch.ch, *color))
+            .collect();
+        assert_eq!(
+            colors,
+            [
+                ('a', None),
+                ('b', None),
+                ('r', Some(red)),
+                ('e', Some(red)),
+                ('d', Some(red))
+            ]
+        );
+
+        // the span color ends up in the vertices of the glyph quads
+        let batches = batch_glyphs(
+            glyphs
+                .iter()
+                .map(|(fch, dst, color)| (fch.as_ref(), *dst, color.unwrap_or(Color::WHITE))),
+            |_| Some((64, 64)),
+        );
+        let vertex_colors: Vec<[u8; 4]> = batches
+            .iter()
+            .flat_map(|batch| batch.vertices.iter().map(|vertex| vertex.color))
+            .collect();
+        assert_eq!(vertex_colors.len(), 5 * 4);
+        assert!(vertex_colors[..8].iter().all(|color| *color == [255; 4]));
+        assert!(vertex_colors[8..]
+            .iter()
+            .all(|color| *color == [255, 0, 0, 255]));
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    fn deleting_part_of_a_ligature_draws_the_rest_again() {
+        use crate::shaping::tests::{with_not_equal_ligature, BUNDLED_FONT};
+
+        let data = Rc::new(with_not_equal_ligature(BUNDLED_FONT));
+        let mut font = crate::rasterize_font(data, 16, &[crate::ANSI_RANGE], (1024, 1024))
+            .unwrap()
+            .font;
+        let mut screen = TextScreen::new(800, 100, font.glyph_height as usize);
+        screen.push_string(font.get_string_lossy("a != b"));
+        let drawn = |screen: &TextScreen, font: &mut FontDef| {
+            let layout = screen.layout(0, font);
+            screen
+                .line_glyphs(0..screen.content.len(), &layout, 0, font)
+                .into_iter()
+                .map(|(fch, _, _)| fch)
+                .collect::<Vec<_>>()
+        };
+        let glyphs = drawn(&screen, &mut font);
+        let chars: Vec<char> = glyphs.iter().map(|fch| fch.ch).collect();
+        assert_eq!(chars, ['a', '!', 'b']);
+        let plain = font.get_char('!' as usize).unwrap();
+        assert!(!Rc::ptr_eq(&glyphs[1], &plain));
+
+        screen.move_cursor_to(3);
+        screen.pop_char();
+        assert_eq!(screen.get_text(), "a = b");
+        let glyphs = drawn(&screen, &mut font);
+        let chars: Vec<char> = glyphs.iter().map(|fch| fch.ch).collect();
+        assert_eq!(chars, ['a', '=', 'b']);
+        let equal = font.get_char('=' as usize).unwrap();
+        assert!(Rc::ptr_eq(&glyphs[1], &equal));
+    }
+
+    #[test]
+    fn later_spans_win_where_they_overlap() {
+        let (red, blue) = (Color::RGB(255, 0, 0), Color::RGB(0, 0, 255));
+        let (mut screen, _) = screen("overlapping", 100);
+        screen.set_color_spans(vec![(0..10, blue), (2..4, red)]);
+        assert_eq!(
+            screen.color_spans,
+            [(0..2, blue), (2..4, red), (4..10, blue)]
+        );
+        assert_eq!(screen.span_color(1), Some(blue));
+        assert_eq!(screen.span_color(3), Some(red));
+        assert_eq!(screen.span_color(4), Some(blue));
+        assert_eq!(screen.span_color(10), None);
+
+        // an earlier span hidden under a later one is gone, gaps stay uncolored
+        screen.set_color_spans(vec![(2..4, red), (0..6, blue), (8..9, red), (4..5, blue)]);
+        assert_eq!(screen.color_spans, [(0..6, blue), (8..9, red)]);
+        assert_eq!(screen.span_color(7), None);
+        assert_eq!(screen.span_color(8), Some(red));
+    }
+
+    #[test]
+    fn word_wrap_moves_the_overflowing_word_to_the_next_row() {
+        let (mut screen, font) = screen("hello worldwide", 100);
+        screen.set_wrap_mode(WrapMode::Word);
+        let layout = screen.layout(0, &font);
+        assert_eq!(layout[5], (40, 0));
+        // "worldwide" starts the second row instead of being split up
+        assert_eq!(layout[6], (0, 1));
+        assert_eq!(layout[14], (64, 1));
+    }
+
+    #[test]
+    fn continuation_rows_start_at_the_indentation() {
+        let (mut screen, font) = screen("    abcdefghijkl\nnext", 100);
+        // twelve 8px cells fit, the thirteenth character starts the continuation row
+        assert_eq!(screen.layout(0, &font)[12], (0, 1));
+
+        screen.set_wrap_indent(true, 0);
+        let layout = screen.layout(0, &font);
+        assert_eq!(layout[4], (32, 0));
+        assert_eq!(layout[12], (32, 1));
+        assert_eq!(layout[13], (40, 1));
+        // the next line is not affected
+        assert_eq!(layout[17], (0, 2));
+
+        screen.set_wrap_indent(true, 2);
+        let layout = screen.layout(0, &font);
+        assert_eq!(layout[12], (48, 1));
+        // at most half the width is used for indentation
+        screen.set_wrap_indent(true, 20);
+        assert_eq!(screen.layout(0, &font)[12], (50, 1));
+    }
+
+    #[test]
+    fn current_line_follows_the_cursor_onto_a_wrapped_row() {
+        let (mut screen, font) = screen("hello worldwide", 100);
+        screen.set_wrap_mode(WrapMode::Word);
+        screen.move_cursor_to(10);
+        let layout = screen.layout(0, &font);
+        assert_eq!(
+            screen.current_line_rect(&layout, 5, 20),
+            Some(Rect::new(5, 30, 100, 10))
+        );
+        screen.move_cursor_to(2);
+        assert_eq!(
+            screen.current_line_rect(&layout, 5, 20),
+            Some(Rect::new(5, 20, 100, 10))
+        );
+    }
+
+    fn assert_rows_in_order(lines: &Lines) {
+        for (idx, line) in lines.lines.iter().enumerate() {
+            assert_eq!(line.row, idx);
+        }
+    }
+
+    #[test]
+    fn splitting_and_merging_lines_keeps_text_and_rows() {
+        let mut font = monospace_font(8);
+        let mut lines: Lines = font.get_string_lossy("one\ntwo\nthree").into();
+        let text = |lines: &Lines| lines.iter().map(|fch| fch.ch).collect::<String>();
+        let check = |lines: &Lines, expected: &str| {
+            assert_eq!(text(lines), expected);
+            assert_eq!(lines.len(), expected.chars().count());
+            assert_eq!(lines.line_count(), expected.matches('\n').count() + 1);
+            assert_rows_in_order(lines);
+            for line in &lines.lines {
+                let width: u32 = line.iter().map(|fch| fch.bbox.width()).sum();
+                assert_eq!(line.width, width);
+            }
+        };
+        check(&lines, "one\ntwo\nthree");
+
+        // split the first line, the rows below move down
+        lines.insert(1, font.get_char('\n' as usize).unwrap());
+        check(&lines, "o\nne\ntwo\nthree");
+        lines.insert_many(0, font.get_string_lossy("\n\n"));
+        check(&lines, "\n\no\nne\ntwo\nthree");
+
+        // merge lines again, the rows below move up
+        lines.remove(3);
+        check(&lines, "\n\none\ntwo\nthree");
+        lines.remove_range(0..2);
+        check(&lines, "one\ntwo\nthree");
+        lines.remove_range(2..9);
+        check(&lines, "onhree");
+
+        // several lines pasted mid-line carry the rest of it along
+        lines.insert_many(2, font.get_string_lossy("e\ntwo\nt"));
+        check(&lines, "one\ntwo\nthree");
+        lines.insert_many(4, font.get_string_lossy("and "));
+        check(&lines, "one\nand two\nthree");
+        // within a line, and up to the start of the next one
+        lines.remove_range(4..8);
+        check(&lines, "one\ntwo\nthree");
+        assert_eq!(
+            lines
+                .remove_range(4..8)
+                .iter()
+                .map(|fch| fch.ch)
+                .collect::<String>(),
+            "two\n"
+        );
+        check(&lines, "one\nthree");
+        lines.remove_range(0..0);
+        check(&lines, "one\nthree");
+
+        // ranges run backwards and can be replayed, across lines or within one
+        let across = lines.range(1..6);
+        assert_eq!(
+            across.clone().rev().map(|fch| fch.ch).collect::<String>(),
+            "ht\nen"
+        );
+        assert_eq!(across.map(|fch| fch.ch).collect::<String>(), "ne\nth");
+        let within = lines.range(5..8);
+        assert_eq!(
+            within.clone().rev().map(|fch| fch.ch).collect::<String>(),
+            "erh"
+        );
+        assert_eq!(within.count(), 3);
     }
 }
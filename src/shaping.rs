@@ -0,0 +1,126 @@
+use rustybuzz::{shape as hb_shape, Face, UnicodeBuffer};
+
+/// A glyph rustybuzz picked for a run of text, positions in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShapedGlyph {
+    /// Glyph index in the font, 0 if the font has no glyph for the cluster
+    pub glyph_id: u32,
+    /// Byte offset of the first character of the cluster the glyph belongs to
+    pub cluster: usize,
+    pub x_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+/// Shape `text` with the font in `font_data`, at `pixel_size` pixels per em.
+/// Glyphs come in visual order, with the default features of the font, ligatures included.
+/// Returns `None` if `font_data` is not a font rustybuzz can read.
+pub fn shape(font_data: &[u8], pixel_size: u32, text: &str) -> Option<Vec<ShapedGlyph>> {
+    let face = Face::from_slice(font_data, 0)?;
+    // positions come in font units
+    let scale = pixel_size as f32 / face.units_per_em().max(1) as f32;
+    let to_pixels = |units: i32| (units as f32 * scale).round() as i32;
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(text);
+    let output = hb_shape(&face, &[], buffer);
+    Some(
+        output
+            .glyph_infos()
+            .iter()
+            .zip(output.glyph_positions())
+            .map(|(info, position)| ShapedGlyph {
+                glyph_id: info.glyph_id,
+                cluster: info.cluster as usize,
+                x_advance: to_pixels(position.x_advance),
+                x_offset: to_pixels(position.x_offset),
+                y_offset: to_pixels(position.y_offset),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const BUNDLED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
+    fn be16(values: &[u16]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    /// `font` with its GSUB table replaced by one whose only feature is a `liga` turning
+    /// `!=` into `≠`, none of the fonts at hand has such a ligature of its own
+    pub(crate) fn with_not_equal_ligature(font: &[u8]) -> Vec<u8> {
+        let face = Face::from_slice(font, 0).unwrap();
+        let glyph = |ch: char| face.glyph_index(ch).unwrap().0;
+        let (exclam, equal, not_equal) = (glyph('!'), glyph('='), glyph('≠'));
+        let mut gsub = be16(&[1, 0, 10, 30, 44]);
+        // script list: the default script with one language system using feature 0
+        gsub.extend(be16(&[1]));
+        gsub.extend(b"DFLT");
+        gsub.extend(be16(&[8, 4, 0, 0, 0xFFFF, 1, 0]));
+        // feature list: liga using lookup 0
+        gsub.extend(be16(&[1]));
+        gsub.extend(b"liga");
+        gsub.extend(be16(&[8, 0, 1, 0]));
+        // lookup list: a ligature substitution of exclam equal by notequal
+        gsub.extend(be16(&[1, 4, 4, 0, 1, 8]));
+        gsub.extend(be16(&[
+            1, 8, 1, 14, 1, 1, exclam, 1, 4, not_equal, 2, equal,
+        ]));
+
+        // rebuild the table directory, its records sorted by tag like the format asks
+        let table_count = u16::from_be_bytes([font[4], font[5]]) as usize;
+        let mut tables: Vec<([u8; 4], Vec<u8>)> = (0..table_count)
+            .map(|idx| {
+                let record = &font[12 + idx * 16..28 + idx * 16];
+                let field =
+                    |at: usize| u32::from_be_bytes(record[at..at + 4].try_into().unwrap()) as usize;
+                let tag: [u8; 4] = record[..4].try_into().unwrap();
+                (tag, font[field(8)..field(8) + field(12)].to_vec())
+            })
+            .filter(|(tag, _)| tag != b"GSUB")
+            .collect();
+        tables.push((*b"GSUB", gsub));
+        tables.sort_by_key(|(tag, _)| *tag);
+        let mut out = font[..12].to_vec();
+        out[4..6].copy_from_slice(&(tables.len() as u16).to_be_bytes());
+        let mut offset = 12 + tables.len() * 16;
+        for (tag, data) in &tables {
+            out.extend(tag);
+            out.extend([0; 4]);
+            out.extend((offset as u32).to_be_bytes());
+            out.extend((data.len() as u32).to_be_bytes());
+            offset += data.len().next_multiple_of(4);
+        }
+        for (_, data) in &tables {
+            out.extend(data);
+            out.resize(out.len().next_multiple_of(4), 0);
+        }
+        out
+    }
+
+    #[test]
+    fn ligature_shapes_into_one_glyph() {
+        let font = with_not_equal_ligature(BUNDLED_FONT);
+        let face = Face::from_slice(&font, 0).unwrap();
+        let not_equal = face.glyph_index('≠').unwrap().0 as u32;
+
+        let shaped = shape(&font, 16, "!=").unwrap();
+        assert_eq!(shaped.len(), 1);
+        assert_eq!(shaped[0].glyph_id, not_equal);
+        assert_eq!(shaped[0].cluster, 0);
+
+        let shaped = shape(&font, 16, "a != b").unwrap();
+        assert_eq!(shaped.len(), 5);
+        assert_eq!(shaped[2].glyph_id, not_equal);
+        assert_eq!(shaped[3].cluster, "a !=".len());
+        // without the ligature every character keeps a glyph of its own
+        assert_eq!(shape(BUNDLED_FONT, 16, "!=").unwrap().len(), 2);
+        assert!(shape(b"not a font", 16, "!=").is_none());
+    }
+}
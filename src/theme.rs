@@ -0,0 +1,45 @@
+use sdl2::pixels::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub background: Color,
+    pub foreground: Color,
+    pub cursor: Color,
+    pub selection: Color,
+    /// Added on top of the row holding the cursor
+    pub current_line: Color,
+    pub scrollbar_track: Color,
+    pub scrollbar_thumb: Color,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme {
+            background: Color::RGB(0, 0, 0),
+            foreground: Color::RGB(255, 255, 255),
+            cursor: Color::RGB(255, 255, 255),
+            selection: Color::RGB(50, 50, 50),
+            current_line: Color::RGB(20, 20, 20),
+            scrollbar_track: Color::RGB(15, 15, 15),
+            scrollbar_thumb: Color::RGB(90, 90, 90),
+        }
+    }
+
+    pub fn light() -> Self {
+        Theme {
+            background: Color::RGB(250, 250, 250),
+            foreground: Color::RGB(20, 20, 20),
+            cursor: Color::RGB(20, 20, 20),
+            selection: Color::RGB(60, 90, 140),
+            current_line: Color::RGB(5, 5, 5),
+            scrollbar_track: Color::RGB(235, 235, 235),
+            scrollbar_thumb: Color::RGB(160, 160, 160),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}